@@ -1,7 +1,11 @@
+use crate::embedder::Embedder;
+use crate::utils::config::AzureEmbeddingConfig;
+use crate::utils::constants::{AZURE_EMBEDDING_DIMENSION, EMBEDDING_BATCH_SIZE};
 use crate::utils::vars;
 use anyhow::Result;
 use async_openai::types::CreateEmbeddingRequestArgs;
 use async_openai::Client;
+use futures::future::BoxFuture;
 use langchain_rust::llm::AzureConfig;
 
 pub struct AzureEmbedding<'a> {
@@ -9,6 +13,7 @@ pub struct AzureEmbedding<'a> {
     api_version: &'a str,
     deployment_id: &'a str,
     embed_model: &'a str,
+    dimensions: Option<u32>,
 }
 
 impl<'a> AzureEmbedding<'a> {
@@ -18,6 +23,7 @@ impl<'a> AzureEmbedding<'a> {
             api_version: "",
             deployment_id: "",
             embed_model: "",
+            dimensions: None,
         }
     }
     pub fn with_resource_name(mut self, resource_name: &'a str) -> Self {
@@ -36,22 +42,31 @@ impl<'a> AzureEmbedding<'a> {
         self.embed_model = embed_model;
         self
     }
+    pub fn with_dimensions(mut self, dimensions: Option<u32>) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
 
-    pub async fn generate_embeddings(&self, text: &str) -> Result<Vec<f32>> {
-        let client = Client::with_config(
+    fn client(&self) -> Client<AzureConfig> {
+        Client::with_config(
             AzureConfig::new()
                 .with_api_base(format!("https://{}.openai.azure.com/", self.resource_name))
                 .with_api_version(self.api_version)
                 .with_deployment_id(self.deployment_id)
                 .with_api_key(&vars::get_azureai_api_key()),
-        );
+        )
+    }
 
-        let request = CreateEmbeddingRequestArgs::default()
-            .model(self.embed_model)
-            .input(text)
-            .build()?;
+    pub async fn generate_embeddings(&self, text: &str) -> Result<Vec<f32>> {
+        let mut request = CreateEmbeddingRequestArgs::default();
 
-        let response = client.embeddings().create(request).await?;
+        request.model(self.embed_model).input(text);
+
+        if let Some(dimensions) = self.dimensions {
+            request.dimensions(dimensions);
+        }
+
+        let response = self.client().embeddings().create(request.build()?).await?;
 
         let embeddings = response.data;
 
@@ -61,4 +76,78 @@ impl<'a> AzureEmbedding<'a> {
             anyhow::bail!("Embedding generation failed.")
         }
     }
+
+    pub async fn generate_batch_embeddings(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut request = CreateEmbeddingRequestArgs::default();
+
+        request.model(self.embed_model).input(texts.to_vec());
+
+        if let Some(dimensions) = self.dimensions {
+            request.dimensions(dimensions);
+        }
+
+        let response = self.client().embeddings().create(request.build()?).await?;
+
+        let mut embeddings = response.data;
+
+        if embeddings.len() != texts.len() {
+            anyhow::bail!(
+                "Embedding generation failed, expected {} embeddings got {}",
+                texts.len(),
+                embeddings.len()
+            )
+        }
+
+        embeddings.sort_by_key(|embedding| embedding.index);
+
+        Ok(embeddings
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+}
+
+/// Azure OpenAI embedding deployment from `azure_embedding_config`.
+pub struct AzureEmbedder {
+    embedding: AzureEmbedding<'static>,
+    dimension: usize,
+}
+
+impl AzureEmbedder {
+    pub fn new(config: &'static AzureEmbeddingConfig<'static>) -> Self {
+        Self {
+            embedding: AzureEmbedding::default()
+                .with_resource_name(&config.resource_name)
+                .with_api_version(&config.api_version)
+                .with_deployment_id(&config.deployment_id)
+                .with_embedding_model(&config.embedding_model)
+                .with_dimensions(config.dimension),
+            dimension: config
+                .dimension
+                .map(|dimension| dimension as usize)
+                .unwrap_or(AZURE_EMBEDDING_DIMENSION),
+        }
+    }
+}
+
+impl Embedder for AzureEmbedder {
+    fn embed_batch<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>> {
+        Box::pin(async move {
+            let mut embeddings = Vec::with_capacity(texts.len());
+
+            for batch in texts.chunks(EMBEDDING_BATCH_SIZE) {
+                embeddings.extend(self.embedding.generate_batch_embeddings(batch).await?);
+            }
+
+            Ok(embeddings)
+        })
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn model_id(&self) -> String {
+        format!("azure:{}", self.embedding.embed_model)
+    }
 }
@@ -1,3 +1,4 @@
+use crate::protocol::{EventSender, ServerEvent};
 use crate::utils::vars;
 use anyhow::Result;
 use async_openai::{
@@ -5,8 +6,7 @@ use async_openai::{
     types::{ChatCompletionRequestMessage, CreateChatCompletionRequestArgs},
     Client,
 };
-use futures::SinkExt;
-use futures::{stream::SplitSink, StreamExt};
+use futures::StreamExt;
 
 pub struct AzureInferencing<'a> {
     resource_name: &'a str,
@@ -102,7 +102,7 @@ impl<'a> AzureInferencing<'a> {
     pub async fn stream(
         &self,
         messages: &Vec<ChatCompletionRequestMessage>,
-        websocket: &mut SplitSink<warp::ws::WebSocket, warp::filters::ws::Message>,
+        events: &EventSender,
     ) -> Result<String> {
         let client = Client::with_config(
             AzureConfig::new()
@@ -132,9 +132,9 @@ impl<'a> AzureInferencing<'a> {
                     if let Some(content) = response.choices.first() {
                         if let Some(ref content) = content.delta.content {
                             output.push_str(content);
-                            websocket
-                                .send(warp::ws::Message::text(output.clone()))
-                                .await?;
+                            events.send(ServerEvent::Token {
+                                content: content.to_owned(),
+                            })?;
                         }
                     }
                 }
@@ -1,79 +1,176 @@
+use crate::chat_backend::{ChatBackend, ChatMessage, TokenStream};
+use crate::generation::GenerationParams;
+use crate::local::inferencing::MessageType;
+use crate::utils::constants::DEFAULT_REMOTE_CONTEXT_WINDOW;
 use crate::utils::vars;
 use anyhow::Result;
-use async_openai::types::ChatCompletionRequestMessage;
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionRequestUserMessageContent, ChatCompletionResponseStream,
+    CreateChatCompletionRequest, Stop,
+};
 use async_openai::{config::AzureConfig, types::CreateChatCompletionRequestArgs, Client};
-use futures::stream::SplitSink;
-use futures::SinkExt;
+use futures::stream;
 use futures::StreamExt;
+use futures::TryStreamExt;
+use std::borrow::Cow;
 
 pub struct AzureAI<'a> {
-    resource_name: &'a str,
-    api_version: &'a str,
-    deployment_id: &'a str,
-    inf_model: &'a str,
+    resource_name: Cow<'a, str>,
+    api_version: Cow<'a, str>,
+    deployment_id: Cow<'a, str>,
+    inf_model: Cow<'a, str>,
     max_token: u16,
+    context_window: usize,
 }
 
 impl<'a> AzureAI<'a> {
     pub fn new(
-        resource_name: &'a str,
-        api_version: &'a str,
-        deployment_id: &'a str,
-        inf_model: &'a str,
+        resource_name: impl Into<Cow<'a, str>>,
+        api_version: impl Into<Cow<'a, str>>,
+        deployment_id: impl Into<Cow<'a, str>>,
+        inf_model: impl Into<Cow<'a, str>>,
         max_token: u16,
     ) -> Self {
         Self {
-            resource_name,
-            api_version,
-            deployment_id,
-            inf_model,
+            resource_name: resource_name.into(),
+            api_version: api_version.into(),
+            deployment_id: deployment_id.into(),
+            inf_model: inf_model.into(),
             max_token,
+            context_window: DEFAULT_REMOTE_CONTEXT_WINDOW,
         }
     }
-    pub async fn run_azureai(
-        &self,
-        websocket: &mut SplitSink<warp::ws::WebSocket, warp::filters::ws::Message>,
-        messages: &Vec<ChatCompletionRequestMessage>,
-    ) -> Result<String> {
 
+    pub fn with_context_window(mut self, context_window: usize) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
+    async fn create_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+    ) -> Result<TokenStream<'static>> {
         let auzre_ai_url = format!("https://{}.openai.azure.com", self.resource_name);
 
         let config = AzureConfig::new()
             .with_api_base(auzre_ai_url)
             .with_api_key(vars::get_azureai_api_key())
-            .with_deployment_id(self.deployment_id)
-            .with_api_version(self.api_version);
+            .with_deployment_id(self.deployment_id.as_ref())
+            .with_api_version(self.api_version.as_ref());
 
         let client = Client::with_config(config);
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(self.inf_model)
-            .max_tokens(self.max_token)
-            .messages(messages.clone())
-            .build()?;
+        let request = completion_request(&self.inf_model, self.max_token, messages, params)?;
 
-        let mut stream = client.chat().create_stream(request).await?;
+        let stream = client.chat().create_stream(request).await?;
 
-        let mut output: String = String::new();
+        Ok(completion_tokens(stream))
+    }
+}
+
+impl ChatBackend for AzureAI<'_> {
+    fn stream_chat<'b>(
+        &'b self,
+        messages: &'b [ChatMessage],
+        params: &'b GenerationParams,
+    ) -> TokenStream<'b> {
+        stream::once(self.create_stream(messages, params))
+            .try_flatten()
+            .boxed()
+    }
+
+    fn context_window(&self) -> usize {
+        self.context_window
+    }
+
+    fn max_new_tokens(&self, params: &GenerationParams) -> usize {
+        params.max_tokens.unwrap_or(usize::from(self.max_token))
+    }
+}
+
+/// Streaming chat completion request, `max_token` applies unless `params` sets `max_tokens`.
+/// `top_k` and `repeat_penalty` have no OpenAI equivalent and are ignored.
+pub fn completion_request(
+    model: &str,
+    max_token: u16,
+    messages: &[ChatMessage],
+    params: &GenerationParams,
+) -> Result<CreateChatCompletionRequest> {
+    let max_token = params
+        .max_tokens
+        .map(|max_tokens| u16::try_from(max_tokens).unwrap_or(u16::MAX))
+        .unwrap_or(max_token);
 
-        while let Some(res) = stream.next().await {
+    let mut request = CreateChatCompletionRequestArgs::default();
+
+    request
+        .model(model)
+        .max_tokens(max_token)
+        .messages(to_openai_messages(messages)?);
+
+    if let Some(temperature) = params.temperature {
+        request.temperature(temperature as f32);
+    }
+    if let Some(top_p) = params.top_p {
+        request.top_p(top_p as f32);
+    }
+    // Validated to fit.
+    if let Some(seed) = params.seed {
+        request.seed(seed as i64);
+    }
+    if !params.stop.is_empty() {
+        request.stop(Stop::StringArray(params.stop.clone()));
+    }
+
+    Ok(request.build()?)
+}
+
+/// Content deltas of an OpenAI chat completion stream.
+pub fn completion_tokens(stream: ChatCompletionResponseStream) -> TokenStream<'static> {
+    stream
+        .filter_map(|res| async move {
             match res {
-                Ok(response) => {
-                    if let Some(model_resp) = response.choices.first() {
-                        if let Some(ref content) = model_resp.delta.content {
-                            websocket.send(warp::ws::Message::text(content.clone()))
-                            .await?;
-                            output += content;
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("{:?}", e);
-                    break;
-                }
+                Ok(response) => response
+                    .choices
+                    .first()
+                    .and_then(|model_resp| model_resp.delta.content.clone())
+                    .map(Ok),
+                Err(e) => Some(Err(e.into())),
             }
-        }
+        })
+        .boxed()
+}
 
-        Ok(output)
+pub fn to_openai_messages(messages: &[ChatMessage]) -> Result<Vec<ChatCompletionRequestMessage>> {
+    let mut openai_messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+
+    for message in messages {
+        match message.role {
+            MessageType::System => openai_messages.push(
+                ChatCompletionRequestSystemMessageArgs::default()
+                    .content(message.content.as_str())
+                    .build()?
+                    .into(),
+            ),
+            MessageType::User => openai_messages.push(
+                ChatCompletionRequestUserMessageArgs::default()
+                    .content(ChatCompletionRequestUserMessageContent::Text(
+                        message.content.to_owned(),
+                    ))
+                    .build()?
+                    .into(),
+            ),
+            MessageType::Assistant => openai_messages.push(
+                ChatCompletionRequestAssistantMessageArgs::default()
+                    .content(message.content.as_str())
+                    .build()?
+                    .into(),
+            ),
+        }
     }
+
+    Ok(openai_messages)
 }
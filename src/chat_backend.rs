@@ -0,0 +1,160 @@
+use crate::azureai::AzureAI;
+use crate::generation::GenerationParams;
+use crate::local::inferencing::{LocalChatBackend, MessageType};
+use crate::openai_compatible::OpenAICompatible;
+use crate::prompt;
+use crate::utils::config::{AppConfig, DeploymentBackend};
+use crate::utils::constants::{DEFAULT_LOCAL_MODEL, DEFAULT_REMOTE_CONTEXT_WINDOW};
+use crate::utils::vars;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::FutureExt;
+use std::borrow::Cow;
+
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub role: MessageType,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: MessageType, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// Generated text pieces in the order the model produced them.
+pub type TokenStream<'a> = BoxStream<'a, Result<String>>;
+
+/// A chat model that streams its answer token by token.
+pub trait ChatBackend: Send + Sync {
+    fn stream_chat<'a>(
+        &'a self,
+        messages: &'a [ChatMessage],
+        params: &'a GenerationParams,
+    ) -> TokenStream<'a>;
+
+    /// Tokens the model attends to, prompt and answer together.
+    fn context_window(&self) -> usize;
+
+    /// Tokens reserved for the answer to a request with `params`.
+    fn max_new_tokens(&self, params: &GenerationParams) -> usize;
+
+    /// Tokens `text` takes in a prompt, estimated unless the backend has its tokenizer.
+    fn count_tokens(&self, text: &str) -> usize {
+        prompt::estimate_tokens(text)
+    }
+
+    /// Load what `count_tokens` needs, called before a prompt is packed.
+    fn prepare(&self) -> BoxFuture<'_, Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Resolve `deployment_type` to one of the configured deployments, falling back to
+/// `app_config.inferencing_model` when it's empty. `deployment_model` overrides the
+/// model name for remote deployments.
+pub fn get_chat_backend<'a>(
+    deployment_type: &str,
+    deployment_model: &'a str,
+) -> Result<Box<dyn ChatBackend + 'a>> {
+    let app_config = AppConfig::global();
+
+    let deployment_type = if deployment_type.trim().is_empty() {
+        app_config.app_config.inferencing_model.as_ref()
+    } else {
+        deployment_type.trim()
+    };
+
+    let deployment = app_config.deployment(deployment_type).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Unknown deployment type: {deployment_type}, expected one of {:?}",
+            app_config.deployment_names()
+        )
+    })?;
+
+    match deployment.backend {
+        DeploymentBackend::Local { model } => {
+            let model_name = if deployment_model.trim().is_empty() {
+                model.unwrap_or(Cow::Borrowed(DEFAULT_LOCAL_MODEL))
+            } else {
+                deployment_model.trim().into()
+            };
+
+            let local_model = app_config.local_model(&model_name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown local model: {model_name}, expected one of {:?}",
+                    app_config.local_model_names()
+                )
+            })?;
+
+            let mut backend =
+                LocalChatBackend::new(local_model, deployment.max_tokens.map(usize::from));
+
+            if let Some(context_window) = deployment.context_window {
+                backend = backend.with_context_window(context_window);
+            }
+
+            Ok(Box::new(backend))
+        }
+        DeploymentBackend::Azure {
+            resource_name,
+            api_version,
+            deployment_id,
+            inferencing_model,
+        } => {
+            let inf_model = if deployment_model.trim().is_empty() {
+                inferencing_model
+            } else {
+                deployment_model.trim().into()
+            };
+
+            Ok(Box::new(
+                AzureAI::new(
+                    resource_name,
+                    api_version,
+                    deployment_id,
+                    inf_model,
+                    deployment.max_tokens.unwrap_or(2048),
+                )
+                .with_context_window(
+                    deployment
+                        .context_window
+                        .unwrap_or(DEFAULT_REMOTE_CONTEXT_WINDOW),
+                ),
+            ))
+        }
+        DeploymentBackend::OpenAI {
+            base_url,
+            api_key_env,
+            inferencing_model,
+        } => {
+            let inf_model = if deployment_model.trim().is_empty() {
+                inferencing_model
+            } else {
+                deployment_model.trim().into()
+            };
+
+            let api_key = api_key_env.and_then(|api_key_env| vars::get_env_override(&api_key_env));
+
+            Ok(Box::new(
+                OpenAICompatible::new(
+                    base_url,
+                    api_key,
+                    inf_model,
+                    deployment.max_tokens.unwrap_or(2048),
+                )
+                .with_context_window(
+                    deployment
+                        .context_window
+                        .unwrap_or(DEFAULT_REMOTE_CONTEXT_WINDOW),
+                ),
+            ))
+        }
+    }
+}
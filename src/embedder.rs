@@ -0,0 +1,65 @@
+use crate::azure::embeddings::AzureEmbedder;
+use crate::local::embedding::LocalEmbedder;
+use crate::utils::config::AppConfig;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+
+/// Common interface over the embedding backends so ingestion and search
+/// don't need to know which model produces the vectors.
+pub trait Embedder: Send + Sync {
+    /// Embed every text, returning one vector of `dimension()` values per input.
+    fn embed_batch<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>>;
+
+    fn dimension(&self) -> usize;
+
+    /// Stable identifier of the backend and model, e.g. `local:BAAI_V1.5L`.
+    fn model_id(&self) -> String;
+}
+
+impl dyn Embedder {
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_batch(&[text.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Embedding generation failed."))
+    }
+}
+
+/// Embedder for the `embedding_model` name used in `AppConfig` (`local` or `azure`).
+pub fn get_embedder(embedding_model: &str) -> Result<Box<dyn Embedder>> {
+    let app_config = AppConfig::global();
+
+    match embedding_model {
+        "local" => Ok(Box::new(LocalEmbedder::new(
+            &app_config.local_embedding_config,
+        ))),
+        "azure" => Ok(Box::new(AzureEmbedder::new(
+            &app_config.azure_embedding_config,
+        ))),
+        _ => anyhow::bail!("Unsupported embedding model: {embedding_model}"),
+    }
+}
+
+/// Embedder configured in `app_config.embedding_model`.
+pub fn default_embedder() -> Result<Box<dyn Embedder>> {
+    get_embedder(&AppConfig::global().app_config.embedding_model)
+}
+
+/// Embedder that produces vectors for `model_id`, as recorded for a knowledge base.
+pub fn get_embedder_by_model_id(model_id: &str) -> Result<Box<dyn Embedder>> {
+    let (embedding_model, _) = model_id
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Invalid embedding model id: {model_id}"))?;
+
+    let embedder = get_embedder(embedding_model)?;
+
+    if embedder.model_id() != model_id {
+        anyhow::bail!(
+            "Embedding model {model_id} isn't configured, configured model is {}",
+            embedder.model_id()
+        );
+    }
+
+    Ok(embedder)
+}
@@ -0,0 +1,133 @@
+use crate::utils::constants::{MAX_GENERATION_TOKENS, MAX_STOP_SEQUENCES};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Per request generation settings, `None` falls back to the deployment defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct GenerationParams {
+    pub max_tokens: Option<usize>,
+    /// 0 always picks the most likely token.
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// Local models only.
+    pub top_k: Option<usize>,
+    /// At most `i64::MAX`, the largest seed the OpenAI API takes.
+    pub seed: Option<u64>,
+    /// Local models only.
+    pub repeat_penalty: Option<f32>,
+    /// Generation ends before the first of these, which isn't part of the answer.
+    #[serde(default)]
+    pub stop: Vec<String>,
+}
+
+impl GenerationParams {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(max_tokens) = self.max_tokens {
+            if max_tokens == 0 || max_tokens > MAX_GENERATION_TOKENS {
+                anyhow::bail!("max_tokens must be between 1 and {MAX_GENERATION_TOKENS}");
+            }
+        }
+
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                anyhow::bail!("temperature must be between 0 and 2");
+            }
+        }
+
+        if let Some(top_p) = self.top_p {
+            if !(top_p > 0.0 && top_p <= 1.0) {
+                anyhow::bail!("top_p must be greater than 0 and at most 1");
+            }
+        }
+
+        if self.top_k == Some(0) {
+            anyhow::bail!("top_k must be greater than 0");
+        }
+
+        if self.seed.is_some_and(|seed| i64::try_from(seed).is_err()) {
+            anyhow::bail!("seed must be at most {}", i64::MAX);
+        }
+
+        if let Some(repeat_penalty) = self.repeat_penalty {
+            if !(repeat_penalty > 0.0 && repeat_penalty <= 2.0) {
+                anyhow::bail!("repeat_penalty must be greater than 0 and at most 2");
+            }
+        }
+
+        if self.stop.len() > MAX_STOP_SEQUENCES {
+            anyhow::bail!("stop can't have more than {MAX_STOP_SEQUENCES} sequences");
+        }
+
+        if self.stop.iter().any(|stop| stop.is_empty()) {
+            anyhow::bail!("stop sequences can't be empty");
+        }
+
+        Ok(())
+    }
+}
+
+/// Cuts a token stream at the first stop sequence.
+///
+/// Text that could be the start of a stop sequence is held back until the
+/// next tokens show whether it is one.
+#[derive(Debug, Clone, Default)]
+pub struct StopSequences {
+    stop: Vec<String>,
+    pending: String,
+    stopped: bool,
+}
+
+impl StopSequences {
+    pub fn new(stop: &[String]) -> Self {
+        Self {
+            stop: stop.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Text that can be sent after `token`, possibly empty.
+    pub fn push(&mut self, token: &str) -> String {
+        if self.stopped {
+            return String::new();
+        }
+
+        self.pending.push_str(token);
+
+        if let Some(index) = self
+            .stop
+            .iter()
+            .filter_map(|stop| self.pending.find(stop.as_str()))
+            .min()
+        {
+            self.stopped = true;
+            self.pending.truncate(index);
+
+            return std::mem::take(&mut self.pending);
+        }
+
+        // Start of the longest tail that is a prefix of some stop sequence.
+        let held = self
+            .pending
+            .char_indices()
+            .map(|(index, _)| index)
+            .find(|&index| {
+                let tail = &self.pending[index..];
+                self.stop.iter().any(|stop| stop.starts_with(tail))
+            })
+            .unwrap_or(self.pending.len());
+
+        let held = self.pending.split_off(held);
+
+        std::mem::replace(&mut self.pending, held)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Held back text, once generation ended without reaching a stop sequence.
+    pub fn finish(self) -> String {
+        self.pending
+    }
+}
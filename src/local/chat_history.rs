@@ -0,0 +1,126 @@
+use crate::chat_backend::ChatMessage;
+use crate::local::database::get_pool;
+use crate::local::inferencing::MessageType;
+use crate::utils::constants::*;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+/// A conversation, stored in `chat_sessions`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ChatSession {
+    pub id: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub message_count: i64,
+}
+
+/// One turn of a conversation, stored in `chat_messages`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct StoredMessage {
+    pub id: i64,
+    pub role: String,
+    pub content: String,
+    pub created_at: NaiveDateTime,
+}
+
+pub fn validate_session_id(session_id: &str) -> Result<()> {
+    if session_id.trim().is_empty() {
+        anyhow::bail!("Session id can't be empty");
+    }
+
+    if session_id.len() > MAX_SESSION_ID_LEN {
+        anyhow::bail!("Session id can't be longer than {MAX_SESSION_ID_LEN} characters");
+    }
+
+    Ok(())
+}
+
+/// Append messages in one transaction, creating the session on its first
+/// message. Answers to concurrent questions of the session don't interleave.
+pub async fn add_messages(session_id: &str, messages: &[(MessageType, &str)]) -> Result<()> {
+    validate_session_id(session_id)?;
+
+    let pool = get_pool().await?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(&upsert_chat_session_sql())
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for &(role, content) in messages {
+        sqlx::query(&insert_chat_message_sql())
+            .bind(session_id)
+            .bind(role.to_string())
+            .bind(content)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// The last `MAX_HISTORY_MESSAGES` messages of a session, oldest first.
+pub async fn recent_messages(session_id: &str) -> Result<Vec<ChatMessage>> {
+    validate_session_id(session_id)?;
+
+    let pool = get_pool().await?;
+
+    let messages = sqlx::query_as::<_, StoredMessage>(&get_recent_chat_messages_sql())
+        .bind(session_id)
+        .fetch_all(pool)
+        .await?;
+
+    messages
+        .into_iter()
+        .map(|message| {
+            Ok(ChatMessage::new(
+                MessageType::parse(&message.role)?,
+                message.content,
+            ))
+        })
+        .collect()
+}
+
+pub async fn get_session(session_id: &str) -> Result<Option<ChatSession>> {
+    let pool = get_pool().await?;
+
+    Ok(sqlx::query_as::<_, ChatSession>(&get_chat_session_sql())
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await?)
+}
+
+pub async fn get_messages(session_id: &str) -> Result<Vec<StoredMessage>> {
+    let pool = get_pool().await?;
+
+    Ok(sqlx::query_as::<_, StoredMessage>(&get_chat_messages_sql())
+        .bind(session_id)
+        .fetch_all(pool)
+        .await?)
+}
+
+/// Most recently active sessions first.
+pub async fn list_sessions() -> Result<Vec<ChatSession>> {
+    let pool = get_pool().await?;
+
+    Ok(sqlx::query_as::<_, ChatSession>(&list_chat_sessions_sql())
+        .fetch_all(pool)
+        .await?)
+}
+
+/// Returns `false` when the session didn't exist.
+pub async fn delete_session(session_id: &str) -> Result<bool> {
+    let pool = get_pool().await?;
+
+    let result = sqlx::query(&delete_chat_session_sql())
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,254 @@
+use crate::chat_backend::ChatMessage;
+use crate::local::inferencing::MessageType;
+use crate::utils::local_models::ChatTemplate;
+
+use anyhow::Result;
+use minijinja::{context, Environment, ErrorKind};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+
+const JINJA_TEMPLATE_NAME: &'static str = "chat_template";
+
+/// Template a loaded local model renders its prompts with.
+pub enum PromptTemplate {
+    BuiltIn(ChatTemplate),
+    /// `chat_template` shipped in the model's `tokenizer_config.json`.
+    Jinja(Box<JinjaTemplate>),
+}
+
+impl PromptTemplate {
+    pub fn render(&self, messages: &[ChatMessage]) -> Result<String> {
+        match self {
+            Self::BuiltIn(template) => Ok(render(*template, messages)),
+            Self::Jinja(template) => template.render(messages),
+        }
+    }
+}
+
+/// The fields of a Hugging Face `tokenizer_config.json` used for prompting.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenizerConfig {
+    pub chat_template: Option<String>,
+    pub bos_token: Option<String>,
+    pub eos_token: Option<String>,
+}
+
+impl TokenizerConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: Value = serde_json::from_str(json)?;
+
+        // Either the template itself or a list of named templates.
+        let chat_template = match config.get("chat_template") {
+            Some(Value::String(template)) => Some(template.to_owned()),
+            Some(Value::Array(templates)) => templates
+                .iter()
+                .find(|template| template["name"] == "default")
+                .or_else(|| templates.first())
+                .and_then(|template| template["template"].as_str())
+                .map(str::to_owned),
+            _ => None,
+        };
+
+        Ok(Self {
+            chat_template,
+            bos_token: special_token(config.get("bos_token")),
+            eos_token: special_token(config.get("eos_token")),
+        })
+    }
+
+    /// Empty when the model directory has no `tokenizer_config.json`.
+    pub fn read(model_dir: &Path) -> Result<Self> {
+        let path = model_dir.join("tokenizer_config.json");
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Self::from_json(&std::fs::read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("Invalid {}: {e}", path.display()))
+    }
+}
+
+/// Special tokens are either plain strings or added token objects with a `content`.
+fn special_token(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(token) => Some(token.to_owned()),
+        Value::Object(token) => token.get("content")?.as_str().map(str::to_owned),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateMessage<'a> {
+    role: String,
+    content: &'a str,
+}
+
+/// Jinja chat template compiled with the extensions Hugging Face templates rely on.
+pub struct JinjaTemplate {
+    env: Environment<'static>,
+    bos_token: String,
+    eos_token: String,
+}
+
+impl JinjaTemplate {
+    pub fn new(
+        source: String,
+        bos_token: Option<String>,
+        eos_token: Option<String>,
+    ) -> Result<Self> {
+        let mut env = Environment::new();
+
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function(
+            "raise_exception",
+            |message: String| -> std::result::Result<String, minijinja::Error> {
+                Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+            },
+        );
+
+        env.add_template_owned(JINJA_TEMPLATE_NAME, source)
+            .map_err(|e| anyhow::anyhow!("Invalid chat_template: {e}"))?;
+
+        Ok(Self {
+            env,
+            bos_token: bos_token.unwrap_or_default(),
+            eos_token: eos_token.unwrap_or_default(),
+        })
+    }
+
+    /// The leading beginning of sequence token is stripped, the tokenizer adds it.
+    pub fn render(&self, messages: &[ChatMessage]) -> Result<String> {
+        let messages: Vec<TemplateMessage> = messages
+            .iter()
+            .map(|message| TemplateMessage {
+                role: message.role.to_string(),
+                content: &message.content,
+            })
+            .collect();
+
+        let rendered = self
+            .env
+            .get_template(JINJA_TEMPLATE_NAME)?
+            .render(context! {
+                messages => messages,
+                add_generation_prompt => true,
+                bos_token => self.bos_token,
+                eos_token => self.eos_token,
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to render chat_template: {e}"))?;
+
+        match rendered.strip_prefix(self.bos_token.as_str()) {
+            Some(rendered) if !self.bos_token.is_empty() => Ok(rendered.to_owned()),
+            _ => Ok(rendered),
+        }
+    }
+}
+
+/// Render `messages` followed by the assistant turn the model completes.
+///
+/// The beginning of sequence token is left to the tokenizer, which adds it
+/// when encoding with special tokens.
+pub fn render(template: ChatTemplate, messages: &[ChatMessage]) -> String {
+    match template {
+        ChatTemplate::ChatMl => render_chatml(messages),
+        ChatTemplate::Llama3 => render_llama3(messages),
+        ChatTemplate::Phi3 => render_phi3(messages),
+        ChatTemplate::MistralInstruct => render_mistral_instruct(messages),
+        ChatTemplate::Gemma => render_gemma(messages),
+    }
+}
+
+fn render_chatml(messages: &[ChatMessage]) -> String {
+    let mut result = String::new();
+
+    for message in messages {
+        result += &format!(
+            "<|im_start|>{}\n{}<|im_end|>\n",
+            message.role, message.content
+        );
+    }
+
+    result + "<|im_start|>assistant\n"
+}
+
+fn render_llama3(messages: &[ChatMessage]) -> String {
+    let mut result = String::new();
+
+    for message in messages {
+        result += &format!(
+            "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+            message.role,
+            message.content.trim()
+        );
+    }
+
+    result + "<|start_header_id|>assistant<|end_header_id|>\n\n"
+}
+
+fn render_phi3(messages: &[ChatMessage]) -> String {
+    let mut result = String::new();
+
+    for message in messages {
+        result += &format!("<|{}|>\n{}<|end|>\n", message.role, message.content);
+    }
+
+    result + "<|assistant|>\n"
+}
+
+/// Prepend the system message to the first user message, for templates without a system role.
+fn merge_system_message(messages: &[ChatMessage]) -> Vec<(MessageType, String)> {
+    let mut merged: Vec<(MessageType, String)> = Vec::new();
+    let mut system: Option<&str> = None;
+
+    for message in messages {
+        match message.role {
+            MessageType::System => system = Some(message.content.as_str()),
+            MessageType::User => {
+                let content = match system.take() {
+                    Some(system) => format!("{system}\n\n{}", message.content),
+                    None => message.content.to_owned(),
+                };
+
+                merged.push((MessageType::User, content));
+            }
+            MessageType::Assistant => {
+                merged.push((MessageType::Assistant, message.content.to_owned()))
+            }
+        }
+    }
+
+    merged
+}
+
+fn render_mistral_instruct(messages: &[ChatMessage]) -> String {
+    let mut result = String::new();
+
+    for (role, content) in merge_system_message(messages) {
+        match role {
+            MessageType::Assistant => result += &format!(" {}</s>", content.trim()),
+            _ => result += &format!("[INST] {} [/INST]", content.trim()),
+        }
+    }
+
+    result
+}
+
+/// Gemma calls the assistant `model`.
+fn render_gemma(messages: &[ChatMessage]) -> String {
+    let mut result = String::new();
+
+    for (role, content) in merge_system_message(messages) {
+        let role = match role {
+            MessageType::Assistant => "model",
+            _ => "user",
+        };
+
+        result += &format!("<start_of_turn>{role}\n{}<end_of_turn>\n", content.trim());
+    }
+
+    result + "<start_of_turn>model\n"
+}
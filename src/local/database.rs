@@ -1,5 +1,12 @@
-use crate::local::embedding::get_embeddings;
+use crate::embedder::{get_embedder_by_model_id, Embedder};
+use crate::utils::config::AppConfig;
 use crate::utils::constants::*;
+use crate::utils::extract::ExtractedDocument;
+use crate::utils::knowledge_base::{
+    DistanceMetric, KnowledgeBaseName, KnowledgeBaseSettings, SearchMode, SearchTuning,
+    VectorIndex,
+};
+use crate::utils::metadata_filter::{FilterParam, MetadataFilter};
 use crate::utils::text_splitter::TextSplitter;
 use crate::utils::vars::get_pgurl;
 use anyhow::{Error, Result};
@@ -10,7 +17,8 @@ use lazy_static::lazy_static;
 use pgvector::Vector;
 use serde_json::Value;
 use sqlx::Row;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{postgres::PgPoolOptions, PgConnection, PgPool, Postgres, Transaction};
+use std::time::Instant;
 use uuid::Uuid;
 
 lazy_static! {
@@ -18,9 +26,16 @@ lazy_static! {
         AsyncOnce::new(async { create_connection().await });
 }
 
+pub(crate) async fn get_pool() -> Result<&'static PgPool> {
+    POOL.get()
+        .await
+        .as_ref()
+        .map_err(|e| Error::msg(format!("DB Connection Initialization Failed: {e}")))
+}
+
 async fn create_connection() -> Result<PgPool> {
     match PgPoolOptions::new()
-        .max_connections(MAX_POOL_CONNECTION)
+        .max_connections(AppConfig::global().app_config.max_pool_connection)
         .connect(&get_pgurl())
         .await
     {
@@ -31,6 +46,61 @@ async fn create_connection() -> Result<PgPool> {
                 .execute(&pool)
                 .await?;
 
+            for migration in migrate_search_base_sql() {
+                sqlx::query(&migration).execute(&pool).await?;
+            }
+
+            for migration in lowercase_search_tables_sql() {
+                sqlx::query(&migration).execute(&pool).await?;
+            }
+
+            let table_names: Vec<String> = sqlx::query_scalar(&get_unmigrated_vector_tables_sql())
+                .fetch_all(&pool)
+                .await?;
+
+            for table_name in table_names {
+                let Ok(table_name) = KnowledgeBaseName::parse(&table_name) else {
+                    println!("Skipping migration of invalid knowledge base: {table_name}");
+                    continue;
+                };
+
+                println!("Migrating knowledge base {table_name}: adding content_tsv, the table is locked until it's rewritten");
+
+                let started = Instant::now();
+                let mut tx = pool.begin().await?;
+
+                for migration in migrate_vector_table_sql(&table_name) {
+                    sqlx::query(&migration).execute(&mut *tx).await?;
+                }
+
+                tx.commit().await?;
+
+                println!(
+                    "Migrated knowledge base {table_name} in {} ms",
+                    started.elapsed().as_millis()
+                );
+            }
+
+            sqlx::query(&create_ingestion_jobs_sql())
+                .execute(&pool)
+                .await?;
+
+            sqlx::query(&fail_interrupted_ingestion_jobs_sql())
+                .execute(&pool)
+                .await?;
+
+            sqlx::query(&create_chat_sessions_sql())
+                .execute(&pool)
+                .await?;
+
+            sqlx::query(&create_chat_messages_sql())
+                .execute(&pool)
+                .await?;
+
+            sqlx::query(&create_chat_messages_index_sql())
+                .execute(&pool)
+                .await?;
+
             return Ok(pool);
         }
         Err(e) => {
@@ -56,7 +126,7 @@ pub struct EmbeddingVectorValue {
 impl EmbeddingVectorValue {
     pub async fn get_adjacent_chunks(
         &self,
-        table_name: &str,
+        table_name: &KnowledgeBaseName,
         mut upper: i32,
         lower: i32,
     ) -> Result<Vec<EmbeddingVectorValue>> {
@@ -65,18 +135,14 @@ impl EmbeddingVectorValue {
                 upper = self.chunk_number;
             }
 
-            match sqlx::query_as::<_, EmbeddingVectorValue>(&get_adj_chunk_sql(table_name))
+            let result = sqlx::query_as::<_, EmbeddingVectorValue>(&get_adj_chunk_sql(table_name))
                 .bind(&self.content_id)
                 .bind(self.chunk_number - upper)
                 .bind(self.chunk_number + lower)
                 .fetch_all(pool)
-                .await
-            {
-                Ok(result) => return Ok(result),
-                Err(e) => {
-                    panic!("Error Occured: {:?}", e);
-                }
-            };
+                .await?;
+
+            return Ok(result);
         }
         return Err(Error::msg("DB Connection Initialization Failed."));
     }
@@ -89,20 +155,55 @@ pub struct RowContent {
     pub created_at: NaiveDateTime,
 }
 
-pub async fn create_table(table_name: &str) -> Result<()> {
+pub async fn create_table(
+    table_name: &KnowledgeBaseName,
+    settings: &KnowledgeBaseSettings,
+    vector_index: Option<&VectorIndex>,
+) -> Result<()> {
+    settings.validate()?;
+
+    if let Some(vector_index) = vector_index {
+        vector_index.validate(settings.dimension)?;
+    }
+
     if let Ok(pool) = POOL.get().await {
-        sqlx::query(&create_vector_table_sql(table_name))
+        sqlx::query(&create_vector_table_sql(table_name, settings.dimension))
+            .execute(pool)
+            .await?;
+
+        sqlx::query(&create_full_text_index_sql(table_name))
+            .execute(pool)
+            .await?;
+
+        if let Some(vector_index) = vector_index {
+            sqlx::query(&create_vector_index_sql(
+                table_name,
+                settings.distance_metric,
+                vector_index,
+            ))
             .execute(pool)
             .await?;
+        }
 
         sqlx::query(&create_raw_content_table_sql(table_name))
             .execute(pool)
             .await?;
 
-        let _ = sqlx::query(&insert_into_search_table_sql())
-            .bind(table_name)
+        let inserted = sqlx::query(&insert_into_search_table_sql())
+            .bind(table_name.as_str())
+            .bind(&settings.embedding_model)
+            .bind(settings.dimension as i32)
+            .bind(settings.distance_metric.as_str())
+            .bind(settings.chunk_size as i32)
+            .bind(settings.chunk_overlap as i32)
             .execute(pool)
-            .await;
+            .await?
+            .rows_affected();
+
+        // Created concurrently with other settings, the first one is kept.
+        if inserted == 0 && get_knowledge_base_settings(table_name).await? != *settings {
+            anyhow::bail!("Knowledge base already exists with other settings: {table_name}");
+        }
 
         return Ok(());
     }
@@ -110,26 +211,104 @@ pub async fn create_table(table_name: &str) -> Result<()> {
     Err(Error::msg("DB Connection Initialization Failed."))
 }
 
-async fn insert_into(table_name: &str, values: EmbeddingVectorValue) -> Result<()> {
-    if let Ok(pool) = POOL.get().await {
-        sqlx::query(&insert_into_vector_table_sql(table_name))
-            .bind(values.content_id)
-            .bind(values.content_chunk)
-            .bind(values.chunk_number)
-            .bind(values.embedding)
-            .bind(values.metadata)
-            .bind(values.created_at)
-            .execute(pool)
-            .await?;
+/// Replaces the vector index of an existing knowledge base, blocks until Postgres
+/// has finished building it. The new index is built concurrently under another
+/// name, so the table stays writable, and swapped in once it's ready.
+pub async fn build_vector_index(
+    table_name: &KnowledgeBaseName,
+    settings: &KnowledgeBaseSettings,
+    vector_index: &VectorIndex,
+) -> Result<()> {
+    let pool = get_pool().await?;
 
-        return Ok(());
+    vector_index.validate(settings.dimension)?;
+
+    sqlx::query(&drop_rebuilt_vector_index_sql(table_name))
+        .execute(pool)
+        .await?;
+
+    sqlx::query(&rebuild_vector_index_sql(
+        table_name,
+        settings.distance_metric,
+        vector_index,
+    ))
+    .execute(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(&drop_vector_index_sql(table_name))
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(&rename_rebuilt_vector_index_sql(table_name))
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Settings of the knowledge base, `None` when it doesn't exist.
+pub async fn find_knowledge_base_settings(
+    table_name: &KnowledgeBaseName,
+) -> Result<Option<KnowledgeBaseSettings>> {
+    if let Ok(pool) = POOL.get().await {
+        let Some(row) = sqlx::query(&get_search_table_settings_sql())
+            .bind(table_name.as_str())
+            .fetch_optional(pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let dimension: i32 = row.try_get("dimension")?;
+        let distance_metric: &str = row.try_get("distance_metric")?;
+        let chunk_size: i32 = row.try_get("chunk_size")?;
+        let chunk_overlap: i32 = row.try_get("chunk_overlap")?;
+
+        return Ok(Some(KnowledgeBaseSettings {
+            embedding_model: row.try_get("embedding_model")?,
+            dimension: dimension as usize,
+            distance_metric: DistanceMetric::parse(distance_metric)?,
+            chunk_size: chunk_size as usize,
+            chunk_overlap: chunk_overlap as usize,
+        }));
     }
 
-    return Err(Error::msg("DB Connection Intialization Failed."));
+    Err(Error::msg("DB Connection Initialization Failed."))
+}
+
+pub async fn get_knowledge_base_settings(
+    table_name: &KnowledgeBaseName,
+) -> Result<KnowledgeBaseSettings> {
+    find_knowledge_base_settings(table_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Knowledge base not found: {table_name}"))
+}
+
+async fn insert_into(
+    conn: &mut PgConnection,
+    table_name: &KnowledgeBaseName,
+    values: EmbeddingVectorValue,
+) -> Result<()> {
+    sqlx::query(&insert_into_vector_table_sql(table_name))
+        .bind(values.content_id)
+        .bind(values.content_chunk)
+        .bind(values.chunk_number)
+        .bind(values.embedding)
+        .bind(values.metadata)
+        .bind(values.created_at)
+        .execute(conn)
+        .await?;
+
+    Ok(())
 }
 
 async fn builk_insert_into(
-    table_name: &str,
+    conn: &mut PgConnection,
+    table_name: &KnowledgeBaseName,
     content_ids: Vec<String>,
     content_chunks: Vec<String>,
     chunk_numbers: Vec<i32>,
@@ -137,68 +316,75 @@ async fn builk_insert_into(
     metadatas: Vec<Value>,
     created_ats: Vec<NaiveDateTime>,
 ) -> Result<()> {
-    if let Ok(pool) = POOL.get().await {
-        sqlx::query(&bulk_insert_into_vector_table_sql(table_name))
-            .bind(content_ids)
-            .bind(content_chunks)
-            .bind(chunk_numbers)
-            .bind(embeddings)
-            .bind(metadatas)
-            .bind(created_ats)
-            .execute(pool)
-            .await
-            .map_err(Error::msg)?;
-    }
+    sqlx::query(&bulk_insert_into_vector_table_sql(table_name))
+        .bind(content_ids)
+        .bind(content_chunks)
+        .bind(chunk_numbers)
+        .bind(embeddings)
+        .bind(metadatas)
+        .bind(created_ats)
+        .execute(conn)
+        .await
+        .map_err(Error::msg)?;
 
     Ok(())
 }
 
 async fn insert_content_into(
-    table_name: &str,
+    conn: &mut PgConnection,
+    table_name: &KnowledgeBaseName,
     content_id: &str,
     title: &str,
     text: &str,
     metadata: Value,
 ) -> Result<()> {
-    if let Ok(pool) = POOL.get().await {
-        sqlx::query(&insert_raw_content_sql(table_name))
-            .bind(content_id)
-            .bind(title)
-            .bind(text)
-            .bind(metadata)
-            .execute(pool)
-            .await?;
+    sqlx::query(&insert_raw_content_sql(table_name))
+        .bind(content_id)
+        .bind(title)
+        .bind(text)
+        .bind(metadata)
+        .execute(conn)
+        .await?;
 
-        return Ok(());
-    }
+    Ok(())
+}
+
+/// Keeps only the ascii characters of `chunk`, `None` when nothing is left to embed.
+fn clean_chunk(chunk: &str) -> Option<String> {
+    let content_chunk: String = chunk.chars().filter(|c| c.is_ascii()).collect::<String>();
+    let content_chunk = content_chunk.trim();
 
-    return Err(Error::msg("DB Connection Intialization Failed."));
+    if content_chunk.is_empty() {
+        None
+    } else {
+        Some(content_chunk.to_owned())
+    }
 }
 
 pub async fn insert_vector_index_pg(
-    table_name: &str,
+    table_name: &KnowledgeBaseName,
     content_id: &str,
     chunk_number: i32,
     content_chunk: &str,
     metadata: Value,
 ) -> Result<()> {
-    let content_chunk = content_chunk
-        .chars()
-        .filter(|c| c.is_ascii())
-        .collect::<String>();
+    let pool = get_pool().await?;
 
-    if content_chunk.is_empty() {
-        return Err(anyhow::anyhow!("Content Chunk is empty"));
-    }
+    let content_chunk =
+        clean_chunk(content_chunk).ok_or_else(|| anyhow::anyhow!("Content Chunk is empty"))?;
 
-    let vector: Vec<f32> = get_embeddings(&content_chunk)?
-        .reshape((EMBEDDING_DIMENSION,))?
-        .to_vec1()?;
+    let settings = get_knowledge_base_settings(table_name).await?;
+
+    let embedder: Box<dyn Embedder> = get_embedder_by_model_id(&settings.embedding_model)?;
+
+    let vector: Vec<f32> = embedder.embed(&content_chunk).await?;
+
+    settings.check_embedding(&embedder.model_id(), &vector)?;
 
     let values = EmbeddingVectorValue {
         id: 0,
         content_id: content_id.to_string(),
-        content_chunk: content_chunk.to_string(),
+        content_chunk,
         chunk_number,
         metadata,
         embedding: vector.into(),
@@ -206,149 +392,290 @@ pub async fn insert_vector_index_pg(
         score: 0.0,
     };
 
-    insert_into(table_name, values).await?;
+    let mut conn = pool.acquire().await?;
+
+    insert_into(&mut conn, table_name, values).await?;
 
     Ok(())
 }
 
+/// Embeds every chunk first, then writes the content row and its chunks in one
+/// transaction so a failure never leaves a document without its chunks.
 pub async fn insert_split_chunks(
-    table_name: &str,
+    table_name: &KnowledgeBaseName,
     title: &str,
     text: &str,
     metadata: Value,
 ) -> Result<()> {
-    let content_id = Uuid::new_v4().to_string().replace("-", "");
+    let pool = get_pool().await?;
 
-    insert_content_into(table_name, &content_id, title, text, metadata.clone()).await?;
+    let settings = get_knowledge_base_settings(table_name).await?;
+
+    let embedder: Box<dyn Embedder> = get_embedder_by_model_id(&settings.embedding_model)?;
+
+    let content_id = Uuid::new_v4().to_string().replace("-", "");
 
     let text_splitter = TextSplitter::new();
 
     let chunks_with_overlap = text_splitter
-        .with_chunk_size(1000)
-        .with_chunk_overlap(150)
+        .with_chunk_size(settings.chunk_size)
+        .with_chunk_overlap(settings.chunk_overlap)
         .split(text);
 
-    for (i, chunk) in chunks_with_overlap.clone().into_iter().enumerate() {
-        insert_vector_index_pg(table_name, &content_id, i as i32, &chunk, metadata.clone()).await?;
+    let mut values: Vec<EmbeddingVectorValue> = Vec::new();
+
+    for (i, chunk) in chunks_with_overlap.into_iter().enumerate() {
+        let Some(content_chunk) = clean_chunk(&chunk) else {
+            continue;
+        };
+
+        let vector: Vec<f32> = embedder.embed(&content_chunk).await?;
+
+        settings.check_embedding(&embedder.model_id(), &vector)?;
+
+        values.push(EmbeddingVectorValue {
+            id: 0,
+            content_id: content_id.to_owned(),
+            content_chunk,
+            chunk_number: i as i32,
+            metadata: metadata.clone(),
+            embedding: vector.into(),
+            created_at: NaiveDateTime::default(),
+            score: 0.0,
+        });
+    }
+
+    let mut tx = pool.begin().await?;
+
+    insert_content_into(&mut tx, table_name, &content_id, title, text, metadata).await?;
+
+    for value in values {
+        insert_into(&mut tx, table_name, value).await?;
     }
 
+    tx.commit().await?;
+
     Ok(())
 }
 
-pub async fn bulk_insert_split_chunks(
-    table_name: &str,
-    title: &str,
-    text: &str,
-    metadata: Value,
-) -> Result<()> {
-    let content_id = Uuid::new_v4().to_string().replace("-", "");
+/// Writes the documents of one upload in a single transaction, so an upload
+/// that fails part way leaves nothing behind. Dropping it without `commit`
+/// rolls everything back.
+pub struct DocumentWriter {
+    table_name: KnowledgeBaseName,
+    settings: KnowledgeBaseSettings,
+    embedder: Box<dyn Embedder>,
+    tx: Transaction<'static, Postgres>,
+}
 
-    insert_content_into(table_name, &content_id, title, text, metadata.clone()).await?;
+impl DocumentWriter {
+    pub async fn begin(table_name: &KnowledgeBaseName) -> Result<Self> {
+        let pool = get_pool().await?;
 
-    let overlap_size: usize = 150;
-    let chunk_size: usize = 1000;
+        let settings = get_knowledge_base_settings(table_name).await?;
+        let embedder: Box<dyn Embedder> = get_embedder_by_model_id(&settings.embedding_model)?;
 
-    let mut position = 0;
-    let mut chunks_with_overlap = Vec::new();
+        Ok(Self {
+            table_name: table_name.clone(),
+            settings,
+            embedder,
+            tx: pool.begin().await?,
+        })
+    }
 
-    while position < text.len() {
-        let end = text
-            .char_indices()
-            .skip(position)
-            .take(chunk_size)
-            .last()
-            .map(|(i, _)| i)
-            .unwrap_or(text.len());
+    /// Splits `documents` into chunks, each stored with its metadata, the `heading`
+    /// path of the section it starts in and the pages it spans. The chunks are
+    /// embedded together, returns the number inserted.
+    pub async fn insert(
+        &mut self,
+        documents: &[(&str, &ExtractedDocument, Value)],
+    ) -> Result<usize> {
+        let table_name = &self.table_name;
+        let settings = &self.settings;
+        let embedder = &self.embedder;
+
+        let overlap_size: usize = settings.chunk_overlap;
+        let chunk_size: usize = settings.chunk_size;
+
+        let mut contents: Vec<(String, &str, &str, Value)> = Vec::new();
+
+        let mut content_ids: Vec<String> = Vec::new();
+        let mut content_chunks: Vec<String> = Vec::new();
+        let mut chunk_numbers: Vec<i32> = Vec::new();
+        let mut metadatas: Vec<Value> = Vec::new();
+        let mut created_ats: Vec<NaiveDateTime> = Vec::new();
+
+        for (title, document, metadata) in documents {
+            let text = document.text.as_str();
+
+            let content_id = Uuid::new_v4().to_string().replace("-", "");
+
+            for (i, chunk) in document
+                .chunks(chunk_size, overlap_size)
+                .into_iter()
+                .enumerate()
+            {
+                let Some(content_chunk) = clean_chunk(&chunk.text) else {
+                    continue;
+                };
 
-        let mut chunk = &text[position..end];
+                let mut chunk_metadata = metadata.clone();
 
-        if end != text.len() {
-            let overlap_end = text
-                .char_indices()
-                .skip(end)
-                .take(overlap_size)
-                .last()
-                .map(|(i, _)| i)
-                .unwrap_or(text.len());
+                if let Some(chunk_metadata) = chunk_metadata.as_object_mut() {
+                    if let Some(heading) = chunk.heading {
+                        chunk_metadata.insert("heading".to_owned(), Value::String(heading));
+                    }
 
-            chunk = &text[position..overlap_end];
+                    if let Some((page_start, page_end)) = chunk.pages {
+                        chunk_metadata.insert("page_start".to_owned(), page_start.into());
+                        chunk_metadata.insert("page_end".to_owned(), page_end.into());
+                    }
+                }
 
-            position = overlap_end - overlap_size;
-        } else {
-            position = end;
-        }
+                content_ids.push(content_id.to_owned());
+                content_chunks.push(content_chunk);
+                chunk_numbers.push(i as i32);
+                metadatas.push(chunk_metadata);
+                created_ats.push(NaiveDateTime::default());
+            }
 
-        chunks_with_overlap.push(chunk.to_string());
-    }
+            contents.push((content_id, title, text, metadata.clone()));
+        }
 
-    let mut content_ids: Vec<String> = Vec::new();
-    let mut content_chunks: Vec<String> = Vec::new();
-    let mut chunk_numbers: Vec<i32> = Vec::new();
-    let mut metadatas: Vec<Value> = Vec::new();
-    let mut embeddings: Vec<Vector> = Vec::new();
-    let mut created_ats: Vec<NaiveDateTime> = Vec::new();
+        let embeddings: Vec<Vec<f32>> = embedder.embed_batch(&content_chunks).await?;
 
-    for (i, chunk) in chunks_with_overlap.clone().into_iter().enumerate() {
-        let content_chunk: String = chunk.chars().filter(|c| c.is_ascii()).collect::<String>();
-        let content_chunk = content_chunk.trim();
+        if embeddings.len() != content_chunks.len() {
+            return Err(anyhow::anyhow!(
+                "Expected {} embeddings, got {}",
+                content_chunks.len(),
+                embeddings.len()
+            ));
+        }
 
-        if content_chunk.is_empty() {
-            continue;
+        for embedding in embeddings.iter() {
+            settings.check_embedding(&embedder.model_id(), embedding)?;
         }
 
-        let vector: Vec<f32> = get_embeddings(&content_chunk)?
-            .reshape((EMBEDDING_DIMENSION,))?
-            .to_vec1()?;
+        let embeddings: Vec<Vector> = embeddings.into_iter().map(Vector::from).collect();
+
+        let chunk_count = content_chunks.len();
 
-        content_ids.push(content_id.to_owned());
-        content_chunks.push(content_chunk.to_owned());
-        chunk_numbers.push(i as i32);
-        metadatas.push(metadata.clone());
-        embeddings.push(vector.into());
-        created_ats.push(NaiveDateTime::default());
+        for (content_id, title, text, metadata) in contents {
+            insert_content_into(&mut self.tx, table_name, &content_id, title, text, metadata)
+                .await?;
+        }
+
+        builk_insert_into(
+            &mut self.tx,
+            table_name,
+            content_ids,
+            content_chunks,
+            chunk_numbers,
+            embeddings,
+            metadatas,
+            created_ats,
+        )
+        .await?;
+
+        Ok(chunk_count)
     }
 
-    builk_insert_into(
-        table_name,
-        content_ids,
-        content_chunks,
-        chunk_numbers,
-        embeddings,
-        metadatas,
-        created_ats,
-    )
-    .await
-    .map_err(Error::msg)?;
+    pub async fn commit(self) -> Result<()> {
+        self.tx.commit().await?;
 
-    Ok(())
+        Ok(())
+    }
 }
 
+/// `query_embedding` must be embedded by `model_id`, the model the knowledge base was
+/// created with. It's only required when `search_mode` uses embeddings.
 pub async fn get_similar_results(
-    table_name: &str,
-    query: Vector,
+    table_name: &KnowledgeBaseName,
+    search_mode: SearchMode,
+    query_text: &str,
+    model_id: &str,
+    query_embedding: Option<Vector>,
     max_similar_res: usize,
     minimum_score: f32,
+    tuning: SearchTuning,
+    filter: &MetadataFilter,
 ) -> Result<Vec<EmbeddingVectorValue>> {
-    if let Ok(pool) = POOL.get().await {
-        match sqlx::query_as::<_, EmbeddingVectorValue>(&get_similar_result_query(
+    let pool = get_pool().await?;
+
+    let settings = get_knowledge_base_settings(table_name).await?;
+
+    if let Some(query_embedding) = &query_embedding {
+        settings.check_embedding(model_id, query_embedding.as_slice())?;
+    }
+
+    tuning.validate()?;
+
+    let mut tx = pool.begin().await?;
+
+    for (setting, value) in tuning.settings() {
+        sqlx::query(&set_local_config_sql())
+            .bind(setting)
+            .bind(value)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let filter_sql = filter.to_sql(match search_mode {
+        SearchMode::Hybrid => 3,
+        SearchMode::Vector | SearchMode::FullText => 2,
+    })?;
+
+    let filter_clause = filter_sql.and_clause();
+
+    let query_sql = match search_mode {
+        SearchMode::FullText => {
+            get_full_text_result_query(table_name, max_similar_res, &filter_clause)
+        }
+        SearchMode::Vector => get_similar_result_query(
             table_name,
+            settings.distance_metric,
             max_similar_res,
             minimum_score,
-        ))
-        .bind(query)
-        .fetch_all(pool)
-        .await
-        {
-            Ok(result) => {
-                return Ok(result);
-            }
-            Err(e) => {
-                panic!("{:?}", e);
-            }
+            &filter_clause,
+        ),
+        SearchMode::Hybrid => get_hybrid_result_query(
+            table_name,
+            settings.distance_metric,
+            max_similar_res,
+            minimum_score,
+            &filter_clause,
+        ),
+    };
+
+    let mut query = sqlx::query_as::<_, EmbeddingVectorValue>(&query_sql);
+
+    query = match (search_mode, query_embedding) {
+        (SearchMode::FullText, _) => query.bind(query_text),
+        (SearchMode::Vector, Some(query_embedding)) => query.bind(query_embedding),
+        (SearchMode::Hybrid, Some(query_embedding)) => {
+            query.bind(query_embedding).bind(query_text)
         }
+        (search_mode, None) => {
+            return Err(anyhow::anyhow!(
+                "Search mode {} requires a query embedding",
+                search_mode.as_str()
+            ))
+        }
+    };
+
+    for param in filter_sql.params {
+        query = match param {
+            FilterParam::Text(value) => query.bind(value),
+            FilterParam::TextArray(values) => query.bind(values),
+            FilterParam::Timestamp(value) => query.bind(value),
+        };
     }
 
-    return Err(Error::msg("DB Connection Intialization Failed."));
+    let result = query.fetch_all(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(result)
 }
 
 pub async fn list_search_tables() -> Result<Vec<String>> {
@@ -370,17 +697,18 @@ pub async fn list_search_tables() -> Result<Vec<String>> {
     return Err(Error::msg("DB Connection Initialization Failed."));
 }
 
-pub async fn delete_table(table_name: &str) -> Result<()> {
+pub async fn delete_table(table_name: &KnowledgeBaseName) -> Result<()> {
     if let Ok(pool) = POOL.get().await {
-        sqlx::query(&get_drop_table_sql(table_name.to_owned()))
+        sqlx::query(&get_drop_table_sql(table_name))
             .execute(pool)
             .await?;
 
-        sqlx::query(&get_drop_table_sql(table_name.to_owned() + &"_content"))
+        sqlx::query(&get_drop_content_table_sql(table_name))
             .execute(pool)
             .await?;
 
-        sqlx::query(&get_delete_from_search_table_sql(table_name))
+        sqlx::query(&get_delete_from_search_table_sql())
+            .bind(table_name.as_str())
             .execute(pool)
             .await?;
 
@@ -389,37 +717,3 @@ pub async fn delete_table(table_name: &str) -> Result<()> {
 
     return Err(Error::msg("DB Connection Initialization Failed."));
 }
-
-pub async fn get_similar_search(
-    table_name: &str,
-    max_similar_res: usize,
-    lower_chunk: i32,
-    upper_chunk: i32,
-    minimum_score: f32,
-    embedding: Vector,
-) -> Result<Vec<Value>> {
-    let references: Vec<EmbeddingVectorValue> =
-        get_similar_results(table_name, embedding, max_similar_res, minimum_score).await?;
-
-    let mut final_ref: Vec<Value> = Vec::new();
-
-    for reference in references.iter() {
-        let related = reference
-            .get_adjacent_chunks(table_name, upper_chunk, lower_chunk)
-            .await?;
-
-        let mut chunks: String = String::new();
-
-        for r in related.iter() {
-            chunks += &r.content_chunk;
-            chunks.push(' ');
-        }
-
-        final_ref.push(serde_json::json!({
-            "content": reference.content_chunk,
-            "metadata": reference.metadata
-        }))
-    }
-
-    Ok(final_ref)
-}
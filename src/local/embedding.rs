@@ -1,8 +1,12 @@
+use crate::embedder::Embedder;
+use crate::utils::config::LocalEmbeddingConfig;
+use crate::utils::constants::EMBEDDING_BATCH_SIZE;
 use crate::utils::utils;
 use crate::utils::utils::get_device;
 use crate::utils::vars;
 use anyhow::{Context, Error, Result};
-use candle_core::Tensor;
+use candle_core::{DType, Tensor};
+use futures::future::BoxFuture;
 use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config, DTYPE};
 use lazy_static::lazy_static;
@@ -76,3 +80,87 @@ pub fn get_embeddings(sentence: &str) -> Result<Tensor> {
 
     Ok(embeddings)
 }
+
+/// Mean pooled, L2 normalised embeddings for a batch of sentences, padding tokens excluded.
+pub fn get_batch_embeddings(sentences: &[String]) -> Result<Vec<Vec<f32>>> {
+    let (model, tokenizer) = &*EMB_MODEL;
+
+    let sentences: Vec<String> = sentences
+        .iter()
+        .map(|sentence| sentence.chars().filter(|c| c.is_ascii()).collect())
+        .collect();
+
+    let tokens = tokenizer
+        .encode_batch(sentences, true)
+        .map_err(Error::msg)?;
+
+    let token_ids = tokens
+        .iter()
+        .map(|tokens| {
+            let tokens = tokens.get_ids().to_vec();
+            Ok(Tensor::new(tokens.as_slice(), &get_device())?)
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("Unable to get token ids")?;
+
+    let attention_mask = tokens
+        .iter()
+        .map(|tokens| {
+            let mask = tokens.get_attention_mask().to_vec();
+            Ok(Tensor::new(mask.as_slice(), &get_device())?)
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("Unable to get attention mask")?;
+
+    let token_ids = Tensor::stack(&token_ids, 0).context("Unable to stack token ids")?;
+    let attention_mask = Tensor::stack(&attention_mask, 0)?.to_dtype(DType::F32)?;
+
+    let token_type_ids = token_ids.zeros_like().context("Unable to get embeddings")?;
+
+    let embeddings = model.forward(&token_ids, &token_type_ids)?;
+
+    let mask = attention_mask.unsqueeze(2)?;
+    let summed = embeddings.broadcast_mul(&mask)?.sum(1)?;
+    let embeddings = summed.broadcast_div(&mask.sum(1)?)?;
+
+    let embeddings = embeddings.broadcast_div(&embeddings.sqr()?.sum_keepdim(1)?.sqrt()?)?;
+
+    Ok(embeddings.to_vec2()?)
+}
+
+/// Candle BERT model loaded from `EMBEDDING_MODEL_PATH`.
+pub struct LocalEmbedder {
+    embedding_model: String,
+    dimension: usize,
+}
+
+impl LocalEmbedder {
+    pub fn new(config: &LocalEmbeddingConfig) -> Self {
+        Self {
+            embedding_model: config.embedding_model.to_string(),
+            dimension: config.dimension,
+        }
+    }
+}
+
+impl Embedder for LocalEmbedder {
+    fn embed_batch<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>> {
+        Box::pin(async move {
+            let mut embeddings = Vec::with_capacity(texts.len());
+
+            for batch in texts.chunks(EMBEDDING_BATCH_SIZE) {
+                embeddings.extend(get_batch_embeddings(batch)?);
+            }
+
+            Ok(embeddings)
+        })
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn model_id(&self) -> String {
+        format!("local:{}", self.embedding_model)
+    }
+}
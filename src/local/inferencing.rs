@@ -1,93 +1,69 @@
 use core::fmt;
-use std::collections::HashMap;
 
-use crate::azureai::AzureAI;
-use crate::local::database::EmbeddingVectorValue;
-use crate::local::{database, embedding};
-use crate::{utils::vars, utils};
-use anyhow::{Error as E, Result};
-use async_openai::types::{
-    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
-    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-    ChatCompletionRequestUserMessageContent,
+use crate::chat_backend::{get_chat_backend, ChatBackend, ChatMessage, TokenStream};
+use crate::generation::{GenerationParams, StopSequences};
+use crate::local::chat_history;
+use crate::local::models::{self, LocalModel};
+use crate::prompt::{self, PackedPrompt, PromptBudget};
+use crate::protocol::{Citation, EventSender, ServerEvent, Usage};
+use crate::retrieval::SearchHit;
+use crate::utils;
+use crate::utils::config::LocalModelConfig;
+use crate::utils::constants::{
+    DEFAULT_REPEAT_LAST_N, DEFAULT_REPEAT_PENALTY, DEFAULT_SEED, DEFAULT_TEMPERATURE,
 };
+use anyhow::{Error as E, Result};
 use candle_core::Tensor;
 use candle_core::{DType, Device};
-use candle_transformers::generation::LogitsProcessor;
-use candle_transformers::models::quantized_mixformer::Config;
-use candle_transformers::models::quantized_mixformer::MixFormerSequentialForCausalLM as QMixFormer;
-use futures::stream::SplitSink;
-use futures::SinkExt;
-use lazy_static::lazy_static;
-use serde_json::json;
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use std::time::Instant;
 use tokenizers::Tokenizer;
+use tokio::sync::oneshot;
 
 const MAXIMUM_SAMPLE_LEN: usize = 512;
 
-lazy_static! {
-    pub static ref PHI: (QMixFormer, Tokenizer) = load_model().expect("Unable to Load Model");
-}
-
-const EMBEDDING_DIMENSION: usize = 1024;
-
-pub fn load_model() -> Result<(QMixFormer, Tokenizer)> {
-    let quantized_path = vars::get_inferencing_model_path();
-
-    let tokerizer_file = quantized_path.join("tokenizer.json");
-
-    let tokenizer =
-        Tokenizer::from_file(tokerizer_file).expect("Failed to load Quantized tokenizer");
-
-    let weights_filename = quantized_path.join("model-q4k.gguf");
-
-    let config = Config::v2();
-
-    let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
-        &weights_filename,
-        &utils::utils::get_device(),
-    )?;
-
-    let model = QMixFormer::new_v2(&config, vb).map_err(E::msg)?;
-
-    Ok((model, tokenizer))
-}
-
 pub struct TextGeneration {
-    pub model: QMixFormer,
+    pub model: LocalModel,
     pub device: Device,
     pub tokenizer: Tokenizer,
+    pub eos_token_ids: Vec<u32>,
     pub logits_processor: LogitsProcessor,
     pub repeat_penalty: f32,
     pub repeat_last_n: usize,
 }
 
 impl TextGeneration {
-    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        model: QMixFormer,
+        model: LocalModel,
         tokenizer: Tokenizer,
+        eos_token_ids: Vec<u32>,
         seed: u64,
-        temp: Option<f64>,
-        top_p: Option<f64>,
+        sampling: Sampling,
         repeat_penalty: f32,
         repeat_last_n: usize,
     ) -> Self {
-        let logits_processor = LogitsProcessor::new(seed, temp, top_p);
+        let logits_processor = LogitsProcessor::from_sampling(seed, sampling);
         Self {
             model,
             device: utils::utils::get_device(),
             tokenizer,
+            eos_token_ids,
             logits_processor,
             repeat_penalty,
             repeat_last_n,
         }
     }
 
-    pub async fn run(
+    /// Generate up to `sample_len` tokens, handing each decoded token to `on_token`.
+    /// Generation stops early when `on_token` returns `false`.
+    pub fn generate(
         &mut self,
         prompt: &str,
         sample_len: usize,
-        websocket: &mut SplitSink<warp::ws::WebSocket, warp::filters::ws::Message>,
+        mut on_token: impl FnMut(String) -> bool,
     ) -> Result<String> {
         let mut response: String = String::new();
 
@@ -96,15 +72,12 @@ impl TextGeneration {
             anyhow::bail!("Empty prompts are not supported in the phi model.")
         }
         let mut tokens = tokens.get_ids().to_vec();
-        let eos_token = match self.tokenizer.get_vocab(true).get("<|im_end|>") {
-            Some(token) => *token,
-            None => panic!("cannot find the endoftext token"),
-        };
         for index in 0..sample_len {
             let context_size = if index > 0 { 1 } else { tokens.len() };
-            let ctxt = &tokens[tokens.len().saturating_sub(context_size)..];
+            let start_pos = tokens.len().saturating_sub(context_size);
+            let ctxt = &tokens[start_pos..];
             let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
-            let logits = self.model.forward(&input)?;
+            let logits = self.model.forward(&input, start_pos)?;
             let logits = logits.squeeze(0)?.to_dtype(DType::F32)?;
             let logits = if self.repeat_penalty == 1. {
                 logits
@@ -119,27 +92,169 @@ impl TextGeneration {
 
             let next_token = self.logits_processor.sample(&logits)?;
             tokens.push(next_token);
-            if next_token == eos_token {
+            if self.eos_token_ids.contains(&next_token) {
                 break;
             }
             let token = self.tokenizer.decode(&[next_token], true).map_err(E::msg)?;
 
-            websocket
-                .send(warp::ws::Message::text(token.clone()))
-                .await?;
-
             response += &token;
+
+            if !on_token(token) {
+                break;
+            }
         }
         return Ok(response);
     }
 }
 
+/// One of the configured `local_models`, loaded on first use.
+pub struct LocalChatBackend {
+    model: LocalModelConfig<'static>,
+    max_tokens: usize,
+    context_window: usize,
+}
+
+impl LocalChatBackend {
+    pub fn new(model: LocalModelConfig<'static>, max_tokens: Option<usize>) -> Self {
+        Self {
+            context_window: model.context_window(),
+            model,
+            max_tokens: max_tokens.unwrap_or(MAXIMUM_SAMPLE_LEN),
+        }
+    }
+
+    pub fn with_context_window(mut self, context_window: usize) -> Self {
+        self.context_window = context_window;
+        self
+    }
+}
+
+impl ChatBackend for LocalChatBackend {
+    fn stream_chat<'a>(
+        &'a self,
+        messages: &'a [ChatMessage],
+        params: &'a GenerationParams,
+    ) -> TokenStream<'a> {
+        let messages = messages.to_vec();
+        let sample_len = params.max_tokens.unwrap_or(self.max_tokens);
+        let params = params.clone();
+        let model = self.model.clone();
+
+        let (tx, rx) = mpsc::unbounded::<Result<String>>();
+
+        tokio::task::spawn_blocking(move || {
+            let loaded = models::get_model(&model);
+
+            let (loaded, prompt) = match loaded.and_then(|loaded| {
+                let prompt = loaded.template.render(&messages)?;
+                Ok((loaded, prompt))
+            }) {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    let _ = tx.unbounded_send(Err(e));
+                    return;
+                }
+            };
+
+            let mut pipeline = TextGeneration::new(
+                loaded.model.clone(),
+                loaded.tokenizer.clone(),
+                loaded.eos_token_ids.clone(),
+                params.seed.unwrap_or(DEFAULT_SEED),
+                sampling(&params),
+                params.repeat_penalty.unwrap_or(DEFAULT_REPEAT_PENALTY),
+                DEFAULT_REPEAT_LAST_N,
+            );
+
+            let mut stop = StopSequences::new(&params.stop);
+
+            // A closed receiver means the client went away, so stop generating.
+            let result = pipeline.generate(&prompt, sample_len, |token| {
+                let text = stop.push(&token);
+
+                (text.is_empty() || tx.unbounded_send(Ok(text)).is_ok()) && !stop.is_stopped()
+            });
+
+            let rest = stop.finish();
+
+            if !rest.is_empty() {
+                let _ = tx.unbounded_send(Ok(rest));
+            }
+
+            if let Err(e) = result {
+                let _ = tx.unbounded_send(Err(e));
+            }
+        });
+
+        rx.boxed()
+    }
+
+    fn context_window(&self) -> usize {
+        self.context_window
+    }
+
+    fn max_new_tokens(&self, params: &GenerationParams) -> usize {
+        params.max_tokens.unwrap_or(self.max_tokens)
+    }
+
+    /// Estimated until `prepare` has loaded the model.
+    fn count_tokens(&self, text: &str) -> usize {
+        let encoding = models::loaded_model(&self.model)
+            .and_then(|loaded| loaded.tokenizer.encode(text, false).ok());
+
+        match encoding {
+            Some(encoding) => encoding.len(),
+            None => prompt::estimate_tokens(text),
+        }
+    }
+
+    /// Loads the model on a blocking thread the first time it's used.
+    fn prepare(&self) -> BoxFuture<'_, Result<()>> {
+        let model = self.model.clone();
+
+        async move {
+            tokio::task::spawn_blocking(move || models::get_model(&model)).await??;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Candle sampler for `params`, temperature 0 always picks the most likely token.
+fn sampling(params: &GenerationParams) -> Sampling {
+    let temperature = params.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+
+    if temperature <= 0.0 {
+        return Sampling::ArgMax;
+    }
+
+    match (params.top_k, params.top_p) {
+        (None, None) => Sampling::All { temperature },
+        (Some(k), None) => Sampling::TopK { k, temperature },
+        (None, Some(p)) => Sampling::TopP { p, temperature },
+        (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MessageType {
     System,
     User,
     Assistant,
 }
 
+impl MessageType {
+    pub fn parse(role: &str) -> Result<Self> {
+        match role {
+            "system" => Ok(Self::System),
+            "user" => Ok(Self::User),
+            "assistant" => Ok(Self::Assistant),
+            _ => anyhow::bail!("Unknown message role: {role}"),
+        }
+    }
+}
+
 impl fmt::Display for MessageType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -158,7 +273,6 @@ impl fmt::Display for MessageType {
 
 pub struct ModelQuery {
     system_message: String,
-    chat_history: HashMap<String, Vec<(MessageType, String)>>,
 }
 
 impl ModelQuery {
@@ -167,257 +281,170 @@ impl ModelQuery {
             system_message: format!(
                 "You're a friendly and helpful AI Assistant. Be Concise and don't repeat yourself"
             ),
-            chat_history: HashMap::new(),
         }
     }
 
-    pub fn add_user_message(
-        &mut self,
-        question: &str,
+    /// Store a question with its complete answer.
+    pub async fn add_exchange(
+        &self,
         session_id: &str,
-        references: &Vec<EmbeddingVectorValue>,
-    ) {
-        let mut context = Vec::new();
-
-        for reference in references.clone() {
-            context.push(json!({
-                "content": reference.content_chunk,
-                "metadata": reference.metadata
-            }))
-        }
-
-        let context: String = json!(context).to_string();
+        message: &str,
+        response: &str,
+    ) -> Result<()> {
+        chat_history::add_messages(
+            session_id,
+            &[
+                (MessageType::User, message),
+                (MessageType::Assistant, response),
+            ],
+        )
+        .await
+    }
+    /// System message, the stored history of `session_id` and the question with
+    /// its references, packed into the context window of `backend`.
+    pub async fn build_messages(
+        &self,
+        backend: &dyn ChatBackend,
+        params: &GenerationParams,
+        session_id: &str,
+        question: &str,
+        references: &[SearchHit],
+    ) -> Result<PackedPrompt> {
+        let history = chat_history::recent_messages(session_id).await?;
 
-        let next_message = format!("question: \"{question}\"\nreferences: \"{context}\"\n");
+        backend.prepare().await?;
 
-        match self.chat_history.get_mut(session_id) {
-            Some(messages) => {
-                messages.push((MessageType::User, next_message));
-            }
-            None => {
-                self.chat_history.insert(
-                    session_id.to_string(),
-                    vec![
-                        (MessageType::System, self.system_message.clone()),
-                        (MessageType::User, next_message),
-                    ],
-                );
-            }
-        }
-    }
-    pub fn add_assistant_message(&mut self, session_id: &str, response: String) {
-        match self.chat_history.get_mut(session_id) {
-            Some(message) => message.push((MessageType::Assistant, response)),
-            None => {
-                self.chat_history.insert(
-                    session_id.to_string(),
-                    vec![(MessageType::Assistant, response)],
-                );
-            }
-        }
-    }
-    pub fn build_local(&self, session_id: &str) -> String {
-        match self.chat_history.get(session_id) {
-            Some(history) => {
-                let mut result = String::new();
-
-                for (msg_type, message) in history {
-                    match msg_type {
-                        MessageType::System => {
-                            result += &format!("<|im_start|>system\n{message}<|im_end|>\n")
-                        }
-                        MessageType::User => {
-                            result += &format!("<|im_start|>user\n{message}<|im_end|>\n")
-                        }
-                        MessageType::Assistant => {
-                            result += &format!("<|im_start|>assistant\n{message}<|im_end|>\n")
-                        }
-                    }
-                }
+        let budget = PromptBudget {
+            context_window: backend.context_window(),
+            max_new_tokens: backend.max_new_tokens(params),
+        };
 
-                return result;
-            }
-            None => {
-                println!("No History");
-                return String::new();
-            }
-        }
-    }
-    pub fn build_openai(&self, session_id: &str) -> Result<Vec<ChatCompletionRequestMessage>> {
-        let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
-
-        match self.chat_history.get(session_id) {
-            Some(history) => {
-                for (msg_type, message) in history {
-                    match msg_type {
-                        MessageType::System => messages.push(
-                            ChatCompletionRequestSystemMessageArgs::default()
-                                .content(message)
-                                .build()?
-                                .into(),
-                        ),
-                        MessageType::User => messages.push(
-                            ChatCompletionRequestUserMessageArgs::default()
-                                .content(ChatCompletionRequestUserMessageContent::Text(
-                                    message.to_owned(),
-                                ))
-                                .build()?
-                                .into(),
-                        ),
-                        MessageType::Assistant => messages.push(
-                            ChatCompletionRequestAssistantMessageArgs::default()
-                                .content(message)
-                                .build()?
-                                .into(),
-                        ),
-                    }
-                }
-            }
-            None => {
-                println!("No History!");
-            }
+        let packed = prompt::pack_prompt(
+            &self.system_message,
+            history,
+            question,
+            references,
+            &budget,
+            |text| backend.count_tokens(text),
+        )?;
+
+        if packed.dropped_references > 0
+            || packed.truncated_references > 0
+            || packed.dropped_history > 0
+        {
+            println!(
+                "Prompt for session {session_id} trimmed to fit {} tokens: {} references dropped, {} truncated, {} history messages dropped",
+                budget.context_window,
+                packed.dropped_references,
+                packed.truncated_references,
+                packed.dropped_history
+            );
         }
 
-        return Ok(messages);
+        Ok(packed)
     }
+    /// Stream the answer as protocol events: the references, the tokens and
+    /// `done`, or an `error` once something fails.
+    ///
+    /// Generation stops when `cancelled` resolves. The question and its answer are
+    /// only added to the session history once the answer is done, partial answers
+    /// aren't kept.
+    #[allow(clippy::too_many_arguments)]
     pub async fn answer_with_context(
-        &mut self,
-        websocket: &mut SplitSink<warp::ws::WebSocket, warp::filters::ws::Message>,
+        &self,
+        events: &EventSender,
         query: &str,
         session_id: &str,
         deployment_type: &str,
         deployment_model: &str,
-        references: Vec<EmbeddingVectorValue>,
+        references: Vec<SearchHit>,
+        params: &GenerationParams,
+        cancelled: &mut oneshot::Receiver<()>,
     ) -> Result<()> {
         if references.is_empty() {
-            websocket
-                .send(warp::ws::Message::text(
-                    "The query doesn't match any refences",
-                ))
-                .await?;
+            events.send(ServerEvent::error("The query doesn't match any references"))?;
 
             return Ok(());
         }
 
-        match deployment_type {
-            "AZURE" => {
-                let azure_conn =
-                    AzureAI::new("abu-openai", "2024-02-01", "gpt-4o", deployment_model, 2048);
-
-                self.add_user_message(query, session_id, &references);
+        let backend: Box<dyn ChatBackend + '_> =
+            match get_chat_backend(deployment_type, deployment_model) {
+                Ok(backend) => backend,
+                Err(e) => {
+                    events.send(ServerEvent::error(e))?;
 
-                let resp = azure_conn
-                    .run_azureai(websocket, &self.build_openai(session_id)?)
-                    .await?;
-
-                let _ = websocket
-                    .send(warp::ws::Message::text("Reference Source: \n"))
-                    .await;
-
-                for source in references.iter() {
-                    let _ = websocket
-                        .send(warp::ws::Message::text(format!(
-                            "{}",
-                            source.metadata["source"]
-                                .to_string()
-                                .split(".")
-                                .nth(0)
-                                .unwrap_or("")
-                        )))
-                        .await?;
+                    return Ok(());
                 }
+            };
+
+        let packed = match self
+            .build_messages(backend.as_ref(), params, session_id, query, &references)
+            .await
+        {
+            Ok(packed) => packed,
+            Err(e) => {
+                events.send(ServerEvent::error(e))?;
 
-                self.add_assistant_message(session_id, resp);
+                return Ok(());
             }
-            _ => {
-                let (model, tokenizer) = &*PHI;
-
-                let mut pipeline = TextGeneration::new(
-                    model.clone(),
-                    tokenizer.clone(),
-                    12345,
-                    Some(0.7),
-                    None,
-                    1.1,
-                    64,
-                );
-
-                self.add_user_message(query, session_id, &references);
-
-                let resp = pipeline
-                    .run(&self.build_local(session_id), MAXIMUM_SAMPLE_LEN, websocket)
-                    .await?;
+        };
 
-                let _ = websocket
-                    .send(warp::ws::Message::text("Reference Source: \n"))
-                    .await?;
+        events.send(ServerEvent::References {
+            references: packed.references.iter().map(Citation::from).collect(),
+        })?;
+
+        let started = Instant::now();
+        // Dropping the stream stops generation, locally and remotely.
+        let mut tokens = backend.stream_chat(&packed.messages, params);
 
-                for source in references.iter() {
-                    let _ = websocket
-                        .send(warp::ws::Message::text(format!(
-                            "{}",
-                            source.metadata["source"]
-                                .to_string()
-                                .split(".")
-                                .nth(0)
-                                .unwrap_or("")
-                        )))
-                        .await?;
+        let mut resp: String = String::new();
+        let mut outcome: Option<ServerEvent> = None;
+
+        loop {
+            let token = tokio::select! {
+                token = tokens.next() => token,
+                _ = &mut *cancelled => {
+                    outcome = Some(ServerEvent::Cancelled);
+                    break;
                 }
+            };
 
-                self.add_assistant_message(session_id, resp);
-            }
-        }
+            match token {
+                Some(Ok(token)) => {
+                    resp += &token;
 
-        Ok(())
-    }
-    pub async fn get_embeddings(
-        &self,
-        table_name: &str,
-        query: &str,
-        max_similar_res: usize,
-        lower_chunk: i32,
-        upper_chunk: i32,
-        minimum_score: f32,
-    ) -> Result<Vec<EmbeddingVectorValue>> {
-        let embeddings: Vec<f32> = embedding::get_embeddings(&query)?
-            .reshape((EMBEDDING_DIMENSION,))?
-            .to_vec1()?;
-
-        let references = database::get_similar_results(
-            table_name,
-            embeddings.into(),
-            max_similar_res,
-            minimum_score,
-        )
-        .await?;
+                    events.send(ServerEvent::Token { content: token })?;
+                }
+                Some(Err(e)) => {
+                    println!("{:?}", e);
 
-        let mut final_ref: Vec<EmbeddingVectorValue> = Vec::with_capacity(references.len());
+                    outcome = Some(ServerEvent::error(format!("Generation failed: {e}")));
+                    break;
+                }
+                None => break,
+            }
+        }
 
-        for (i, reference) in references.iter().enumerate() {
-            let related = reference
-                .get_adjacent_chunks(table_name, upper_chunk, lower_chunk)
-                .await?;
+        drop(tokens);
 
-            let mut chunks: String = String::new();
+        let outcome = match outcome {
+            Some(outcome) => outcome,
+            None => {
+                self.add_exchange(session_id, packed.user_message(), &resp)
+                    .await?;
 
-            for r in related.iter() {
-                chunks += &r.content_chunk;
-                chunks.push(' ');
+                ServerEvent::Done {
+                    usage: Usage {
+                        prompt_tokens: packed.prompt_tokens,
+                        completion_tokens: backend.count_tokens(&resp),
+                        duration_ms: started.elapsed().as_millis(),
+                    },
+                }
             }
+        };
 
-            final_ref.push(EmbeddingVectorValue {
-                id: i as i64,
-                content_id: reference.content_id.to_owned(),
-                content_chunk: chunks,
-                chunk_number: reference.chunk_number,
-                embedding: reference.embedding.clone(),
-                metadata: reference.metadata.clone(),
-                created_at: reference.created_at,
-                score: reference.score,
-            });
-        }
+        events.send(outcome)?;
 
-        return Ok(final_ref);
+        Ok(())
     }
 }
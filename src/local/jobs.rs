@@ -0,0 +1,125 @@
+use crate::local::database::get_pool;
+use crate::utils::constants::*;
+use crate::utils::knowledge_base::KnowledgeBaseName;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::future::Future;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Progress of one uploaded file, stored in `ingestion_jobs`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct IngestionJob {
+    pub id: String,
+    pub table_name: String,
+    pub file_name: String,
+    pub status: String,
+    pub chunk_count: i32,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Record a new job, `error` marks it as failed straight away (e.g. unsupported file).
+pub async fn create_job(
+    table_name: &KnowledgeBaseName,
+    file_name: &str,
+    error: Option<&str>,
+) -> Result<String> {
+    let pool = get_pool().await?;
+
+    let job_id = Uuid::new_v4().to_string().replace("-", "");
+
+    let status = match error {
+        Some(_) => JobStatus::Failed,
+        None => JobStatus::Queued,
+    };
+
+    sqlx::query(&insert_ingestion_job_sql())
+        .bind(&job_id)
+        .bind(table_name.as_str())
+        .bind(file_name)
+        .bind(status.as_str())
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+    Ok(job_id)
+}
+
+pub async fn update_job(
+    job_id: &str,
+    status: JobStatus,
+    chunk_count: usize,
+    error: Option<&str>,
+) -> Result<()> {
+    let pool = get_pool().await?;
+
+    sqlx::query(&update_ingestion_job_sql())
+        .bind(job_id)
+        .bind(status.as_str())
+        .bind(chunk_count as i32)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_job(job_id: &str) -> Result<Option<IngestionJob>> {
+    let pool = get_pool().await?;
+
+    Ok(sqlx::query_as::<_, IngestionJob>(&get_ingestion_job_sql())
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await?)
+}
+
+/// Most recent jobs, optionally only for one knowledge base.
+pub async fn list_jobs(table_name: Option<&KnowledgeBaseName>) -> Result<Vec<IngestionJob>> {
+    let pool = get_pool().await?;
+
+    Ok(sqlx::query_as::<_, IngestionJob>(&list_ingestion_jobs_sql())
+        .bind(table_name.map(|table_name| table_name.as_str()))
+        .fetch_all(pool)
+        .await?)
+}
+
+/// Drive `ingestion` to completion, recording its outcome on the job.
+pub async fn run_job(job_id: String, ingestion: impl Future<Output = Result<usize>>) {
+    if let Err(e) = update_job(&job_id, JobStatus::Running, 0, None).await {
+        println!("Unable to update job {job_id}: {:?}", e);
+    }
+
+    let result = match ingestion.await {
+        Ok(chunk_count) => update_job(&job_id, JobStatus::Succeeded, chunk_count, None).await,
+        Err(e) => {
+            println!("Ingestion job {job_id} failed: {:?}", e);
+
+            update_job(&job_id, JobStatus::Failed, 0, Some(&e.to_string())).await
+        }
+    };
+
+    if let Err(e) = result {
+        println!("Unable to update job {job_id}: {:?}", e);
+    }
+}
@@ -1,3 +1,8 @@
+pub mod chat_history;
+pub mod chat_template;
 pub mod database;
 pub mod embedding;
 pub mod inferencing;
+pub mod jobs;
+pub mod models;
+pub mod reranker;
@@ -0,0 +1,205 @@
+use crate::local::chat_template::{JinjaTemplate, PromptTemplate, TokenizerConfig};
+use crate::utils::config::LocalModelConfig;
+use crate::utils::local_models::LocalArchitecture;
+use crate::utils::utils::get_device;
+
+use anyhow::{Error as E, Result};
+use candle_core::quantized::gguf_file;
+use candle_core::{Device, Tensor};
+use candle_transformers::models::quantized_mixformer::Config;
+use candle_transformers::models::quantized_mixformer::MixFormerSequentialForCausalLM as QMixFormer;
+use candle_transformers::models::{quantized_llama, quantized_phi3, quantized_qwen2};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokenizers::Tokenizer;
+
+/// A model once loaded, locked while it loads.
+type ModelSlot = Arc<Mutex<Option<Arc<LoadedModel>>>>;
+
+lazy_static! {
+    /// Models used so far by lowercase name, they stay in memory once loaded.
+    static ref LOADED_MODELS: Mutex<HashMap<String, ModelSlot>> = Mutex::new(HashMap::new());
+}
+
+/// Quantized weights of one of the supported architectures.
+#[derive(Clone)]
+pub enum LocalModel {
+    Phi2(QMixFormer),
+    Phi3(quantized_phi3::ModelWeights),
+    /// Llama and Mistral share the GGUF layout.
+    Llama(quantized_llama::ModelWeights),
+    Qwen2(quantized_qwen2::ModelWeights),
+}
+
+impl LocalModel {
+    pub fn load(architecture: LocalArchitecture, weights: &Path, device: &Device) -> Result<Self> {
+        let model = match architecture {
+            LocalArchitecture::Phi2 => {
+                let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
+                    weights, device,
+                )?;
+
+                Self::Phi2(QMixFormer::new_v2(&Config::v2(), vb).map_err(E::msg)?)
+            }
+            LocalArchitecture::Phi3 => {
+                let (content, mut file) = read_gguf(weights)?;
+
+                Self::Phi3(quantized_phi3::ModelWeights::from_gguf(
+                    false, content, &mut file, device,
+                )?)
+            }
+            LocalArchitecture::Llama | LocalArchitecture::Mistral => {
+                let (content, mut file) = read_gguf(weights)?;
+
+                Self::Llama(quantized_llama::ModelWeights::from_gguf(
+                    content, &mut file, device,
+                )?)
+            }
+            LocalArchitecture::Qwen2 => {
+                let (content, mut file) = read_gguf(weights)?;
+
+                Self::Qwen2(quantized_qwen2::ModelWeights::from_gguf(
+                    content, &mut file, device,
+                )?)
+            }
+        };
+
+        Ok(model)
+    }
+
+    /// Logits for the last position of `input`, `index_pos` is the number of
+    /// tokens already in the key value cache.
+    pub fn forward(&mut self, input: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let logits = match self {
+            Self::Phi2(model) => model.forward(input)?,
+            Self::Phi3(model) => model.forward(input, index_pos)?,
+            Self::Llama(model) => model.forward(input, index_pos)?,
+            Self::Qwen2(model) => model.forward(input, index_pos)?,
+        };
+
+        Ok(logits)
+    }
+}
+
+fn read_gguf(weights: &Path) -> Result<(gguf_file::Content, File)> {
+    let mut file = File::open(weights)
+        .map_err(|e| anyhow::anyhow!("Unable to open {}: {e}", weights.display()))?;
+
+    let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(weights))?;
+
+    Ok((content, file))
+}
+
+/// Weights, tokenizer, prompt template and end of turn token ids of a local model.
+pub struct LoadedModel {
+    pub model: LocalModel,
+    pub tokenizer: Tokenizer,
+    pub template: PromptTemplate,
+    pub eos_token_ids: Vec<u32>,
+}
+
+impl LoadedModel {
+    pub fn load(config: &LocalModelConfig) -> Result<Self> {
+        let model_dir = config.model_dir()?;
+
+        let tokenizer = Tokenizer::from_file(model_dir.join("tokenizer.json")).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to load tokenizer of local model {}: {e}",
+                config.name
+            )
+        })?;
+
+        let tokenizer_config = TokenizerConfig::read(&model_dir)?;
+
+        // A configured template wins over the one shipped with the model.
+        let template = match (config.chat_template, tokenizer_config.chat_template) {
+            (None, Some(source)) => PromptTemplate::Jinja(Box::new(JinjaTemplate::new(
+                source,
+                tokenizer_config.bos_token,
+                tokenizer_config.eos_token.clone(),
+            )?)),
+            _ => PromptTemplate::BuiltIn(config.chat_template()),
+        };
+
+        let mut eos_tokens = config.eos_tokens();
+
+        if config.eos_tokens.is_empty() {
+            eos_tokens.extend(tokenizer_config.eos_token);
+        }
+
+        let vocab = tokenizer.get_vocab(true);
+
+        let eos_token_ids: Vec<u32> = eos_tokens
+            .iter()
+            .filter_map(|token| vocab.get(token).copied())
+            .collect();
+
+        if eos_token_ids.is_empty() {
+            anyhow::bail!(
+                "None of the end of turn tokens {:?} are in the vocabulary of local model {}, set its eos_tokens",
+                eos_tokens,
+                config.name
+            );
+        }
+
+        let model = LocalModel::load(
+            config.architecture,
+            &model_dir.join(config.weights.as_ref()),
+            &get_device(),
+        )?;
+
+        Ok(Self {
+            model,
+            tokenizer,
+            template,
+            eos_token_ids,
+        })
+    }
+}
+
+fn model_slot(config: &LocalModelConfig) -> Result<ModelSlot> {
+    let mut loaded_models = LOADED_MODELS
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Local model registry is poisoned"))?;
+
+    Ok(loaded_models
+        .entry(config.name.to_lowercase())
+        .or_default()
+        .clone())
+}
+
+/// Load `config` on first use and share it afterwards. Loading blocks, call
+/// it from a blocking task. Only lookups of the model being loaded wait for it.
+pub fn get_model(config: &LocalModelConfig) -> Result<Arc<LoadedModel>> {
+    let slot = model_slot(config)?;
+
+    let mut slot = slot
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Local model {} failed to load", config.name))?;
+
+    if let Some(model) = slot.as_ref() {
+        return Ok(model.clone());
+    }
+
+    println!(
+        "Loading local model {} ({:?})",
+        config.name, config.architecture
+    );
+
+    let model = Arc::new(LoadedModel::load(config)?);
+
+    *slot = Some(model.clone());
+
+    Ok(model)
+}
+
+/// `config` if it's loaded already, without waiting for it to load.
+pub fn loaded_model(config: &LocalModelConfig) -> Option<Arc<LoadedModel>> {
+    let slot = model_slot(config).ok()?;
+    let slot = slot.try_lock().ok()?;
+
+    slot.clone()
+}
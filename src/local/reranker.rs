@@ -0,0 +1,143 @@
+use crate::local::database::EmbeddingVectorValue;
+use crate::utils::utils::get_device;
+use crate::utils::vars;
+use anyhow::{Context, Error, Result};
+use candle_core::{DType, Module, Tensor, D};
+use candle_nn::{linear, Linear, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use lazy_static::lazy_static;
+use tokenizers::{Tokenizer, TruncationParams, TruncationStrategy};
+
+lazy_static! {
+    /// Loaded on first use, an error here only disables reranking.
+    pub static ref RERANK_MODEL: Result<CrossEncoder> = load_model();
+}
+
+/// BERT sequence classification model scoring (query, passage) pairs,
+/// e.g. `cross-encoder/ms-marco-MiniLM-L-6-v2`.
+pub struct CrossEncoder {
+    model: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+}
+
+pub fn load_model() -> Result<CrossEncoder> {
+    let reranker_model_path = vars::get_reranker_model_path()?;
+
+    let config_path = reranker_model_path.join("config.json");
+    let tokenizer_path = reranker_model_path.join("tokenizer.json");
+    let weights_path = reranker_model_path.join("pytorch_model.bin");
+    let config = std::fs::read_to_string(&config_path)?;
+
+    let config: Config = serde_json::from_str(&config)?;
+
+    let mut tokenizer = Tokenizer::from_file(tokenizer_path).map_err(Error::msg)?;
+
+    tokenizer
+        .with_truncation(Some(TruncationParams {
+            max_length: config.max_position_embeddings,
+            strategy: TruncationStrategy::LongestFirst,
+            ..Default::default()
+        }))
+        .map_err(Error::msg)?;
+
+    tokenizer.with_padding(None);
+
+    let vb = VarBuilder::from_pth(&weights_path, DTYPE, &get_device())?;
+
+    let model = BertModel::load(vb.pp("bert"), &config)?;
+    let pooler = linear(
+        config.hidden_size,
+        config.hidden_size,
+        vb.pp("bert.pooler.dense"),
+    )?;
+    let classifier = linear(config.hidden_size, 1, vb.pp("classifier"))?;
+
+    Ok(CrossEncoder {
+        model,
+        pooler,
+        classifier,
+        tokenizer,
+    })
+}
+
+impl CrossEncoder {
+    /// Relevance of each passage to `query` in `[0, 1]`.
+    ///
+    /// Pairs are scored one at a time, the BERT forward pass here takes no
+    /// attention mask so padded batches would skew the scores.
+    pub fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>> {
+        let mut scores = Vec::with_capacity(passages.len());
+
+        for passage in passages {
+            let tokens = self
+                .tokenizer
+                .encode((query, passage.as_str()), true)
+                .map_err(Error::msg)?;
+
+            let token_ids = Tensor::new(tokens.get_ids(), &get_device())?.unsqueeze(0)?;
+            let token_type_ids = Tensor::new(tokens.get_type_ids(), &get_device())?.unsqueeze(0)?;
+
+            let hidden = self
+                .model
+                .forward(&token_ids, &token_type_ids)
+                .context("Unable to score passage")?;
+
+            let cls = hidden.get_on_dim(1, 0)?;
+            let pooled = self.pooler.forward(&cls)?.tanh()?;
+            let logit = self.classifier.forward(&pooled)?.squeeze(D::Minus1)?;
+
+            let logit: f32 = logit.to_dtype(DType::F32)?.to_vec1()?[0];
+
+            scores.push(1.0 / (1.0 + (-logit).exp()));
+        }
+
+        Ok(scores)
+    }
+}
+
+/// Pairs `items` with `scores` and keeps the `top_n` best, highest first.
+pub fn rank_by_scores<T>(items: Vec<T>, scores: Vec<f32>, top_n: usize) -> Vec<(T, f32)> {
+    let mut ranked: Vec<(T, f32)> = items.into_iter().zip(scores).collect();
+
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked.truncate(top_n);
+
+    ranked
+}
+
+/// Rescores `candidates` against `query` with the cross-encoder and keeps the
+/// `top_n` best, their `score` replaced by the reranker score.
+pub async fn rerank(
+    query: &str,
+    candidates: Vec<EmbeddingVectorValue>,
+    top_n: usize,
+) -> Result<Vec<EmbeddingVectorValue>> {
+    if candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    let query = query.to_string();
+    let passages: Vec<String> = candidates
+        .iter()
+        .map(|candidate| candidate.content_chunk.clone())
+        .collect();
+
+    let scores = tokio::task::spawn_blocking(move || -> Result<Vec<f32>> {
+        let cross_encoder = RERANK_MODEL
+            .as_ref()
+            .map_err(|e| Error::msg(format!("Reranker unavailable: {e}")))?;
+
+        cross_encoder.score(&query, &passages)
+    })
+    .await??;
+
+    Ok(rank_by_scores(candidates, scores, top_n)
+        .into_iter()
+        .map(|(mut candidate, score)| {
+            candidate.score = score as f64;
+            candidate
+        })
+        .collect())
+}
@@ -2,22 +2,33 @@ use anyhow::Result;
 
 pub mod azure;
 pub mod azureai;
+pub mod chat_backend;
+pub mod embedder;
+pub mod generation;
+pub mod openai_compatible;
+pub mod prompt;
+pub mod protocol;
+pub mod retrieval;
 pub mod routes;
 pub mod tests;
 pub mod utils;
 pub mod tools;
 pub mod local;
 
+use std::net::SocketAddr;
 use tracing::Level;
+use utils::config::AppConfig;
 use warp;
 use warp::Filter;
 
-const MAXIMUM_UPLOAD_SIZE: u64 = 100;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt().with_max_level(Level::INFO).init();
 
+    let app_config = AppConfig::init()?;
+
+    let address = SocketAddr::new(app_config.ip_address()?, app_config.web_config.port);
+
     tokio::fs::create_dir_all("./upload_path").await?;
 
     let cors = warp::cors()
@@ -44,26 +55,66 @@ async fn main() -> Result<()> {
         .and(warp::body::json())
         .and_then(routes::delete_table);
 
+    let build_index = warp::path("build_index")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(routes::build_index);
+
+    let search = warp::path("search")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(routes::search);
+
     let search_bases = warp::path("search_bases")
         .and(warp::post())
         .and_then(routes::get_all_search_base);
 
     let handle_upload = warp::path("handle_upload")
         .and(warp::post())
-        .and(warp::multipart::form().max_length(1024 * 1024 * MAXIMUM_UPLOAD_SIZE))
+        .and(warp::multipart::form().max_length(app_config.maximum_upload_bytes()))
         .and_then(routes::handle_upload);
 
+    let get_job = warp::path!("jobs" / String)
+        .and(warp::get())
+        .and_then(routes::get_job);
+
+    let list_jobs = warp::path!("jobs")
+        .and(warp::get())
+        .and(warp::query::<routes::JobsQuery>())
+        .and_then(routes::list_jobs);
+
+    let list_sessions = warp::path!("sessions")
+        .and(warp::get())
+        .and_then(routes::list_sessions);
+
+    let get_session = warp::path!("sessions" / String)
+        .and(warp::get())
+        .and_then(routes::get_session);
+
+    let delete_session = warp::path!("sessions" / String)
+        .and(warp::delete())
+        .and_then(routes::delete_session);
+
     let app_routes = home_route
         .or(app_root
             .and_then(routes::home)
             .or(websocket_route)
             .or(create_table)
             .or(delete_table)
+            .or(build_index)
+            .or(search)
             .or(search_bases)
-            .or(handle_upload))
+            .or(handle_upload)
+            .or(get_job)
+            .or(list_jobs)
+            .or(list_sessions)
+            .or(get_session)
+            .or(delete_session))
         .with(cors);
 
-    warp::serve(app_routes).run(([0, 0, 0, 0], 8000)).await;
+    println!("Listening on {address}");
+
+    warp::serve(app_routes).run(address).await;
 
     Ok(())
 }
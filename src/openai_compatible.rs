@@ -0,0 +1,81 @@
+use crate::azureai::{completion_request, completion_tokens};
+use crate::chat_backend::{ChatBackend, ChatMessage, TokenStream};
+use crate::generation::GenerationParams;
+use crate::utils::constants::DEFAULT_REMOTE_CONTEXT_WINDOW;
+use anyhow::Result;
+use async_openai::{config::OpenAIConfig, Client};
+use futures::stream;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use std::borrow::Cow;
+
+/// Any server speaking the OpenAI `/v1/chat/completions` streaming protocol,
+/// e.g. vLLM, llama.cpp server or a local mock.
+pub struct OpenAICompatible<'a> {
+    base_url: Cow<'a, str>,
+    api_key: Option<String>,
+    inf_model: Cow<'a, str>,
+    max_token: u16,
+    context_window: usize,
+}
+
+impl<'a> OpenAICompatible<'a> {
+    /// `base_url` includes the version prefix, e.g. `http://localhost:8080/v1`.
+    pub fn new(
+        base_url: impl Into<Cow<'a, str>>,
+        api_key: Option<String>,
+        inf_model: impl Into<Cow<'a, str>>,
+        max_token: u16,
+    ) -> Self {
+        Self {
+            base_url: base_url.into(),
+            api_key,
+            inf_model: inf_model.into(),
+            max_token,
+            context_window: DEFAULT_REMOTE_CONTEXT_WINDOW,
+        }
+    }
+
+    pub fn with_context_window(mut self, context_window: usize) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
+    async fn create_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+    ) -> Result<TokenStream<'static>> {
+        let config = OpenAIConfig::new()
+            .with_api_base(self.base_url.trim_end_matches('/'))
+            .with_api_key(self.api_key.clone().unwrap_or_default());
+
+        let client = Client::with_config(config);
+
+        let request = completion_request(&self.inf_model, self.max_token, messages, params)?;
+
+        let stream = client.chat().create_stream(request).await?;
+
+        Ok(completion_tokens(stream))
+    }
+}
+
+impl ChatBackend for OpenAICompatible<'_> {
+    fn stream_chat<'b>(
+        &'b self,
+        messages: &'b [ChatMessage],
+        params: &'b GenerationParams,
+    ) -> TokenStream<'b> {
+        stream::once(self.create_stream(messages, params))
+            .try_flatten()
+            .boxed()
+    }
+
+    fn context_window(&self) -> usize {
+        self.context_window
+    }
+
+    fn max_new_tokens(&self, params: &GenerationParams) -> usize {
+        params.max_tokens.unwrap_or(usize::from(self.max_token))
+    }
+}
@@ -0,0 +1,219 @@
+use crate::chat_backend::ChatMessage;
+use crate::local::inferencing::MessageType;
+use crate::retrieval::SearchHit;
+use crate::utils::constants::{
+    MESSAGE_TOKEN_OVERHEAD, MIN_REFERENCE_TOKENS, REFERENCE_BUDGET_PERCENT,
+};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+/// Token limits of the deployment a prompt is packed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PromptBudget {
+    pub context_window: usize,
+    /// Reserved for the answer, the prompt gets the rest of the window.
+    pub max_new_tokens: usize,
+}
+
+impl PromptBudget {
+    pub fn prompt_tokens(&self) -> Result<usize> {
+        match self.context_window.checked_sub(self.max_new_tokens) {
+            Some(prompt_tokens) if prompt_tokens > 0 => Ok(prompt_tokens),
+            _ => anyhow::bail!(
+                "max_tokens {} leaves no room for the prompt in a {} token context window",
+                self.max_new_tokens,
+                self.context_window
+            ),
+        }
+    }
+}
+
+/// Messages to send, system message first and the new question last.
+#[derive(Debug, Clone)]
+pub struct PackedPrompt {
+    pub messages: Vec<ChatMessage>,
+    /// References included in the user message, best score first.
+    pub references: Vec<SearchHit>,
+    /// Tokens taken by `messages`, as counted when packing.
+    pub prompt_tokens: usize,
+    pub dropped_references: usize,
+    pub truncated_references: usize,
+    pub dropped_history: usize,
+}
+
+impl PackedPrompt {
+    /// The question with its references, as sent to the model.
+    pub fn user_message(&self) -> &str {
+        self.messages
+            .last()
+            .map(|message| message.content.as_str())
+            .unwrap_or_default()
+    }
+}
+
+/// Rough token count for backends without a local tokenizer, about four characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub fn format_user_message(question: &str, references: &[Value]) -> String {
+    let context: String = json!(references).to_string();
+
+    format!("question: \"{question}\"\nreferences: \"{context}\"\n")
+}
+
+fn reference_json(reference: &SearchHit, content: &str) -> Value {
+    json!({
+        "content": content,
+        "metadata": reference.metadata
+    })
+}
+
+/// Longest prefix of `reference` whose JSON takes at most `room` tokens.
+fn truncate_reference(
+    reference: &SearchHit,
+    room: usize,
+    count_tokens: &impl Fn(&str) -> usize,
+) -> Option<Value> {
+    let content = reference.content.as_str();
+
+    let boundaries: Vec<usize> = content
+        .char_indices()
+        .map(|(index, _)| index)
+        .chain(std::iter::once(content.len()))
+        .collect();
+
+    let fits = |end: usize| {
+        let value = reference_json(reference, &content[..end]);
+        (count_tokens(&value.to_string()) < room).then_some(value)
+    };
+
+    let mut best = fits(0)?;
+    let (mut low, mut high) = (1, boundaries.len() - 1);
+
+    while low <= high {
+        let middle = (low + high) / 2;
+
+        match fits(boundaries[middle]) {
+            Some(value) => {
+                best = value;
+                low = middle + 1;
+            }
+            None => high = middle - 1,
+        }
+    }
+
+    Some(best)
+}
+
+/// Fit the system message, `history` and the question with its `references` into `budget`.
+///
+/// References are taken best score first, those that don't fit are skipped so
+/// smaller ones after them can still be kept. The best skipped one is truncated
+/// into what's left when there's enough room, the others are dropped. History
+/// fills what remains, newest first, so the oldest messages are dropped.
+pub fn pack_prompt(
+    system_message: &str,
+    history: Vec<ChatMessage>,
+    question: &str,
+    references: &[SearchHit],
+    budget: &PromptBudget,
+    count_tokens: impl Fn(&str) -> usize,
+) -> Result<PackedPrompt> {
+    let prompt_tokens = budget.prompt_tokens()?;
+
+    let message_tokens = |content: &str| count_tokens(content) + MESSAGE_TOKEN_OVERHEAD;
+
+    let system_tokens = message_tokens(system_message);
+    let question_tokens = message_tokens(&format_user_message(question, &[]));
+
+    if system_tokens + question_tokens > prompt_tokens {
+        anyhow::bail!(
+            "The question doesn't fit in the {} token prompt of this deployment",
+            prompt_tokens
+        );
+    }
+
+    let remaining = prompt_tokens - system_tokens - question_tokens;
+    let reference_budget = remaining * REFERENCE_BUDGET_PERCENT / 100;
+
+    let mut ranked: Vec<&SearchHit> = references.iter().collect();
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut kept: Vec<Value> = Vec::new();
+    let mut kept_references: Vec<&SearchHit> = Vec::new();
+    let mut truncated_references = 0;
+    let mut used = 0;
+    // Best reference that didn't fit whole, with its place among the kept ones.
+    let mut too_long: Option<(usize, &SearchHit)> = None;
+
+    for reference in ranked {
+        let value = reference_json(reference, &reference.content);
+        // One more for the separator in the JSON array.
+        let tokens = count_tokens(&value.to_string()) + 1;
+
+        if used + tokens <= reference_budget {
+            kept.push(value);
+            kept_references.push(reference);
+            used += tokens;
+        } else if too_long.is_none() {
+            too_long = Some((kept.len(), reference));
+        }
+    }
+
+    let room = reference_budget - used;
+
+    if let Some((index, reference)) = too_long.filter(|_| room >= MIN_REFERENCE_TOKENS) {
+        if let Some(value) = truncate_reference(reference, room, &count_tokens) {
+            kept.insert(index, value);
+            kept_references.insert(index, reference);
+            truncated_references += 1;
+        }
+    }
+
+    // Token counts of the pieces don't add up exactly, so check the whole message.
+    let mut user_message = format_user_message(question, &kept);
+
+    while system_tokens + message_tokens(&user_message) > prompt_tokens && !kept.is_empty() {
+        kept.pop();
+        kept_references.pop();
+        user_message = format_user_message(question, &kept);
+    }
+
+    // Without references the message is the bare question, which was checked to fit.
+    let mut history_room = prompt_tokens - system_tokens - message_tokens(&user_message);
+
+    let history_len = history.len();
+    let mut kept_history: Vec<ChatMessage> = Vec::new();
+
+    for message in history.into_iter().rev() {
+        let tokens = message_tokens(&message.content);
+
+        if tokens > history_room {
+            break;
+        }
+
+        history_room -= tokens;
+        kept_history.push(message);
+    }
+
+    kept_history.reverse();
+
+    let dropped_history = history_len - kept_history.len();
+    let dropped_references = references.len() - kept.len();
+    let prompt_tokens = prompt_tokens - history_room;
+
+    let mut messages = vec![ChatMessage::new(MessageType::System, system_message)];
+    messages.extend(kept_history);
+    messages.push(ChatMessage::new(MessageType::User, user_message));
+
+    Ok(PackedPrompt {
+        messages,
+        references: kept_references.into_iter().cloned().collect(),
+        prompt_tokens,
+        dropped_references,
+        truncated_references,
+        dropped_history,
+    })
+}
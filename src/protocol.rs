@@ -0,0 +1,269 @@
+use crate::generation::GenerationParams;
+use crate::retrieval::{RetrievalOptions, SearchHit};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+use warp::ws::Message;
+
+/// Bumped whenever an event changes in a way existing clients can't ignore.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A question to answer from the knowledge base `table_name`.
+#[derive(Debug, Deserialize)]
+pub struct WebSocketMessage {
+    /// Tags the events of this answer, generated when missing.
+    pub request_id: Option<String>,
+    pub table_name: String,
+    pub session_id: String,
+    pub sentence: String,
+    pub deployment_type: String,
+    pub deployment_model: String,
+    #[serde(flatten)]
+    pub retrieval: RetrievalOptions,
+    #[serde(flatten)]
+    pub generation: GenerationParams,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Question(Box<WebSocketMessage>),
+    /// Stops the answer to `request_id`, or every answer in progress.
+    Cancel {
+        request_id: Option<String>,
+    },
+}
+
+impl ClientMessage {
+    /// Messages without a `type` are questions, as sent before cancellation existed.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut value: Value = serde_json::from_str(text)?;
+
+        if let Some(message) = value.as_object_mut() {
+            message.entry("type").or_insert("question".into());
+        }
+
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// A retrieved chunk the answer is based on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Citation {
+    pub source: Option<String>,
+    pub content_id: String,
+    pub chunk_number: i32,
+    /// Pages the chunk spans, for paginated documents like PDFs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_end: Option<u64>,
+    pub score: f64,
+    pub metadata: Value,
+}
+
+impl From<&SearchHit> for Citation {
+    fn from(hit: &SearchHit) -> Self {
+        Self {
+            source: hit.metadata["source"].as_str().map(str::to_owned),
+            content_id: hit.content_id.to_owned(),
+            chunk_number: hit.chunk_number,
+            page_start: hit.metadata["page_start"].as_u64(),
+            page_end: hit.metadata["page_end"].as_u64(),
+            score: hit.score,
+            metadata: hit.metadata.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub duration_ms: u128,
+}
+
+/// Websocket frame sent to the client, every answer ends with `done`,
+/// `cancelled` or `error`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// The chunks sent to the model, before the first token.
+    References {
+        references: Vec<Citation>,
+    },
+    Token {
+        content: String,
+    },
+    Done {
+        usage: Usage,
+    },
+    /// The answer stopped early, it isn't stored in the session history.
+    Cancelled,
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Frame<'a> {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    #[serde(flatten)]
+    event: &'a ServerEvent,
+}
+
+impl ServerEvent {
+    pub fn error(message: impl ToString) -> Self {
+        Self::Error {
+            message: message.to_string(),
+        }
+    }
+
+    /// `{"version": 1, "request_id": "...", "type": "...", ...}`
+    pub fn to_json(&self, request_id: Option<&str>) -> String {
+        // Every field serialises to JSON, this can't fail.
+        serde_json::to_string(&Frame {
+            version: PROTOCOL_VERSION,
+            request_id,
+            event: self,
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// Sends events to one websocket, tagged with the request they belong to.
+///
+/// Answers run concurrently, a single task writes their frames to the socket.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    tx: mpsc::UnboundedSender<Message>,
+    request_id: Option<String>,
+}
+
+impl EventSender {
+    pub fn new(tx: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            tx,
+            request_id: None,
+        }
+    }
+
+    pub fn for_request(&self, request_id: &str) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            request_id: Some(request_id.to_owned()),
+        }
+    }
+
+    /// Fails once the connection is closed.
+    pub fn send(&self, event: ServerEvent) -> Result<()> {
+        self.tx
+            .send(Message::text(event.to_json(self.request_id.as_deref())))
+            .map_err(|_| anyhow::anyhow!("The websocket connection is closed"))
+    }
+}
+
+/// Answers in progress on one connection, by request id.
+///
+/// A cancelled answer keeps its slot until it has stopped.
+#[derive(Debug)]
+pub struct InFlightAnswers {
+    answers: HashMap<String, Option<oneshot::Sender<()>>>,
+    limit: usize,
+}
+
+impl InFlightAnswers {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            answers: HashMap::new(),
+            limit,
+        }
+    }
+
+    pub fn new_request_id() -> String {
+        Uuid::new_v4().to_string().replace("-", "")
+    }
+
+    /// The receiver resolves when the answer is cancelled or the connection closes.
+    pub fn start(&mut self, request_id: &str) -> Result<oneshot::Receiver<()>> {
+        if self.answers.contains_key(request_id) {
+            anyhow::bail!("Request {request_id} is already being answered");
+        }
+
+        if self.answers.len() >= self.limit {
+            anyhow::bail!(
+                "At most {} questions can be answered at once, wait for one or cancel it",
+                self.limit
+            );
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.answers.insert(request_id.to_owned(), Some(tx));
+
+        Ok(rx)
+    }
+
+    /// Cancel `request_id`, or every answer without one. Returns how many were cancelled.
+    pub fn cancel(&mut self, request_id: Option<&str>) -> usize {
+        let cancelled: Vec<oneshot::Sender<()>> = match request_id {
+            Some(request_id) => self
+                .answers
+                .get_mut(request_id)
+                .and_then(Option::take)
+                .into_iter()
+                .collect(),
+            None => self.answers.values_mut().filter_map(Option::take).collect(),
+        };
+
+        let count = cancelled.len();
+
+        for cancel in cancelled {
+            let _ = cancel.send(());
+        }
+
+        count
+    }
+
+    pub fn finish(&mut self, request_id: &str) {
+        self.answers.remove(request_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.answers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty()
+    }
+}
+
+/// Finishes its answer when dropped, so the slot is freed however the answer ends.
+pub struct AnswerSlot {
+    answers: Arc<Mutex<InFlightAnswers>>,
+    request_id: String,
+}
+
+impl AnswerSlot {
+    pub fn new(answers: Arc<Mutex<InFlightAnswers>>, request_id: String) -> Self {
+        Self {
+            answers,
+            request_id,
+        }
+    }
+}
+
+impl Drop for AnswerSlot {
+    fn drop(&mut self) {
+        self.answers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .finish(&self.request_id);
+    }
+}
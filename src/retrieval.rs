@@ -0,0 +1,128 @@
+use crate::embedder::{get_embedder_by_model_id, Embedder};
+use crate::local::database::{self, EmbeddingVectorValue};
+use crate::local::reranker;
+use crate::utils::constants::{MAX_RERANK_CANDIDATES, RERANK_CANDIDATE_FACTOR};
+use crate::utils::knowledge_base::{KnowledgeBaseName, SearchMode, SearchTuning};
+use crate::utils::metadata_filter::MetadataFilter;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How chunks are retrieved for a query, shared by the websocket and `/search`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetrievalOptions {
+    pub max_similar_search: usize,
+    #[serde(default)]
+    pub upper_chunk: i32,
+    #[serde(default)]
+    pub lower_chunk: i32,
+    #[serde(default)]
+    pub minimum_score: f32,
+    #[serde(default)]
+    pub search_mode: SearchMode,
+    #[serde(default)]
+    pub rerank: bool,
+    pub rerank_candidates: Option<usize>,
+    pub ef_search: Option<u32>,
+    pub probes: Option<u32>,
+    #[serde(default)]
+    pub filter: MetadataFilter,
+}
+
+impl RetrievalOptions {
+    /// Number of candidates to fetch for the reranker, `None` when reranking is off.
+    /// Never fewer than `max_similar_search`, even past `MAX_RERANK_CANDIDATES`.
+    pub fn rerank_candidates(&self) -> Option<usize> {
+        let default = self
+            .max_similar_search
+            .saturating_mul(RERANK_CANDIDATE_FACTOR);
+
+        self.rerank.then(|| {
+            self.rerank_candidates
+                .unwrap_or(default)
+                .min(MAX_RERANK_CANDIDATES)
+                .max(self.max_similar_search)
+        })
+    }
+
+    pub fn tuning(&self) -> SearchTuning {
+        SearchTuning {
+            ef_search: self.ef_search,
+            probes: self.probes,
+        }
+    }
+}
+
+/// A retrieved chunk with its adjacent chunks joined into `content`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub content: String,
+    pub score: f64,
+    pub content_id: String,
+    pub chunk_number: i32,
+    pub metadata: Value,
+}
+
+/// Embeds `query` with the knowledge base's model when the search mode needs it,
+/// searches, optionally reranks and expands every hit with its adjacent chunks.
+pub async fn retrieve(
+    table_name: &KnowledgeBaseName,
+    query: &str,
+    options: &RetrievalOptions,
+) -> Result<Vec<SearchHit>> {
+    let settings = database::get_knowledge_base_settings(table_name).await?;
+
+    let embedder: Box<dyn Embedder> = get_embedder_by_model_id(&settings.embedding_model)?;
+
+    let embeddings: Option<Vec<f32>> = if options.search_mode.uses_embedding() {
+        Some(embedder.embed(query).await?)
+    } else {
+        None
+    };
+
+    let rerank_candidates = options.rerank_candidates();
+
+    let references = database::get_similar_results(
+        table_name,
+        options.search_mode,
+        query,
+        &embedder.model_id(),
+        embeddings.map(Into::into),
+        rerank_candidates.unwrap_or(options.max_similar_search),
+        options.minimum_score,
+        options.tuning(),
+        &options.filter,
+    )
+    .await?;
+
+    let references: Vec<EmbeddingVectorValue> = match rerank_candidates {
+        Some(_) => reranker::rerank(query, references, options.max_similar_search).await?,
+        None => references,
+    };
+
+    let mut hits: Vec<SearchHit> = Vec::with_capacity(references.len());
+
+    for reference in references {
+        let related = reference
+            .get_adjacent_chunks(table_name, options.upper_chunk, options.lower_chunk)
+            .await?;
+
+        let mut chunks: String = String::new();
+
+        for r in related.iter() {
+            chunks += &r.content_chunk;
+            chunks.push(' ');
+        }
+
+        hits.push(SearchHit {
+            content: chunks,
+            score: reference.score,
+            content_id: reference.content_id,
+            chunk_number: reference.chunk_number,
+            metadata: reference.metadata,
+        });
+    }
+
+    Ok(hits)
+}
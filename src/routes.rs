@@ -1,8 +1,26 @@
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
+use crate::embedder::{default_embedder, get_embedder, Embedder};
+use crate::local::chat_history;
 use crate::local::database;
 use crate::local::inferencing;
-use crate::utils::upload::{learn_from_pdf, learn_from_text};
+use crate::local::jobs;
+use crate::protocol::{
+    AnswerSlot, ClientMessage, EventSender, InFlightAnswers, ServerEvent, WebSocketMessage,
+};
+use crate::retrieval::{self, RetrievalOptions};
+use crate::utils::archive::{detect_upload, UploadFormat};
+use crate::utils::config::AppConfig;
+use crate::utils::constants::{
+    DEFAULT_CHUNK_OVERLAP, DEFAULT_CHUNK_SIZE, MAX_ANSWERS_PER_CONNECTION,
+};
+use crate::utils::extractors::read_head;
+use crate::utils::knowledge_base::{
+    DistanceMetric, KnowledgeBaseName, KnowledgeBaseSettings, VectorIndex,
+};
+use crate::utils::records::RecordTemplate;
+use crate::utils::upload::{learn_from_archive, learn_from_file};
 
 use futures::SinkExt;
 use futures::StreamExt;
@@ -10,23 +28,11 @@ use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task;
 use warp::http::Response;
 use warp::Buf;
 
-#[derive(Debug, Deserialize)]
-struct WebSocketMessage {
-    table_name: String,
-    session_id: String,
-    sentence: String,
-    deployment_type: String,
-    deployment_model: String,
-    max_similar_search: usize,
-    upper_chunk: i32,
-    lower_chunk: i32,
-    minimum_score: f32,
-}
-
 pub async fn home() -> Result<impl warp::Reply, warp::Rejection> {
     Ok(warp::reply::with_status(
         "Nothing Here",
@@ -44,13 +50,16 @@ pub async fn handle_upload(
     form: warp::multipart::FormData,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let mut parts = form.into_stream();
-    let mut file_paths: Vec<String> = Vec::new();
+    let mut file_paths: Vec<(String, Option<String>)> = Vec::new();
     let mut table_name: Option<String> = None;
+    let mut text_fields: Option<String> = None;
+    let mut text_template: Option<String> = None;
 
     while let Ok(Some(part)) = parts.try_next().await {
         if part.name() == "files" {
             let filename: &str = part.filename().unwrap_or("unknown");
             let filepath = format!("upload_path/{filename}");
+            let content_type = part.content_type().map(str::to_owned);
 
             let mut file = File::create(&filepath)
                 .await
@@ -63,7 +72,7 @@ pub async fn handle_upload(
                     .await
                     .expect("Unable to write to file");
             }
-            file_paths.push(filepath);
+            file_paths.push((filepath, content_type));
         } else {
             let name = part.name().to_string();
             let value = part
@@ -74,9 +83,14 @@ pub async fn handle_upload(
                 })
                 .await;
 
-            if name == String::from("table_name") {
-                if let Ok(value) = value {
-                    table_name = Some(String::from_utf8_lossy(&value).to_string())
+            if let Ok(value) = value {
+                let value = String::from_utf8_lossy(&value).to_string();
+
+                match name.as_str() {
+                    "table_name" => table_name = Some(value),
+                    "text_fields" => text_fields = Some(value),
+                    "text_template" => text_template = Some(value),
+                    _ => {}
                 }
             }
         }
@@ -84,62 +98,310 @@ pub async fn handle_upload(
 
     println!("table_name: {:?}\nFile_names: {:?}", table_name, file_paths);
 
-    if let Some(table_name) = table_name {
-        for file_name in file_paths {
-            if file_name.ends_with("txt") {
-                let table_name_clone = table_name.clone();
-                task::spawn(async move {
-                    let _ = learn_from_text(&table_name_clone, &PathBuf::from(file_name)).await;
-                });
-            } else if file_name.ends_with("pdf") {
-                let table_name_clone = table_name.clone();
-                task::spawn(async move {
-                    let _ = learn_from_pdf(&table_name_clone, &PathBuf::from(file_name)).await;
-                });
-            } else {
-                println!("File type not supported: {file_name}");
+    let table_name = match table_name.as_deref().map(KnowledgeBaseName::parse) {
+        Some(Ok(table_name)) => Some(table_name),
+        Some(Err(e)) => {
+            return Ok(warp::reply::with_status(
+                e.to_string(),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+        None => None,
+    };
+
+    let Some(table_name) = table_name else {
+        return Ok(warp::reply::with_status(
+            "No Knowledge Base Selected".to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let template = match RecordTemplate::parse(text_fields.as_deref(), text_template.as_deref()) {
+        Ok(template) => template,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                format!("{e:#}"),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    let mut jobs: Vec<serde_json::Value> = Vec::new();
+
+    for (file_name, content_type) in file_paths {
+        let source = file_name
+            .rsplit('/')
+            .next()
+            .unwrap_or(&file_name)
+            .to_string();
+
+        let head = read_head(&file_name).await.unwrap_or_default();
+        let format = detect_upload(&file_name, content_type.as_deref(), &head);
+        let supported = format.is_some();
+
+        if !supported {
+            println!("File type not supported: {file_name}");
+        }
+
+        let job_id = match jobs::create_job(
+            &table_name,
+            &source,
+            (!supported).then_some("File type not supported"),
+        )
+        .await
+        {
+            Ok(job_id) => job_id,
+            Err(e) => {
+                return Ok(warp::reply::with_status(
+                    format!("Failed to create ingestion job: {e}"),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
+        };
+
+        jobs.push(serde_json::json!({
+            "job_id": job_id,
+            "file_name": source,
+        }));
+
+        let table_name_clone = table_name.clone();
+        let file_path = PathBuf::from(file_name);
+        let template = template.clone();
+
+        match format {
+            Some(UploadFormat::Document(extractor)) => {
+                task::spawn(jobs::run_job(job_id, async move {
+                    learn_from_file(&table_name_clone, &file_path, extractor, template).await
+                }));
+            }
+            Some(UploadFormat::Archive(kind)) => {
+                let limits = AppConfig::global().archive_limits();
+                task::spawn(jobs::run_job(job_id, async move {
+                    learn_from_archive(&table_name_clone, &file_path, kind, limits, template).await
+                }));
             }
+            None => {}
         }
     }
 
     Ok(warp::reply::with_status(
-        "File is Processing",
-        warp::http::StatusCode::OK,
+        serde_json::json!({ "jobs": jobs }).to_string(),
+        warp::http::StatusCode::ACCEPTED,
     ))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct JobsQuery {
+    table: Option<String>,
+}
+
+pub async fn get_job(job_id: String) -> Result<impl warp::Reply, warp::Rejection> {
+    match jobs::get_job(&job_id).await {
+        Ok(Some(job)) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&job).unwrap())
+            .unwrap()),
+        Ok(None) => Ok(Response::builder()
+            .status(404)
+            .body(format!("Job not found: {job_id}"))
+            .unwrap()),
+        Err(_) => Ok(Response::builder()
+            .status(500)
+            .body("Failed to retrieve job".into())
+            .unwrap()),
+    }
+}
+
+pub async fn list_jobs(query: JobsQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    let table_name = match query.table.as_deref().map(KnowledgeBaseName::parse) {
+        Some(Ok(table_name)) => Some(table_name),
+        Some(Err(e)) => {
+            return Ok(Response::builder()
+                .status(400)
+                .body(e.to_string())
+                .unwrap())
+        }
+        None => None,
+    };
+
+    match jobs::list_jobs(table_name.as_ref()).await {
+        Ok(jobs) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&jobs).unwrap())
+            .unwrap()),
+        Err(_) => Ok(Response::builder()
+            .status(500)
+            .body("Failed to retrieve jobs".into())
+            .unwrap()),
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TableCreate {
     table_name: String,
+    embedding_model: Option<String>,
+    distance_metric: Option<DistanceMetric>,
+    chunk_size: Option<usize>,
+    chunk_overlap: Option<usize>,
+    vector_index: Option<VectorIndex>,
+}
+
+impl TableCreate {
+    fn settings(&self) -> anyhow::Result<KnowledgeBaseSettings> {
+        let embedder: Box<dyn Embedder> = match &self.embedding_model {
+            Some(embedding_model) => get_embedder(embedding_model)?,
+            None => default_embedder()?,
+        };
+
+        Ok(KnowledgeBaseSettings {
+            embedding_model: embedder.model_id(),
+            dimension: embedder.dimension(),
+            distance_metric: self.distance_metric.unwrap_or(DistanceMetric::Cosine),
+            chunk_size: self.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE),
+            chunk_overlap: self.chunk_overlap.unwrap_or(DEFAULT_CHUNK_OVERLAP),
+        })
+    }
 }
 
 pub async fn create_new_table(
     table_create: TableCreate,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    if database::create_table(&table_create.table_name)
-        .await
-        .is_ok()
+    let table_name = match KnowledgeBaseName::parse_new(&table_create.table_name) {
+        Ok(table_name) => table_name,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(400)
+                .body(e.to_string())
+                .unwrap())
+        }
+    };
+
+    let settings = match table_create
+        .settings()
+        .and_then(|settings| settings.validate().map(|_| settings))
     {
-        Ok(Response::builder()
+        Ok(settings) => settings,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(400)
+                .body(e.to_string())
+                .unwrap())
+        }
+    };
+
+    let vector_index = table_create.vector_index.as_ref();
+
+    if let Some(Err(e)) = vector_index.map(|index| index.validate(settings.dimension)) {
+        return Ok(Response::builder()
+            .status(400)
+            .body(e.to_string())
+            .unwrap());
+    }
+
+    // Creating a knowledge base again is fine, as long as nothing would change.
+    match database::find_knowledge_base_settings(&table_name).await {
+        Ok(Some(existing)) if existing != settings => {
+            return Ok(Response::builder()
+                .status(409)
+                .body(format!(
+                    "Knowledge base already exists with other settings: {table_name}"
+                ))
+                .unwrap())
+        }
+        Ok(_) => {}
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(502)
+                .body(format!("Failed to create table: {e}"))
+                .unwrap())
+        }
+    }
+
+    match database::create_table(&table_name, &settings, vector_index).await {
+        Ok(_) => Ok(Response::builder()
             .status(200)
             .body::<String>("Table created successfully".into())
-            .unwrap())
-    } else {
-        Ok(Response::builder()
+            .unwrap()),
+        Err(e) => Ok(Response::builder()
             .status(502)
-            .body("Failed to create table".into())
-            .unwrap())
+            .body(format!("Failed to create table: {e}"))
+            .unwrap()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IndexBuild {
+    table_name: String,
+    vector_index: VectorIndex,
+}
+
+pub async fn build_index(index_build: IndexBuild) -> Result<impl warp::Reply, warp::Rejection> {
+    let table_name = match KnowledgeBaseName::parse(&index_build.table_name) {
+        Ok(table_name) => table_name,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(400)
+                .body(e.to_string())
+                .unwrap())
+        }
+    };
+
+    let settings = match database::find_knowledge_base_settings(&table_name).await {
+        Ok(Some(settings)) => settings,
+        Ok(None) => {
+            return Ok(Response::builder()
+                .status(404)
+                .body(format!("Knowledge base not found: {table_name}"))
+                .unwrap())
+        }
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(502)
+                .body(format!("Failed to build index: {e}"))
+                .unwrap())
+        }
+    };
+
+    if let Err(e) = index_build.vector_index.validate(settings.dimension) {
+        return Ok(Response::builder()
+            .status(400)
+            .body(e.to_string())
+            .unwrap());
+    }
+
+    match database::build_vector_index(&table_name, &settings, &index_build.vector_index).await {
+        Ok(_) => Ok(Response::builder()
+            .status(200)
+            .body(format!(
+                "Index built: {} ({})",
+                &table_name,
+                index_build.vector_index.method()
+            ))
+            .unwrap()),
+        Err(e) => Ok(Response::builder()
+            .status(502)
+            .body(format!("Failed to build index: {e}"))
+            .unwrap()),
     }
 }
 
 pub async fn delete_table(table_create: TableCreate) -> Result<impl warp::Reply, warp::Rejection> {
-    if database::delete_table(&table_create.table_name)
-        .await
-        .is_ok()
-    {
+    let table_name = match KnowledgeBaseName::parse(&table_create.table_name) {
+        Ok(table_name) => table_name,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(400)
+                .body(e.to_string())
+                .unwrap())
+        }
+    };
+
+    if database::delete_table(&table_name).await.is_ok() {
         Ok(Response::builder()
             .status(200)
-            .body::<String>(format!("Table Deleted: {}", &table_create.table_name))
+            .body::<String>(format!("Table Deleted: {}", &table_name))
             .unwrap())
     } else {
         Ok(Response::builder()
@@ -165,60 +427,241 @@ pub async fn get_all_search_base() -> Result<impl warp::Reply, warp::Rejection>
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+    table_name: String,
+    query: String,
+    #[serde(flatten)]
+    retrieval: RetrievalOptions,
+}
+
+pub async fn search(search_request: SearchRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    let table_name = match KnowledgeBaseName::parse(&search_request.table_name) {
+        Ok(table_name) => table_name,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(400)
+                .body(e.to_string())
+                .unwrap())
+        }
+    };
+
+    let query = search_request.query.trim();
+
+    if query.is_empty() {
+        return Ok(Response::builder()
+            .status(400)
+            .body("Query can't be empty".into())
+            .unwrap());
+    }
+
+    match retrieval::retrieve(&table_name, query, &search_request.retrieval).await {
+        Ok(hits) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(serde_json::json!({ "hits": hits }).to_string())
+            .unwrap()),
+        Err(e) => Ok(Response::builder()
+            .status(500)
+            .body(format!("Search failed: {e}"))
+            .unwrap()),
+    }
+}
+
+pub async fn list_sessions() -> Result<impl warp::Reply, warp::Rejection> {
+    match chat_history::list_sessions().await {
+        Ok(sessions) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&sessions).unwrap())
+            .unwrap()),
+        Err(_) => Ok(Response::builder()
+            .status(500)
+            .body("Failed to retrieve sessions".into())
+            .unwrap()),
+    }
+}
+
+pub async fn get_session(session_id: String) -> Result<impl warp::Reply, warp::Rejection> {
+    let session = match chat_history::get_session(&session_id).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            return Ok(Response::builder()
+                .status(404)
+                .body(format!("Session not found: {session_id}"))
+                .unwrap())
+        }
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(500)
+                .body("Failed to retrieve session".into())
+                .unwrap())
+        }
+    };
+
+    match chat_history::get_messages(&session_id).await {
+        Ok(messages) => Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(
+                serde_json::json!({
+                    "session": session,
+                    "messages": messages,
+                })
+                .to_string(),
+            )
+            .unwrap()),
+        Err(_) => Ok(Response::builder()
+            .status(500)
+            .body("Failed to retrieve session".into())
+            .unwrap()),
+    }
+}
+
+pub async fn delete_session(session_id: String) -> Result<impl warp::Reply, warp::Rejection> {
+    match chat_history::delete_session(&session_id).await {
+        Ok(true) => Ok(Response::builder()
+            .status(200)
+            .body(format!("Session Deleted: {session_id}"))
+            .unwrap()),
+        Ok(false) => Ok(Response::builder()
+            .status(404)
+            .body(format!("Session not found: {session_id}"))
+            .unwrap()),
+        Err(_) => Ok(Response::builder()
+            .status(500)
+            .body("Failed to delete session".into())
+            .unwrap()),
+    }
+}
+
 pub async fn ws_handler(ws: warp::ws::Ws) -> Result<impl warp::Reply, warp::Rejection> {
     Ok(ws.on_upgrade(move |socket| client_connection(socket)))
 }
 
 pub async fn client_connection(ws: warp::ws::WebSocket) {
-    let (mut tx, mut rx) = ws.split();
+    let (mut ws_tx, mut rx) = ws.split();
 
-    let mut query_model = inferencing::ModelQuery::new();
+    // Answers run concurrently, their events are written to the socket here.
+    let (tx, mut outgoing) = mpsc::unbounded_channel::<warp::ws::Message>();
+
+    task::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            if ws_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let events = EventSender::new(tx);
+    let in_flight = Arc::new(Mutex::new(InFlightAnswers::new(MAX_ANSWERS_PER_CONNECTION)));
 
     while let Some(result) = rx.next().await {
-        match result {
-            Ok(msg) => {
-                if let Ok(str_msg) = msg.to_str() {
-                    if let Ok(socket_message) = serde_json::from_str::<WebSocketMessage>(str_msg) {
-                        if socket_message.table_name.is_empty() {
-                            let _ = tx
-                                .send(warp::ws::Message::text("No Knowledge Base Selected"))
-                                .await;
-                            continue;
-                        }
-
-                        if let Ok(embed_value) = query_model
-                            .get_embeddings(
-                                &socket_message.table_name,
-                                &socket_message.sentence.trim(),
-                                socket_message.max_similar_search,
-                                socket_message.lower_chunk,
-                                socket_message.upper_chunk,
-                                socket_message.minimum_score,
-                            )
-                            .await
-                        {
-                            let _ = query_model
-                                .answer_with_context(
-                                    &mut tx,
-                                    &socket_message.sentence.trim(),
-                                    &socket_message.session_id.trim(),
-                                    &socket_message.deployment_type,
-                                    &socket_message.deployment_model,
-                                    embed_value,
-                                )
-                                .await;
-                        }
-                    } else {
-                        println!("{:?}", serde_json::from_str::<WebSocketMessage>(str_msg));
-                        let _ = tx
-                            .send(warp::ws::Message::text("Cannot Parse the Input"))
-                            .await;
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(_e) => break,
+        };
+
+        let Ok(str_msg) = msg.to_str() else {
+            continue;
+        };
+
+        match ClientMessage::parse(str_msg) {
+            Ok(ClientMessage::Question(question)) => {
+                let request_id = question
+                    .request_id
+                    .clone()
+                    .unwrap_or_else(InFlightAnswers::new_request_id);
+                let events = events.for_request(&request_id);
+
+                if question.table_name.is_empty() {
+                    let _ = events.send(ServerEvent::error("No Knowledge Base Selected"));
+                    continue;
+                }
+
+                let table_name = match KnowledgeBaseName::parse(&question.table_name) {
+                    Ok(table_name) => table_name,
+                    Err(e) => {
+                        let _ = events.send(ServerEvent::error(e));
+                        continue;
+                    }
+                };
+
+                if let Err(e) = question.generation.validate() {
+                    let _ = events.send(ServerEvent::error(e));
+                    continue;
+                }
+
+                let started = in_flight.lock().unwrap().start(&request_id);
+
+                let cancelled = match started {
+                    Ok(cancelled) => cancelled,
+                    Err(e) => {
+                        let _ = events.send(ServerEvent::error(e));
+                        continue;
+                    }
+                };
+
+                let slot = AnswerSlot::new(in_flight.clone(), request_id);
+
+                task::spawn(async move {
+                    let _slot = slot;
+
+                    if let Err(e) = answer_question(&events, *question, table_name, cancelled).await
+                    {
+                        let _ = events.send(ServerEvent::error(e));
                     }
+                });
+            }
+            Ok(ClientMessage::Cancel { request_id }) => {
+                let cancelled = in_flight.lock().unwrap().cancel(request_id.as_deref());
+
+                if cancelled == 0 {
+                    let events = match &request_id {
+                        Some(request_id) => events.for_request(request_id),
+                        None => events.clone(),
+                    };
+
+                    let _ = events.send(ServerEvent::error("No answer in progress to cancel"));
                 }
             }
-            Err(_e) => {
-                break;
+            Err(e) => {
+                println!("{:?}", e);
+                let _ = events.send(ServerEvent::error("Cannot Parse the Input"));
             }
         }
     }
+
+    // The client went away, stop generating for it.
+    in_flight.lock().unwrap().cancel(None);
+}
+
+/// Retrieve the references for `question` and stream the answer, until `cancelled`.
+async fn answer_question(
+    events: &EventSender,
+    question: WebSocketMessage,
+    table_name: KnowledgeBaseName,
+    mut cancelled: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    let references = tokio::select! {
+        references = retrieval::retrieve(
+            &table_name,
+            question.sentence.trim(),
+            &question.retrieval,
+        ) => references?,
+        _ = &mut cancelled => return events.send(ServerEvent::Cancelled),
+    };
+
+    inferencing::ModelQuery::new()
+        .answer_with_context(
+            events,
+            question.sentence.trim(),
+            question.session_id.trim(),
+            &question.deployment_type,
+            &question.deployment_model,
+            references,
+            &question.generation,
+            &mut cancelled,
+        )
+        .await
 }
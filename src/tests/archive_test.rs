@@ -0,0 +1,217 @@
+#[cfg(test)]
+mod archive_test {
+    use crate::utils::archive::{
+        detect_upload, expand_archive, ArchiveEntry, ArchiveKind, ArchiveLimits, ArchiveMember,
+        UploadFormat,
+    };
+    use anyhow::Result;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    const LIMITS: ArchiveLimits = ArchiveLimits {
+        max_entries: 10,
+        max_bytes: 1024,
+        max_depth: 2,
+    };
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (path, bytes) in files {
+            zip.start_file(*path, SimpleFileOptions::default()).unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn tar_gz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+        for (path, bytes) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(bytes.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            tar.append_data(&mut header, path, *bytes).unwrap();
+        }
+
+        tar.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn expand(kind: ArchiveKind, bytes: &[u8]) -> Result<Vec<ArchiveEntry>> {
+        let mut entries = Vec::new();
+
+        expand_archive(kind, bytes, LIMITS, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+
+        Ok(entries)
+    }
+
+    fn members(kind: ArchiveKind, bytes: &[u8]) -> Vec<ArchiveMember> {
+        expand(kind, bytes)
+            .unwrap()
+            .into_iter()
+            .map(|entry| match entry {
+                ArchiveEntry::File(member) => member,
+                ArchiveEntry::Unreadable { path, error } => panic!("{path}: {error}"),
+            })
+            .collect()
+    }
+
+    fn paths(kind: ArchiveKind, bytes: &[u8]) -> Vec<String> {
+        members(kind, bytes)
+            .into_iter()
+            .map(|member| member.path)
+            .collect()
+    }
+
+    #[test]
+    fn test_zip() {
+        let bytes = zip(&[
+            ("docs/guide.md", b"# Guide"),
+            ("docs/.hidden.md", b"secret"),
+            ("__MACOSX/docs/._guide.md", b"junk"),
+            ("../escape.md", b"outside"),
+            ("notes.txt", b"notes"),
+        ]);
+
+        assert_eq!(
+            paths(ArchiveKind::Zip, &bytes),
+            vec!["docs/guide.md", "notes.txt"]
+        );
+
+        let members = members(ArchiveKind::Zip, &bytes);
+        assert_eq!(members[0].bytes, b"# Guide");
+    }
+
+    #[test]
+    fn test_tar_gz() {
+        let bytes = tar_gz(&[("./faq.csv", b"question,answer\n"), ("src/lib.rs", b"")]);
+
+        assert_eq!(
+            paths(ArchiveKind::TarGz, &bytes),
+            vec!["faq.csv", "src/lib.rs"]
+        );
+    }
+
+    #[test]
+    fn test_nested_archives() {
+        let inner = tar_gz(&[("b.md", b"# B")]);
+        let outer = zip(&[("a.md", b"# A"), ("inner.tgz", &inner)]);
+
+        assert_eq!(
+            paths(ArchiveKind::Zip, &outer),
+            vec!["a.md", "inner.tgz/b.md"]
+        );
+
+        let nested = zip(&[("outer.zip", &outer)]);
+        let error = expand(ArchiveKind::Zip, &nested).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Archives are nested more than 2 levels deep at outer.zip/inner.tgz"
+        );
+    }
+
+    #[test]
+    fn test_unreadable_nested_archives() {
+        let mut truncated = zip(&[("b.md", b"# B")]);
+        truncated.truncate(truncated.len() - 10);
+
+        let outer = zip(&[
+            ("broken.zip", &truncated),
+            ("a.md", b"# A"),
+            ("notes.tar.gz", b"not gzip"),
+            ("c.md", b"# C"),
+        ]);
+
+        let entries: Vec<String> = expand(ArchiveKind::Zip, &outer)
+            .unwrap()
+            .into_iter()
+            .map(|entry| match entry {
+                ArchiveEntry::File(member) => member.path,
+                ArchiveEntry::Unreadable { path, .. } => format!("unreadable {path}"),
+            })
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                "unreadable broken.zip",
+                "a.md",
+                "unreadable notes.tar.gz",
+                "c.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let files: Vec<(String, &[u8])> = (0..11)
+            .map(|index| (format!("{index}.txt"), b"text".as_slice()))
+            .collect();
+        let files: Vec<(&str, &[u8])> = files
+            .iter()
+            .map(|(path, bytes)| (path.as_str(), *bytes))
+            .collect();
+
+        let error = expand(ArchiveKind::Zip, &zip(&files)).unwrap_err();
+        assert_eq!(error.to_string(), "Archive has more than 10 files");
+
+        // Highly compressible, small on disk.
+        let large = vec![b'a'; 1025];
+        let bytes = tar_gz(&[("large.txt", &large)]);
+
+        assert!(bytes.len() < 1024);
+        let error = expand(ArchiveKind::TarGz, &bytes).unwrap_err();
+        assert_eq!(error.to_string(), "Archive expands to more than 1024 bytes");
+
+        // Nested archives count towards the same limits, which fail the upload.
+        let inner = zip(&[("a.txt", &[b'a'; 600]), ("b.txt", &[b'b'; 600])]);
+        let outer = zip(&[("inner.zip", &inner)]);
+        let error = expand(ArchiveKind::Zip, &outer).unwrap_err();
+        assert_eq!(error.to_string(), "Archive expands to more than 1024 bytes");
+    }
+
+    fn detect(file_name: &str, head: &[u8]) -> Option<String> {
+        detect_upload(file_name, None, head).map(|format| match format {
+            UploadFormat::Document(extractor) => extractor.name().to_owned(),
+            UploadFormat::Archive(kind) => kind.name().to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_detect_upload() {
+        assert_eq!(
+            detect("bundle.ZIP", b"PK\x03\x04word/"),
+            Some("zip".to_owned())
+        );
+        assert_eq!(detect("docs.tar.gz", b""), Some("tar.gz".to_owned()));
+        assert_eq!(detect("docs.tgz", b""), Some("tar.gz".to_owned()));
+        assert_eq!(detect("docs.tar", b""), Some("tar".to_owned()));
+
+        // ZIP based documents aren't archives.
+        assert_eq!(
+            detect("report", b"PK\x03\x04...word/document.xml"),
+            Some("docx".to_owned())
+        );
+        assert_eq!(
+            detect("upload", b"PK\x03\x04...data.csv"),
+            Some("zip".to_owned())
+        );
+        assert_eq!(detect("upload", b"\x1f\x8b\x08"), Some("tar.gz".to_owned()));
+
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(detect("upload", &tar), Some("tar".to_owned()));
+
+        assert_eq!(detect("upload", b"plain"), None);
+    }
+}
@@ -0,0 +1,127 @@
+#[cfg(test)]
+mod chat_template_test {
+    use crate::chat_backend::ChatMessage;
+    use crate::local::chat_template::{render, JinjaTemplate, TokenizerConfig};
+    use crate::local::inferencing::MessageType;
+    use crate::utils::local_models::{ChatTemplate, LocalArchitecture};
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::new(MessageType::System, "Be brief."),
+            ChatMessage::new(MessageType::User, "Hi"),
+            ChatMessage::new(MessageType::Assistant, "Hello!"),
+            ChatMessage::new(MessageType::User, "Bye"),
+        ]
+    }
+
+    #[test]
+    fn test_chatml() {
+        assert_eq!(
+            render(ChatTemplate::ChatMl, &conversation()),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\nHello!<|im_end|>\n<|im_start|>user\nBye<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_llama3() {
+        assert_eq!(
+            render(ChatTemplate::Llama3, &conversation()),
+            "<|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|><|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\nHello!<|eot_id|><|start_header_id|>user<|end_header_id|>\n\nBye<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_phi3() {
+        assert_eq!(
+            render(ChatTemplate::Phi3, &conversation()),
+            "<|system|>\nBe brief.<|end|>\n<|user|>\nHi<|end|>\n<|assistant|>\nHello!<|end|>\n<|user|>\nBye<|end|>\n<|assistant|>\n"
+        );
+    }
+
+    #[test]
+    fn test_mistral_instruct() {
+        assert_eq!(
+            render(ChatTemplate::MistralInstruct, &conversation()),
+            "[INST] Be brief.\n\nHi [/INST] Hello!</s>[INST] Bye [/INST]"
+        );
+    }
+
+    #[test]
+    fn test_architecture_defaults() {
+        assert_eq!(
+            LocalArchitecture::Phi2.chat_template(),
+            ChatTemplate::ChatMl
+        );
+        assert_eq!(
+            LocalArchitecture::Qwen2.chat_template(),
+            ChatTemplate::ChatMl
+        );
+        assert_eq!(
+            LocalArchitecture::Llama.chat_template(),
+            ChatTemplate::Llama3
+        );
+        assert_eq!(
+            LocalArchitecture::Mistral.chat_template(),
+            ChatTemplate::MistralInstruct
+        );
+        assert_eq!(ChatTemplate::Llama3.eos_tokens()[0], "<|eot_id|>");
+    }
+
+    #[test]
+    fn test_gemma() {
+        assert_eq!(
+            render(ChatTemplate::Gemma, &conversation()),
+            "<start_of_turn>user\nBe brief.\n\nHi<end_of_turn>\n<start_of_turn>model\nHello!<end_of_turn>\n<start_of_turn>user\nBye<end_of_turn>\n<start_of_turn>model\n"
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_config() {
+        let config = TokenizerConfig::from_json(
+            r#"{
+                "bos_token": {"content": "<s>", "lstrip": false},
+                "eos_token": "</s>",
+                "chat_template": [
+                    {"name": "tool_use", "template": "tools"},
+                    {"name": "default", "template": "chat"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.bos_token.as_deref(), Some("<s>"));
+        assert_eq!(config.eos_token.as_deref(), Some("</s>"));
+        assert_eq!(config.chat_template.as_deref(), Some("chat"));
+
+        let config = TokenizerConfig::from_json(r#"{"model_max_length": 2048}"#).unwrap();
+        assert_eq!(config, TokenizerConfig::default());
+    }
+
+    #[test]
+    fn test_jinja_template() {
+        let source = "{{ bos_token }}{% for message in messages %}{% if message['role'] == 'system' %}{{ raise_exception('No system role') }}{% endif %}\n<|{{ message['role'] }}|>{{ message['content'].strip() }}{{ eos_token }}\n{% endfor %}{% if add_generation_prompt %}<|assistant|>{% endif %}";
+
+        let template = JinjaTemplate::new(
+            source.to_owned(),
+            Some("<s>".to_owned()),
+            Some("</s>".to_owned()),
+        )
+        .unwrap();
+
+        let messages = vec![
+            ChatMessage::new(MessageType::User, " Hi "),
+            ChatMessage::new(MessageType::Assistant, "Hello!"),
+        ];
+
+        assert_eq!(
+            template.render(&messages).unwrap(),
+            "<|user|>Hi</s>\n<|assistant|>Hello!</s>\n<|assistant|>"
+        );
+        assert!(template.render(&conversation()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_jinja_template() {
+        assert!(JinjaTemplate::new("{% for %}".to_owned(), None, None).is_err());
+    }
+}
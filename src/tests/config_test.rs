@@ -0,0 +1,232 @@
+#[cfg(test)]
+mod config_test {
+    use crate::utils::config::{AppConfig, DeploymentBackend, DeploymentConfig, LocalModelConfig};
+    use crate::utils::constants::PHI2_CONTEXT_WINDOW;
+    use crate::utils::local_models::{ChatTemplate, LocalArchitecture};
+    use crate::utils::vars;
+    use serde::Deserialize;
+    use std::borrow::Cow;
+    use std::env;
+
+    #[derive(Deserialize)]
+    struct Deployments<'a> {
+        deployments: Vec<DeploymentConfig<'a>>,
+    }
+
+    #[derive(Deserialize)]
+    struct LocalModels<'a> {
+        local_models: Vec<LocalModelConfig<'a>>,
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(AppConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ip_address() {
+        let mut config = AppConfig::default();
+        config.web_config.ip_address = Cow::Borrowed("not-an-ip");
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_zero_upload_size() {
+        let mut config = AppConfig::default();
+        config.app_config.maximum_upload_size = 0;
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_unsupported_inferencing_model() {
+        let mut config = AppConfig::default();
+        config.app_config.inferencing_model = Cow::Borrowed("unknown");
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_maximum_upload_bytes() {
+        let mut config = AppConfig::default();
+        config.app_config.maximum_upload_size = 2;
+
+        assert_eq!(config.maximum_upload_bytes(), 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_archive_limits() {
+        let mut config = AppConfig::default();
+        config.app_config.max_archive_size = 2;
+
+        let limits = config.archive_limits();
+        assert_eq!(limits.max_bytes, 2 * 1024 * 1024);
+        assert_eq!(limits.max_entries, 1000);
+        assert_eq!(limits.max_depth, 2);
+
+        config.app_config.max_archive_depth = 0;
+        assert!(config.validate().is_err());
+    }
+
+    // Environment variables are shared by every test, so the overrides are checked
+    // in one test.
+    #[test]
+    fn test_env_overrides() {
+        env::set_var(vars::APP_IP_ADDRESS, " 127.0.0.1 ");
+        env::set_var(vars::APP_PORT, "9000");
+        env::set_var(vars::APP_MAXIMUM_UPLOAD_SIZE, "");
+        env::set_var(vars::APP_INFERENCING_MODEL, "azure");
+
+        let config = AppConfig::default().with_env_overrides().unwrap();
+        assert_eq!(config.web_config.ip_address, "127.0.0.1");
+        assert_eq!(config.web_config.port, 9000);
+        assert_eq!(config.app_config.maximum_upload_size, 100);
+        assert_eq!(config.app_config.inferencing_model, "azure");
+
+        env::set_var(vars::APP_PORT, "not-a-port");
+        assert!(AppConfig::default().with_env_overrides().is_err());
+
+        for var in [
+            vars::APP_IP_ADDRESS,
+            vars::APP_PORT,
+            vars::APP_MAXIMUM_UPLOAD_SIZE,
+            vars::APP_INFERENCING_MODEL,
+        ] {
+            env::remove_var(var);
+        }
+
+        let config = AppConfig::default().with_env_overrides().unwrap();
+        assert_eq!(config.web_config.ip_address, "0.0.0.0");
+        assert_eq!(config.web_config.port, 8000);
+    }
+
+    #[test]
+    fn test_load_missing_config_file() {
+        env::set_var(vars::APP_CONFIG_PATH, "/nonexistent/app_config.toml");
+        let config = AppConfig::load();
+        env::remove_var(vars::APP_CONFIG_PATH);
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_built_in_deployments() {
+        let config = AppConfig::default();
+
+        assert!(matches!(
+            config.deployment("LOCAL").unwrap().backend,
+            DeploymentBackend::Local { model: None }
+        ));
+        assert!(matches!(
+            config.deployment("azure").unwrap().backend,
+            DeploymentBackend::Azure { .. }
+        ));
+        assert!(config.deployment("unknown").is_none());
+    }
+
+    #[test]
+    fn test_configured_deployments() {
+        let mut config = AppConfig::default();
+
+        config.deployments = toml::from_str::<Deployments>(
+            r#"
+            [[deployments]]
+            name = "gpt4o"
+            backend = "azure"
+            resource_name = "my-resource"
+            api_version = "2024-02-01"
+            deployment_id = "gpt-4o"
+            inferencing_model = "gpt-4o"
+            max_tokens = 1024
+
+            [[deployments]]
+            name = "local"
+            backend = "local"
+            max_tokens = 256
+            "#,
+        )
+        .unwrap()
+        .deployments;
+
+        let gpt4o = config.deployment("GPT4O").unwrap();
+        assert_eq!(gpt4o.max_tokens, Some(1024));
+        assert!(matches!(
+            gpt4o.backend,
+            DeploymentBackend::Azure { ref resource_name, .. } if resource_name == "my-resource"
+        ));
+
+        assert_eq!(config.deployment("local").unwrap().max_tokens, Some(256));
+        assert_eq!(config.deployments().len(), 3);
+    }
+
+    #[test]
+    fn test_built_in_local_model() {
+        let config = AppConfig::default();
+
+        let phi2 = config.local_model("PHI2").unwrap();
+        assert_eq!(phi2.architecture, LocalArchitecture::Phi2);
+        assert_eq!(phi2.chat_template(), ChatTemplate::ChatMl);
+        assert_eq!(phi2.context_window(), PHI2_CONTEXT_WINDOW);
+        assert_eq!(phi2.eos_tokens(), vec!["<|im_end|>", "<|endoftext|>"]);
+    }
+
+    #[test]
+    fn test_configured_local_models() {
+        let mut config = AppConfig::default();
+
+        config.local_models = toml::from_str::<LocalModels>(
+            r#"
+            [[local_models]]
+            name = "llama3"
+            architecture = "llama"
+            path = "/models/llama3"
+            weights = "llama3.gguf"
+
+            [[local_models]]
+            name = "mistral"
+            architecture = "mistral"
+            path = "/models/mistral"
+            weights = "mistral.gguf"
+            chat_template = "chatml"
+            eos_tokens = ["<|im_end|>"]
+            context_window = 4096
+            "#,
+        )
+        .unwrap()
+        .local_models;
+
+        let llama3 = config.local_model("llama3").unwrap();
+        assert_eq!(llama3.chat_template(), ChatTemplate::Llama3);
+        assert_eq!(llama3.eos_tokens(), vec!["<|eot_id|>", "<|end_of_text|>"]);
+
+        let mistral = config.local_model("mistral").unwrap();
+        assert_eq!(mistral.chat_template(), ChatTemplate::ChatMl);
+        assert_eq!(mistral.eos_tokens(), vec!["<|im_end|>"]);
+        assert_eq!(mistral.context_window(), 4096);
+
+        assert_eq!(
+            config.local_model_names(),
+            vec!["phi2", "llama3", "mistral"]
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_local_deployment_unknown_model() {
+        let mut config = AppConfig::default();
+
+        config.deployments = toml::from_str::<Deployments>(
+            r#"
+            [[deployments]]
+            name = "llama"
+            backend = "local"
+            model = "llama3"
+            "#,
+        )
+        .unwrap()
+        .deployments;
+
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,255 @@
+#[cfg(test)]
+mod extract_test {
+    use crate::utils::extract::{
+        code_language, extract_code, extract_docx, extract_html, extract_markdown, extract_pdf,
+        extract_rst, Heading,
+    };
+    use serde_json::json;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn titles(headings: &[Heading]) -> Vec<(usize, &str)> {
+        headings
+            .iter()
+            .map(|heading| (heading.level, heading.title.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_markdown() {
+        let document = extract_markdown(
+            "# Guide\n\nIntro with `code` and [a link](https://example.com).\n\n## Install\n\n- one\n- two\n\n```rust\nfn main() {}\n```\n",
+        );
+
+        assert_eq!(
+            document.text,
+            "Guide\n\nIntro with code and a link.\n\nInstall\n\n- one\n- two\n\nfn main() {}"
+        );
+        assert_eq!(
+            titles(&document.headings),
+            vec![(1, "Guide"), (2, "Install")]
+        );
+        assert_eq!(document.metadata["format"], "markdown");
+        assert_eq!(document.metadata["title"], "Guide");
+        assert_eq!(document.metadata["code_languages"], json!(["rust"]));
+    }
+
+    #[test]
+    fn test_heading_path() {
+        let document = extract_markdown(
+            "# Setup\n\nText\n\n## Linux\n\nApt\n\n## Windows\n\nMsi\n\n# Usage\n\nRun",
+        );
+
+        let offset = |text: &str| document.text.find(text).unwrap();
+
+        assert_eq!(document.heading_path(0).as_deref(), Some("Setup"));
+        assert_eq!(
+            document.heading_path(offset("Apt")).as_deref(),
+            Some("Setup > Linux")
+        );
+        assert_eq!(
+            document.heading_path(offset("Msi")).as_deref(),
+            Some("Setup > Windows")
+        );
+        assert_eq!(
+            document.heading_path(offset("Run")).as_deref(),
+            Some("Usage")
+        );
+        assert_eq!(extract_markdown("no headings").heading_path(5), None);
+    }
+
+    #[test]
+    fn test_chunk_headings() {
+        let document = extract_markdown(&format!(
+            "# Résumé\n\n{}\n\n## Küche\n\n{}",
+            "é".repeat(30),
+            "ü".repeat(30)
+        ));
+        let kitchen = document.text.find("Küche").unwrap();
+
+        let chunks = document.chunks(10, 2);
+        assert!(chunks.len() > 5);
+
+        for chunk in chunks {
+            assert!(document.text[chunk.start..].starts_with(&chunk.text));
+
+            let heading = if chunk.start >= kitchen {
+                "Résumé > Küche"
+            } else {
+                "Résumé"
+            };
+            assert_eq!(chunk.heading.as_deref(), Some(heading));
+        }
+    }
+
+    #[test]
+    fn test_html() {
+        let document = extract_html(
+            r#"<html><head><title>Manual</title><style>p { color: red }</style></head>
+            <body><script>alert(1)</script>
+            <h1>Overview</h1><p>First   <b>bold</b> paragraph.</p>
+            <h2>Example</h2><pre><code class="language-python">print(1)
+print(2)</code></pre></body></html>"#,
+        );
+
+        assert_eq!(
+            document.text,
+            "Overview\n\nFirst bold paragraph.\n\nExample\n\nprint(1)\nprint(2)"
+        );
+        assert_eq!(
+            titles(&document.headings),
+            vec![(1, "Overview"), (2, "Example")]
+        );
+        assert_eq!(document.metadata["title"], "Manual");
+        assert_eq!(document.metadata["code_languages"], json!(["python"]));
+    }
+
+    #[test]
+    fn test_rst() {
+        let document = extract_rst(
+            "=====\nTitle\n=====\n\nIntro with ``code`` and `docs <https://example.com>`_.\n\nSection\n-------\n\n.. code-block:: python\n\n   print(1)\n\nOther\n-----\n\nEnd\n",
+        );
+
+        assert_eq!(
+            titles(&document.headings),
+            vec![(1, "Title"), (2, "Section"), (2, "Other")]
+        );
+        assert!(document.text.contains("Intro with code and docs."));
+        assert!(document.text.contains("print(1)"));
+        assert!(!document.text.contains(".. code-block"));
+        assert_eq!(document.metadata["code_languages"], json!(["python"]));
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(code_language("src/main.RS"), Some("rust"));
+        assert_eq!(code_language("setup.py"), Some("python"));
+        assert_eq!(code_language("notes.txt"), None);
+        assert_eq!(code_language("Makefile"), None);
+
+        let document = extract_code("lib.ts", "export const a = 1;\n");
+        assert_eq!(document.text, "export const a = 1;");
+        assert_eq!(document.metadata["language"], "typescript");
+        assert!(document.headings.is_empty());
+    }
+
+    #[test]
+    fn test_pdf_pages() {
+        let document = extract_pdf(&[
+            "Introduction\n\n".to_owned(),
+            "  \n".to_owned(),
+            "Results".to_owned(),
+            "Appendix".to_owned(),
+        ]);
+
+        assert_eq!(document.text, "Introduction\n\nResults\n\nAppendix");
+        assert_eq!(document.metadata["format"], "pdf");
+        assert_eq!(document.metadata["page_count"], 4);
+
+        let offset = |text: &str| document.text.find(text).unwrap();
+
+        assert_eq!(document.page_range(0, 5), Some((1, 1)));
+        assert_eq!(
+            document.page_range(offset("Results"), offset("Results") + 7),
+            Some((3, 3))
+        );
+        assert_eq!(document.page_range(2, offset("Appendix") + 3), Some((1, 4)));
+        // The break before a page belongs to the one it follows.
+        assert_eq!(document.page_range(0, offset("Results")), Some((1, 1)));
+
+        assert_eq!(extract_markdown("# No pages").page_range(0, 5), None);
+    }
+
+    #[test]
+    fn test_chunk_pages() {
+        let pages = vec![
+            "Ünïcödé ".repeat(5),
+            "Straße ".repeat(5),
+            "終わり".repeat(5),
+        ];
+        let document = extract_pdf(&pages);
+
+        let chunks = document.chunks(20, 5);
+        let pages: Vec<Option<(usize, usize)>> = chunks.iter().map(|chunk| chunk.pages).collect();
+
+        assert_eq!(
+            pages,
+            vec![
+                Some((1, 1)),
+                Some((1, 2)),
+                // Starts on the break after page 1.
+                Some((1, 2)),
+                Some((2, 3)),
+                Some((3, 3))
+            ]
+        );
+
+        for chunk in &chunks {
+            assert!(document.text[chunk.start..].starts_with(&chunk.text));
+        }
+    }
+
+    fn docx(body: &str) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+
+        zip.start_file("word/document.xml", options).unwrap();
+        zip.write_all(
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{body}</w:body></w:document>"#
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        zip.start_file("docProps/core.xml", options).unwrap();
+        zip.write_all(br#"<cp:coreProperties xmlns:cp="cp" xmlns:dc="dc"><dc:title>Report</dc:title></cp:coreProperties>"#)
+            .unwrap();
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    const DOCX_LIMIT: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_docx() {
+        let bytes = docx(
+            r#"<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Results</w:t></w:r></w:p>
+            <w:p><w:r><w:t xml:space="preserve">Revenue </w:t></w:r><w:r><w:t>grew &amp; costs fell.</w:t><w:tab/><w:t>Q3</w:t></w:r></w:p>
+            <w:p></w:p>
+            <w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Details</w:t></w:r></w:p>
+            <w:p><w:r><w:instrText>PAGE</w:instrText><w:t>Line</w:t><w:br/><w:t>break</w:t></w:r></w:p>"#,
+        );
+        let document = extract_docx(&bytes, DOCX_LIMIT).unwrap();
+
+        assert_eq!(
+            document.text,
+            "Results\n\nRevenue grew & costs fell.\tQ3\n\nDetails\n\nLine\nbreak"
+        );
+        assert_eq!(
+            titles(&document.headings),
+            vec![(1, "Results"), (2, "Details")]
+        );
+        assert_eq!(document.metadata["title"], "Report");
+        assert_eq!(document.metadata["format"], "docx");
+
+        assert!(extract_docx(b"not a zip", DOCX_LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_docx_part_limit() {
+        // Highly compressible, small on disk.
+        let body = format!("<w:p><w:r><w:t>{}</w:t></w:r></w:p>", "a".repeat(4096));
+        let bytes = docx(&body);
+
+        assert!(bytes.len() < 1024);
+        assert!(extract_docx(&bytes, 8192).is_ok());
+
+        let error = extract_docx(&bytes, 1024).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "word/document.xml expands to more than 1024 bytes"
+        );
+    }
+}
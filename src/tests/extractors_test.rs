@@ -0,0 +1,124 @@
+#[cfg(test)]
+mod extractors_test {
+    use crate::utils::extract::{self, ExtractedDocument, CODE_EXTENSIONS};
+    use crate::utils::extractors::{DocumentExtractor, ExtractorRegistry};
+    use anyhow::Result;
+
+    fn detect(file_name: &str, content_type: Option<&str>, head: &[u8]) -> Option<&'static str> {
+        ExtractorRegistry::default()
+            .detect(file_name, content_type, head)
+            .map(|extractor| extractor.name())
+    }
+
+    #[test]
+    fn test_detect_by_extension() {
+        assert_eq!(detect("a.PDF", None, b""), Some("pdf"));
+        assert_eq!(
+            detect("upload_path/notes.txt", None, b"notes"),
+            Some("text")
+        );
+        assert_eq!(detect("Report.docx", None, b""), Some("docx"));
+        assert_eq!(detect("README.md", None, b"# Title"), Some("markdown"));
+        assert_eq!(detect("index.htm", None, b"<p>hi</p>"), Some("html"));
+        assert_eq!(detect("guide.rst", None, b""), Some("rst"));
+        assert_eq!(detect("main.go", None, b"package main"), Some("code"));
+        assert_eq!(detect("faq.csv", None, b"question,answer"), Some("csv"));
+        assert_eq!(detect("catalog.json", None, b"[]"), Some("json"));
+        assert_eq!(detect("catalog.ndjson", None, b"{}"), Some("jsonl"));
+        assert_eq!(detect("archive.mytxt", None, b""), None);
+        assert_eq!(detect("LICENSE", None, b"MIT"), None);
+    }
+
+    #[test]
+    fn test_detect_by_signature() {
+        // Content wins over a misleading extension.
+        assert_eq!(detect("scan.txt", None, b"%PDF-1.7\n"), Some("pdf"));
+        assert_eq!(
+            detect(
+                "upload",
+                None,
+                b"PK\x03\x04....[Content_Types].xml....word/document.xml"
+            ),
+            Some("docx")
+        );
+        assert_eq!(
+            detect("page", None, b"\xef\xbb\xbf  <!DOCTYPE html><html>"),
+            Some("html")
+        );
+
+        // Other ZIP archives aren't documents.
+        assert_eq!(detect("data", None, b"PK\x03\x04....data.csv"), None);
+    }
+
+    #[test]
+    fn test_detect_by_mime_type() {
+        assert_eq!(
+            detect("upload", Some("text/markdown; charset=utf-8"), b"# Title"),
+            Some("markdown")
+        );
+        assert_eq!(detect("upload", Some("text/plain"), b"hi"), Some("text"));
+        assert_eq!(
+            detect("upload", Some("application/octet-stream"), b"hi"),
+            None
+        );
+        // The extension is more specific than a generic MIME type.
+        assert_eq!(
+            detect("notes.md", Some("text/plain"), b"# Title"),
+            Some("markdown")
+        );
+    }
+
+    struct LogExtractor;
+
+    impl DocumentExtractor for LogExtractor {
+        fn name(&self) -> &'static str {
+            "log"
+        }
+
+        fn extensions(&self) -> &[&'static str] {
+            &["log", "txt"]
+        }
+
+        fn extract(&self, _file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+            Ok(extract::extract_text(
+                "log",
+                &String::from_utf8_lossy(bytes),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_custom_extractor() {
+        let extractor = LogExtractor;
+
+        // Without a signature or MIME types, only the extension matches.
+        assert!(!extractor.sniff(b"GET /"));
+        assert!(extractor.mime_types().is_empty());
+
+        let document = extractor.extract("server.log", b"GET /\n").unwrap();
+        assert_eq!(document.text, "GET /");
+        assert_eq!(document.metadata["format"], "log");
+    }
+
+    #[test]
+    fn test_text_must_be_utf8() {
+        let registry = ExtractorRegistry::default();
+        let extractor = registry.detect("notes.txt", None, b"").unwrap();
+
+        assert!(extractor.extract("notes.txt", b"\xff\xfe").is_err());
+        assert_eq!(
+            extractor.extract("notes.txt", b"hello\n").unwrap().text,
+            "hello"
+        );
+    }
+
+    #[test]
+    fn test_code_extensions_have_languages() {
+        for extension in CODE_EXTENSIONS {
+            assert!(
+                extract::code_language(&format!("file.{extension}")).is_some(),
+                "{extension} has no language"
+            );
+        }
+    }
+}
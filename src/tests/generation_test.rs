@@ -0,0 +1,121 @@
+#[cfg(test)]
+mod generation_test {
+    use crate::generation::{GenerationParams, StopSequences};
+    use crate::utils::constants::MAX_GENERATION_TOKENS;
+
+    fn params(json: &str) -> GenerationParams {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn stream(stop: &[&str], tokens: &[&str]) -> (Vec<String>, bool) {
+        let stop: Vec<String> = stop.iter().map(|stop| stop.to_string()).collect();
+        let mut sequences = StopSequences::new(&stop);
+        let mut sent: Vec<String> = Vec::new();
+
+        for token in tokens {
+            let text = sequences.push(token);
+
+            if !text.is_empty() {
+                sent.push(text);
+            }
+
+            if sequences.is_stopped() {
+                break;
+            }
+        }
+
+        let stopped = sequences.is_stopped();
+        let rest = sequences.finish();
+
+        if !rest.is_empty() {
+            sent.push(rest);
+        }
+
+        (sent, stopped)
+    }
+
+    #[test]
+    fn test_generation_params() {
+        let default = params("{}");
+        assert_eq!(default, GenerationParams::default());
+        assert!(default.validate().is_ok());
+
+        let custom = params(
+            r#"{"temperature": 0, "top_p": 0.9, "top_k": 40, "max_tokens": 256, "seed": 7, "repeat_penalty": 1.2, "stop": ["\nUser:"]}"#,
+        );
+        assert_eq!(custom.temperature, Some(0.0));
+        assert_eq!(custom.top_k, Some(40));
+        assert_eq!(custom.stop, vec!["\nUser:"]);
+        assert!(custom.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_generation_params() {
+        for json in [
+            r#"{"temperature": 2.5}"#,
+            r#"{"temperature": -1}"#,
+            r#"{"top_p": 0}"#,
+            r#"{"top_p": 1.5}"#,
+            r#"{"top_k": 0}"#,
+            r#"{"max_tokens": 0}"#,
+            r#"{"repeat_penalty": 0}"#,
+            r#"{"stop": [""]}"#,
+            r#"{"stop": ["a", "b", "c", "d", "e"]}"#,
+            r#"{"seed": 9223372036854775808}"#,
+        ] {
+            assert!(params(json).validate().is_err(), "{json} should be invalid");
+        }
+
+        let too_long = GenerationParams {
+            max_tokens: Some(MAX_GENERATION_TOKENS + 1),
+            ..Default::default()
+        };
+        assert!(too_long.validate().is_err());
+
+        let largest_seed = GenerationParams {
+            seed: Some(i64::MAX as u64),
+            ..Default::default()
+        };
+        assert!(largest_seed.validate().is_ok());
+    }
+
+    #[test]
+    fn test_without_stop_sequences() {
+        assert_eq!(
+            stream(&[], &["Hello", " world"]),
+            (vec!["Hello".to_owned(), " world".to_owned()], false)
+        );
+    }
+
+    #[test]
+    fn test_stop_sequence_across_tokens() {
+        let (sent, stopped) = stream(&["\nUser:"], &["Sure", ".", "\n", "Us", "er:", " more"]);
+
+        assert!(stopped);
+        assert_eq!(sent.concat(), "Sure.");
+    }
+
+    #[test]
+    fn test_partial_stop_sequence_released() {
+        let (sent, stopped) = stream(&["\nUser:"], &["a", "\n", "Use", "ful"]);
+
+        assert!(!stopped);
+        assert_eq!(sent, vec!["a", "\nUseful"]);
+    }
+
+    #[test]
+    fn test_held_back_text_flushed() {
+        let (sent, stopped) = stream(&["END"], &["The E"]);
+
+        assert!(!stopped);
+        assert_eq!(sent, vec!["The ", "E"]);
+    }
+
+    #[test]
+    fn test_earliest_stop_sequence_wins() {
+        let (sent, stopped) = stream(&["two", "one"], &["zero one two"]);
+
+        assert!(stopped);
+        assert_eq!(sent, vec!["zero "]);
+    }
+}
@@ -0,0 +1,94 @@
+#[cfg(test)]
+mod metadata_filter_test {
+    use crate::utils::constants::MAX_FILTER_CONDITIONS;
+    use crate::utils::metadata_filter::{FilterParam, FilterSql, MetadataFilter};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_empty_filter() {
+        let filter = MetadataFilter::default();
+
+        assert!(filter.is_empty());
+        assert_eq!(filter.to_sql(2).unwrap(), FilterSql::default());
+        assert_eq!(filter.to_sql(2).unwrap().and_clause(), "");
+    }
+
+    #[test]
+    fn test_filter_to_sql() {
+        let filter: MetadataFilter = serde_json::from_str(
+            r#"{
+                "equals": {"source": "report.pdf"},
+                "in": {"lang": ["en", "de"]},
+                "source_prefix": "2024/",
+                "uploaded_after": "2024-01-01T00:00:00Z",
+                "uploaded_before": "2024-02-01T00:00:00Z"
+            }"#,
+        )
+        .unwrap();
+
+        let filter_sql = filter.to_sql(3).unwrap();
+
+        assert_eq!(
+            filter_sql.clause,
+            "(metadata::jsonb ->> $3) = $4 AND (metadata::jsonb ->> $5) = ANY($6::text[]) AND starts_with(metadata::jsonb ->> 'source', $7) AND (CASE WHEN metadata::jsonb ->> 'upload_time' ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}[ T][0-9]{2}:[0-9]{2}:[0-9]{2}' THEN left(metadata::jsonb ->> 'upload_time', 19)::timestamp END) >= $8 AND (CASE WHEN metadata::jsonb ->> 'upload_time' ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}[ T][0-9]{2}:[0-9]{2}:[0-9]{2}' THEN left(metadata::jsonb ->> 'upload_time', 19)::timestamp END) < $9"
+        );
+
+        assert_eq!(
+            filter_sql.params,
+            vec![
+                FilterParam::Text("source".to_string()),
+                FilterParam::Text("report.pdf".to_string()),
+                FilterParam::Text("lang".to_string()),
+                FilterParam::TextArray(vec!["en".to_string(), "de".to_string()]),
+                FilterParam::Text("2024/".to_string()),
+                FilterParam::Timestamp(
+                    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap().naive_utc()
+                ),
+                FilterParam::Timestamp(
+                    Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap().naive_utc()
+                ),
+            ]
+        );
+
+        assert!(filter_sql.and_clause().starts_with(" AND ((metadata::jsonb ->> $3)"));
+    }
+
+    #[test]
+    fn test_filter_keeps_values_out_of_sql() {
+        let filter: MetadataFilter = serde_json::from_str(
+            r#"{"equals": {"source') OR 1=1 --": "x'; DROP TABLE search_tables; --"}}"#,
+        )
+        .unwrap();
+
+        let filter_sql = filter.to_sql(2).unwrap();
+
+        assert_eq!(filter_sql.clause, "(metadata::jsonb ->> $2) = $3");
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        assert!(serde_json::from_str::<MetadataFilter>(r#"{"source": "a"}"#).is_err());
+
+        let empty_in: MetadataFilter = serde_json::from_str(r#"{"in": {"lang": []}}"#).unwrap();
+        assert!(empty_in.to_sql(2).is_err());
+
+        let empty_key: MetadataFilter = serde_json::from_str(r#"{"equals": {" ": "a"}}"#).unwrap();
+        assert!(empty_key.to_sql(2).is_err());
+
+        let reversed: MetadataFilter = serde_json::from_str(
+            r#"{"uploaded_after": "2024-02-01T00:00:00Z", "uploaded_before": "2024-01-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        assert!(reversed.to_sql(2).is_err());
+
+        let too_many = MetadataFilter {
+            any_of: [(
+                "source".to_string(),
+                (0..=MAX_FILTER_CONDITIONS).map(|i| i.to_string()).collect(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        assert!(too_many.to_sql(2).is_err());
+    }
+}
@@ -1,4 +1,17 @@
 pub mod text_splitter_test;
 pub mod sql_query_test;
 pub mod azureai;
-pub mod tools;
\ No newline at end of file
+pub mod tools;
+pub mod config_test;
+pub mod openai_compatible;
+pub mod reranker_test;
+pub mod metadata_filter_test;
+pub mod retrieval_test;
+pub mod prompt_test;
+pub mod chat_template_test;
+pub mod generation_test;
+pub mod protocol_test;
+pub mod extract_test;
+pub mod extractors_test;
+pub mod records_test;
+pub mod archive_test;
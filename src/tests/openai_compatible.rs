@@ -0,0 +1,44 @@
+#[cfg(test)]
+mod openai_compatible_test {
+    use crate::chat_backend::{ChatBackend, ChatMessage};
+    use crate::generation::GenerationParams;
+    use crate::local::inferencing::MessageType;
+    use crate::openai_compatible::OpenAICompatible;
+    use futures::TryStreamExt;
+    use warp::Filter;
+
+    const STREAM_BODY: &'static str = concat!(
+        "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"mock\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"mock\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" world\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"mock\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n"
+    );
+
+    #[tokio::test]
+    async fn test_stream_chat_from_mock_server() {
+        let route = warp::path!("v1" / "chat" / "completions")
+            .and(warp::post())
+            .map(|| {
+                warp::http::Response::builder()
+                    .header("content-type", "text/event-stream")
+                    .body(STREAM_BODY)
+                    .unwrap()
+            });
+
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let backend = OpenAICompatible::new(format!("http://{address}/v1"), None, "mock", 16);
+
+        let messages = vec![ChatMessage::new(MessageType::User, "Hi")];
+        let params = GenerationParams::default();
+
+        let tokens: Vec<String> = backend
+            .stream_chat(&messages, &params)
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(tokens.concat(), "Hello world");
+    }
+}
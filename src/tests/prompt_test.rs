@@ -0,0 +1,218 @@
+#[cfg(test)]
+mod prompt_test {
+    use crate::chat_backend::ChatMessage;
+    use crate::local::inferencing::MessageType;
+    use crate::prompt::{estimate_tokens, format_user_message, pack_prompt, PromptBudget};
+    use crate::retrieval::SearchHit;
+    use crate::utils::constants::MESSAGE_TOKEN_OVERHEAD;
+    use serde_json::json;
+
+    fn count_words(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    fn hit(content: &str, score: f64) -> SearchHit {
+        SearchHit {
+            content: content.to_owned(),
+            score,
+            content_id: "id".to_owned(),
+            chunk_number: 0,
+            metadata: json!({"source": "doc.txt"}),
+        }
+    }
+
+    fn words(count: usize) -> String {
+        vec!["word"; count].join(" ")
+    }
+
+    fn history(count: usize, words_per_message: usize) -> Vec<ChatMessage> {
+        (0..count)
+            .map(|i| {
+                let role = if i % 2 == 0 {
+                    MessageType::User
+                } else {
+                    MessageType::Assistant
+                };
+                ChatMessage::new(role, format!("m{i} {}", words(words_per_message - 1)))
+            })
+            .collect()
+    }
+
+    fn prompt_size(messages: &[ChatMessage]) -> usize {
+        messages
+            .iter()
+            .map(|message| count_words(&message.content) + MESSAGE_TOKEN_OVERHEAD)
+            .sum()
+    }
+
+    #[test]
+    fn test_prompt_budget() {
+        let budget = PromptBudget {
+            context_window: 2048,
+            max_new_tokens: 512,
+        };
+        assert_eq!(budget.prompt_tokens().unwrap(), 1536);
+
+        let budget = PromptBudget {
+            context_window: 512,
+            max_new_tokens: 512,
+        };
+        assert!(budget.prompt_tokens().is_err());
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn test_everything_fits() {
+        let budget = PromptBudget {
+            context_window: 4096,
+            max_new_tokens: 512,
+        };
+        let references = [hit("low", 0.2), hit("high", 0.9)];
+
+        let packed = pack_prompt(
+            "system",
+            history(4, 10),
+            "question",
+            &references,
+            &budget,
+            count_words,
+        )
+        .unwrap();
+
+        assert_eq!(packed.messages.len(), 6);
+        assert_eq!(packed.messages[0].role, MessageType::System);
+        assert_eq!(packed.messages[1].content, history(4, 10)[0].content);
+        assert_eq!(packed.dropped_references, 0);
+        assert_eq!(packed.truncated_references, 0);
+        assert_eq!(packed.dropped_history, 0);
+        assert_eq!(packed.prompt_tokens, prompt_size(&packed.messages));
+        assert_eq!(packed.references[0].content, "high");
+
+        // Best scoring reference first.
+        let expected = format_user_message(
+            "question",
+            &[
+                json!({"content": "high", "metadata": {"source": "doc.txt"}}),
+                json!({"content": "low", "metadata": {"source": "doc.txt"}}),
+            ],
+        );
+        assert_eq!(packed.user_message(), expected);
+    }
+
+    #[test]
+    fn test_oldest_history_dropped() {
+        let budget = PromptBudget {
+            context_window: 600,
+            max_new_tokens: 512,
+        };
+
+        let packed = pack_prompt(
+            "system",
+            history(10, 10),
+            "question",
+            &[],
+            &budget,
+            count_words,
+        )
+        .unwrap();
+
+        assert!(packed.dropped_history > 0);
+        assert!(prompt_size(&packed.messages) <= 88);
+
+        // The kept history is the most recent, in order.
+        let kept = &packed.messages[1..packed.messages.len() - 1];
+        assert_eq!(kept.last().unwrap().content, history(10, 10)[9].content);
+        assert_eq!(kept.len(), 10 - packed.dropped_history);
+    }
+
+    #[test]
+    fn test_references_truncated_and_dropped() {
+        let budget = PromptBudget {
+            context_window: 1024,
+            max_new_tokens: 512,
+        };
+        let references = [
+            hit(&words(300), 0.9),
+            hit(&words(300), 0.8),
+            hit(&words(300), 0.1),
+        ];
+
+        let packed = pack_prompt(
+            "system",
+            Vec::new(),
+            "question",
+            &references,
+            &budget,
+            count_words,
+        )
+        .unwrap();
+
+        assert_eq!(packed.truncated_references, 1);
+        assert_eq!(packed.dropped_references, 1);
+        assert_eq!(packed.references.len(), 2);
+        assert_eq!(packed.references[1].score, 0.8);
+        assert!(prompt_size(&packed.messages) <= 512);
+        assert!(packed.user_message().contains("\"content\":\"word"));
+    }
+
+    #[test]
+    fn test_smaller_references_kept_after_one_too_long() {
+        let budget = PromptBudget {
+            context_window: 1024,
+            max_new_tokens: 512,
+        };
+        let references = [
+            hit(&words(300), 0.9),
+            hit(&words(300), 0.8),
+            hit(&words(20), 0.5),
+        ];
+
+        let packed = pack_prompt(
+            "system",
+            Vec::new(),
+            "question",
+            &references,
+            &budget,
+            count_words,
+        )
+        .unwrap();
+
+        // Too little room is left to truncate the second one.
+        assert_eq!(packed.truncated_references, 0);
+        assert_eq!(packed.dropped_references, 1);
+        assert_eq!(
+            packed
+                .references
+                .iter()
+                .map(|reference| reference.score)
+                .collect::<Vec<f64>>(),
+            vec![0.9, 0.5]
+        );
+        assert!(prompt_size(&packed.messages) <= 512);
+    }
+
+    #[test]
+    fn test_question_too_long() {
+        let budget = PromptBudget {
+            context_window: 600,
+            max_new_tokens: 512,
+        };
+
+        let result = pack_prompt(
+            "system",
+            Vec::new(),
+            &words(200),
+            &[hit("a", 1.0)],
+            &budget,
+            count_words,
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(test)]
+mod protocol_test {
+    use crate::protocol::{
+        AnswerSlot, Citation, ClientMessage, InFlightAnswers, ServerEvent, Usage, PROTOCOL_VERSION,
+    };
+    use crate::retrieval::SearchHit;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    fn frame(event: ServerEvent) -> Value {
+        serde_json::from_str(&event.to_json(None)).unwrap()
+    }
+
+    #[test]
+    fn test_token_and_error_frames() {
+        assert_eq!(
+            frame(ServerEvent::Token {
+                content: "Hello".to_owned()
+            }),
+            json!({"version": PROTOCOL_VERSION, "type": "token", "content": "Hello"})
+        );
+
+        assert_eq!(
+            frame(ServerEvent::error("No Knowledge Base Selected")),
+            json!({"version": 1, "type": "error", "message": "No Knowledge Base Selected"})
+        );
+    }
+
+    #[test]
+    fn test_references_frame() {
+        let hit = SearchHit {
+            content: "chunk".to_owned(),
+            score: 0.5,
+            content_id: "id".to_owned(),
+            chunk_number: 3,
+            metadata: json!({"source": "report.pdf"}),
+        };
+
+        assert_eq!(
+            frame(ServerEvent::References {
+                references: vec![Citation::from(&hit)]
+            }),
+            json!({
+                "version": 1,
+                "type": "references",
+                "references": [{
+                    "source": "report.pdf",
+                    "content_id": "id",
+                    "chunk_number": 3,
+                    "score": 0.5,
+                    "metadata": {"source": "report.pdf"}
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_references_pages() {
+        let hit = SearchHit {
+            content: "chunk".to_owned(),
+            score: 0.5,
+            content_id: "id".to_owned(),
+            chunk_number: 7,
+            metadata: json!({"source": "report.pdf", "page_start": 4, "page_end": 5}),
+        };
+
+        let citation = serde_json::to_value(Citation::from(&hit)).unwrap();
+
+        assert_eq!(citation["page_start"], 4);
+        assert_eq!(citation["page_end"], 5);
+    }
+
+    #[test]
+    fn test_done_frame() {
+        let usage = Usage {
+            prompt_tokens: 120,
+            completion_tokens: 30,
+            duration_ms: 450,
+        };
+
+        assert_eq!(
+            frame(ServerEvent::Done { usage }),
+            json!({
+                "version": 1,
+                "type": "done",
+                "usage": {"prompt_tokens": 120, "completion_tokens": 30, "duration_ms": 450}
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_id_tagged() {
+        let frame: Value =
+            serde_json::from_str(&ServerEvent::Cancelled.to_json(Some("abc"))).unwrap();
+
+        assert_eq!(
+            frame,
+            json!({"version": 1, "request_id": "abc", "type": "cancelled"})
+        );
+    }
+
+    #[test]
+    fn test_client_messages() {
+        // Questions sent without a type, as before cancellation existed.
+        let question = r#"{"table_name": "docs", "session_id": "s1", "sentence": "What?", "deployment_type": "local", "deployment_model": "", "temperature": 0}"#;
+
+        match ClientMessage::parse(question).unwrap() {
+            ClientMessage::Question(question) => {
+                assert_eq!(question.table_name, "docs");
+                assert_eq!(question.request_id, None);
+                assert_eq!(question.generation.temperature, Some(0.0));
+            }
+            message => panic!("Expected a question, got {message:?}"),
+        }
+
+        match ClientMessage::parse(r#"{"type": "cancel", "request_id": "abc"}"#).unwrap() {
+            ClientMessage::Cancel { request_id } => assert_eq!(request_id.as_deref(), Some("abc")),
+            message => panic!("Expected a cancel, got {message:?}"),
+        }
+
+        assert!(ClientMessage::parse(r#"{"type": "pause"}"#).is_err());
+        assert!(ClientMessage::parse(r#"{"type": "question"}"#).is_err());
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let mut in_flight = InFlightAnswers::new(2);
+
+        let _first = in_flight.start("a").unwrap();
+        assert!(in_flight.start("a").is_err());
+
+        let _second = in_flight.start("b").unwrap();
+        assert!(in_flight.start("c").is_err());
+
+        in_flight.finish("a");
+        assert!(in_flight.start("c").is_ok());
+        assert_eq!(in_flight.len(), 2);
+    }
+
+    #[test]
+    fn test_cancel_answers() {
+        let mut in_flight = InFlightAnswers::new(3);
+
+        let mut first = in_flight.start("a").unwrap();
+        let mut second = in_flight.start("b").unwrap();
+
+        assert_eq!(in_flight.cancel(Some("a")), 1);
+        assert!(first.try_recv().is_ok());
+        assert!(second.try_recv().is_err());
+
+        // Cancelled answers keep their slot until they finish.
+        assert_eq!(in_flight.cancel(Some("a")), 0);
+        assert!(in_flight.start("a").is_err());
+
+        assert_eq!(in_flight.cancel(None), 1);
+        assert!(second.try_recv().is_ok());
+        assert_eq!(in_flight.cancel(Some("missing")), 0);
+    }
+
+    #[test]
+    fn test_answer_slot_finishes_on_drop() {
+        let in_flight = Arc::new(Mutex::new(InFlightAnswers::new(1)));
+        let _cancelled = in_flight.lock().unwrap().start("a").unwrap();
+
+        let slot = AnswerSlot::new(in_flight.clone(), "a".to_owned());
+        let answer = std::thread::spawn(move || {
+            let _slot = slot;
+            panic!("answer failed");
+        });
+
+        assert!(answer.join().is_err());
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(test)]
+mod records_test {
+    use crate::utils::records::{
+        parse_csv, parse_json, parse_jsonl, record_documents, RecordTemplate,
+    };
+    use serde_json::json;
+
+    const FAQ: &[u8] = b"question,answer,category,id\nHow do I reset my password?,Use the login page.,account,1\nWhere is my order?,Check the orders tab., ,2\n";
+
+    #[test]
+    fn test_parse_csv() {
+        let records = parse_csv(FAQ).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            vec![
+                ("question".to_owned(), json!("How do I reset my password?")),
+                ("answer".to_owned(), json!("Use the login page.")),
+                ("category".to_owned(), json!("account")),
+                ("id".to_owned(), json!("1")),
+            ]
+        );
+        // Empty cells are left out.
+        assert!(!records[1].iter().any(|(field, _)| field == "category"));
+
+        assert!(parse_csv(b"a,b\n1,2,3\n").is_err());
+    }
+
+    #[test]
+    fn test_text_fields() {
+        let template = RecordTemplate::parse(Some(" question , answer"), None).unwrap();
+        let documents = record_documents("csv", parse_csv(FAQ).unwrap(), &template).unwrap();
+
+        assert_eq!(documents.len(), 2);
+        assert_eq!(
+            documents[0].text,
+            "question: How do I reset my password?\nanswer: Use the login page."
+        );
+        assert_eq!(
+            json!(documents[0].metadata),
+            json!({"format": "csv", "category": "account", "id": "1", "record": 1})
+        );
+        assert_eq!(documents[1].metadata["record"], 2);
+    }
+
+    #[test]
+    fn test_text_template() {
+        let template = RecordTemplate::parse(
+            None,
+            Some("{{ name }} ({{ price }} USD): {{ description }}"),
+        )
+        .unwrap();
+
+        let records = parse_jsonl(
+            br#"{"sku": "A1", "name": "Desk", "price": 120, "description": "Oak desk", "tags": ["office"]}
+
+{"sku": "B2", "name": "Lamp", "price": 30.5, "description": "LED lamp"}"#,
+        )
+        .unwrap();
+        let documents = record_documents("jsonl", records, &template).unwrap();
+
+        assert_eq!(documents[0].text, "Desk (120 USD): Oak desk");
+        assert_eq!(documents[0].metadata["sku"], "A1");
+        assert_eq!(documents[0].metadata["tags"], json!(["office"]));
+        assert!(!documents[0].metadata.contains_key("price"));
+        assert_eq!(documents[1].text, "Lamp (30.5 USD): LED lamp");
+
+        assert!(RecordTemplate::parse(None, Some("{{ name")).is_err());
+    }
+
+    #[test]
+    fn test_all_fields() {
+        let records = parse_json(br#"[{"b": "two", "a": 1, "empty": null}]"#).unwrap();
+        let documents = record_documents("json", records, &RecordTemplate::default()).unwrap();
+
+        assert_eq!(documents[0].text, "a: 1\nb: two");
+        assert_eq!(
+            json!(documents[0].metadata),
+            json!({"format": "json", "record": 1})
+        );
+
+        assert_eq!(parse_json(br#"{"a": 1}"#).unwrap().len(), 1);
+        assert!(parse_json(b"[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let error = parse_jsonl(b"{\"a\": 1}\n\nnot json\n").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid JSON on line 3");
+
+        // A typo in text_fields leaves every record without text.
+        let template = RecordTemplate::parse(Some("questoin"), None).unwrap();
+        assert!(record_documents("csv", parse_csv(FAQ).unwrap(), &template).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+#[cfg(test)]
+mod reranker_test {
+    use crate::local::reranker::rank_by_scores;
+
+    #[test]
+    fn test_rank_by_scores_keeps_best() {
+        let ranked = rank_by_scores(vec!["a", "b", "c", "d"], vec![0.2, 0.9, 0.1, 0.5], 2);
+
+        assert_eq!(ranked, vec![("b", 0.9), ("d", 0.5)]);
+    }
+
+    #[test]
+    fn test_rank_by_scores_fewer_than_top_n() {
+        let ranked = rank_by_scores(vec!["a", "b"], vec![0.1, 0.3], 5);
+
+        assert_eq!(ranked, vec![("b", 0.3), ("a", 0.1)]);
+    }
+}
@@ -0,0 +1,42 @@
+#[cfg(test)]
+mod retrieval_test {
+    use crate::retrieval::RetrievalOptions;
+    use crate::utils::constants::{MAX_RERANK_CANDIDATES, RERANK_CANDIDATE_FACTOR};
+    use crate::utils::knowledge_base::SearchMode;
+
+    #[test]
+    fn test_retrieval_options_defaults() {
+        let options: RetrievalOptions =
+            serde_json::from_str(r#"{"max_similar_search": 5}"#).unwrap();
+
+        assert_eq!(options.search_mode, SearchMode::Vector);
+        assert_eq!(options.upper_chunk, 0);
+        assert_eq!(options.lower_chunk, 0);
+        assert!(options.filter.is_empty());
+        assert_eq!(options.rerank_candidates(), None);
+    }
+
+    #[test]
+    fn test_rerank_candidates() {
+        let options: RetrievalOptions =
+            serde_json::from_str(r#"{"max_similar_search": 5, "rerank": true}"#).unwrap();
+        assert_eq!(options.rerank_candidates(), Some(5 * RERANK_CANDIDATE_FACTOR));
+
+        let options: RetrievalOptions = serde_json::from_str(
+            r#"{"max_similar_search": 5, "rerank": true, "rerank_candidates": 1000}"#,
+        )
+        .unwrap();
+        assert_eq!(options.rerank_candidates(), Some(MAX_RERANK_CANDIDATES));
+
+        let options: RetrievalOptions = serde_json::from_str(
+            r#"{"max_similar_search": 5, "rerank": true, "rerank_candidates": 2}"#,
+        )
+        .unwrap();
+        assert_eq!(options.rerank_candidates(), Some(5));
+
+        // More results than the reranker usually gets, all of them are candidates.
+        let options: RetrievalOptions =
+            serde_json::from_str(r#"{"max_similar_search": 150, "rerank": true}"#).unwrap();
+        assert_eq!(options.rerank_candidates(), Some(150));
+    }
+}
@@ -1,8 +1,16 @@
 #[cfg(test)]
 mod sql_query_test {
     use crate::utils::constants::*;
+    use crate::utils::knowledge_base::{
+        DistanceMetric, KnowledgeBaseName, KnowledgeBaseSettings, SearchMode, SearchTuning,
+        VectorIndex,
+    };
     pub const TABLE_NAME: &'static str = "test_table";
 
+    fn table_name() -> KnowledgeBaseName {
+        KnowledgeBaseName::parse(TABLE_NAME).unwrap()
+    }
+
     #[test]
     fn test_create_search_base_sql() {
         assert_eq!(create_search_base_sql(), format!("CREATE TABLE IF NOT EXISTS {SEARCH_TABLES} (id bigserial PRIMARY KEY, table_name TEXT UNIQUE)"))
@@ -10,22 +18,22 @@ mod sql_query_test {
 
     #[test]
     fn test_get_adj_chunk_sql() {
-        assert_eq!(get_adj_chunk_sql(TABLE_NAME), "SELECT * FROM test_table WHERE content_id = $1 AND chunk_number >= $2 AND chunk_number <= $3 ORDER BY chunk_number ASC")
+        assert_eq!(get_adj_chunk_sql(&table_name()), "SELECT *, CAST(0 AS FLOAT8) as score FROM \"test_table\" WHERE content_id = $1 AND chunk_number >= $2 AND chunk_number <= $3 ORDER BY chunk_number ASC")
     }
 
     #[test]
     fn test_create_vector_table_sql() {
         assert_eq!(
-            create_vector_table_sql(TABLE_NAME),
-            "CREATE TABLE IF NOT EXISTS test_table (id bigserial PRIMARY KEY, content_id TEXT, content_chunk TEXT, chunk_number int, embedding vector(1024), metadata JSON, created_at timestamp)"
+            create_vector_table_sql(&table_name(), 1024),
+            "CREATE TABLE IF NOT EXISTS \"test_table\" (id bigserial PRIMARY KEY, content_id TEXT, content_chunk TEXT, chunk_number int, embedding vector(1024), metadata JSON, created_at timestamp, content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('english', coalesce(content_chunk, ''))) STORED)"
         )
     }
 
     #[test]
     fn test_create_raw_content_table_sql() {
         assert_eq!(
-            create_raw_content_table_sql(TABLE_NAME),
-            "CREATE TABLE IF NOT EXISTS test_table_content (id bigserial PRIMARY KEY, content_id TEXT, title TEXT, text TEXT, metadata JSON)"
+            create_raw_content_table_sql(&table_name()),
+            "CREATE TABLE IF NOT EXISTS \"test_table_content\" (id bigserial PRIMARY KEY, content_id TEXT, title TEXT, text TEXT, metadata JSON)"
         )
     }
 
@@ -33,16 +41,58 @@ mod sql_query_test {
     fn test_insert_into_search_table_sql() {
         assert_eq!(
             insert_into_search_table_sql(),
-            format!("INSERT INTO {SEARCH_TABLES} (table_name) VALUES ($1)")
+            format!("INSERT INTO {SEARCH_TABLES} (table_name, embedding_model, dimension, distance_metric, chunk_size, chunk_overlap) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (table_name) DO NOTHING")
         )
     }
 
+    #[test]
+    fn test_get_search_table_settings_sql() {
+        assert_eq!(
+            get_search_table_settings_sql(),
+            format!("SELECT embedding_model, dimension, distance_metric, chunk_size, chunk_overlap FROM {SEARCH_TABLES} WHERE table_name = $1")
+        )
+    }
+
+    #[test]
+    fn test_migrate_search_base_sql() {
+        let migrations = migrate_search_base_sql();
+
+        assert_eq!(migrations.len(), 5);
+        assert_eq!(
+            migrations[0],
+            format!("ALTER TABLE {SEARCH_TABLES} ADD COLUMN IF NOT EXISTS embedding_model TEXT NOT NULL DEFAULT 'local:BAAI_V1.5L'")
+        );
+    }
+
+    #[test]
+    fn test_lowercase_search_tables_sql() {
+        let migrations = lowercase_search_tables_sql();
+
+        assert_eq!(migrations.len(), 2);
+        assert!(migrations[0].starts_with("DELETE FROM search_tables s USING search_tables t WHERE lower(t.table_name) = lower(s.table_name)"));
+        assert_eq!(
+            migrations[1],
+            "UPDATE search_tables SET table_name = lower(table_name) WHERE table_name <> lower(table_name)"
+        );
+
+        // A knowledge base created as `MyDocs` before names were normalized is
+        // looked up by its lowercased row, and Postgres' folded table name.
+        let legacy = KnowledgeBaseName::parse("MyDocs").unwrap();
+        assert_eq!(legacy.as_str(), "mydocs");
+        assert_eq!(legacy.quoted(), "\"mydocs\"");
+    }
+
+    #[test]
+    fn test_create_vector_table_sql_dimension() {
+        assert!(create_vector_table_sql(&table_name(), 1536).contains("embedding vector(1536)"))
+    }
+
     #[test]
     fn test_bulk_insert_into_vector_table_sql() {
         assert_eq!(
-            bulk_insert_into_vector_table_sql(TABLE_NAME).replace("\n", "").replace("   ", "").trim(),
+            bulk_insert_into_vector_table_sql(&table_name()).replace("\n", "").replace("   ", "").trim(),
             format!("
-                INSERT INTO {TABLE_NAME}(content_id, content_chunk, chunk_number, embedding, metadata, created_at) 
+                INSERT INTO \"{TABLE_NAME}\"(content_id, content_chunk, chunk_number, embedding, metadata, created_at) 
                 SELECT * FROM UNNEST($1::text[], $2::text[], $3::int4[], $4::vector[], $5::jsonb[], $6::timestamp[])
             ").replace("\n", "").replace("   ", "").trim()
         )
@@ -50,17 +100,60 @@ mod sql_query_test {
 
     #[test]
     fn test_insert_raw_content_sql() {
-        assert_eq!(insert_raw_content_sql(TABLE_NAME), "INSERT INTO test_table_content (content_id, title, text, metadata) VALUES ($1, $2, $3, $4::jsonb)")
+        assert_eq!(insert_raw_content_sql(&table_name()), "INSERT INTO \"test_table_content\" (content_id, title, text, metadata) VALUES ($1, $2, $3, $4::jsonb)")
     }
 
     #[test]
     fn test_get_similar_result_query() {
         assert_eq!(
-            get_similar_result_query(TABLE_NAME, 10, 0.5),
-            "SELECT *, (1.0-(embedding <=> $1::vector)) as score FROM test_table WHERE (1.0-(embedding <=> $1::vector)) >=0.5 ORDER BY score DESC LIMIT 10"
+            get_similar_result_query(&table_name(), DistanceMetric::Cosine, 10, 0.5, ""),
+            "SELECT *, (1.0-(embedding <=> $1::vector)) as score FROM \"test_table\" WHERE (1.0-(embedding <=> $1::vector)) >=0.5 ORDER BY (embedding <=> $1::vector) LIMIT 10"
+        )
+    }
+
+    #[test]
+    fn test_get_similar_result_query_l2() {
+        assert_eq!(
+            get_similar_result_query(&table_name(), DistanceMetric::L2, 10, 0.5, ""),
+            "SELECT *, (1.0/(1.0+(embedding <-> $1::vector))) as score FROM \"test_table\" WHERE (1.0/(1.0+(embedding <-> $1::vector))) >=0.5 ORDER BY (embedding <-> $1::vector) LIMIT 10"
         )
     }
 
+    #[test]
+    fn test_knowledge_base_settings_check_embedding() {
+        let settings = KnowledgeBaseSettings {
+            embedding_model: "local:BAAI_V1.5L".to_string(),
+            dimension: 3,
+            distance_metric: DistanceMetric::Cosine,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk_overlap: DEFAULT_CHUNK_OVERLAP,
+        };
+
+        assert!(settings.validate().is_ok());
+        assert!(settings
+            .check_embedding("local:BAAI_V1.5L", &[0.1, 0.2, 0.3])
+            .is_ok());
+        assert!(settings
+            .check_embedding("azure:text-embedding-ada-002", &[0.1, 0.2, 0.3])
+            .is_err());
+        assert!(settings
+            .check_embedding("local:BAAI_V1.5L", &[0.1, 0.2])
+            .is_err());
+    }
+
+    #[test]
+    fn test_knowledge_base_settings_invalid_chunking() {
+        let settings = KnowledgeBaseSettings {
+            embedding_model: "local:BAAI_V1.5L".to_string(),
+            dimension: 1024,
+            distance_metric: DistanceMetric::Cosine,
+            chunk_size: 200,
+            chunk_overlap: 200,
+        };
+
+        assert!(settings.validate().is_err());
+    }
+
     #[test]
     fn test_get_search_tables_sql() {
         assert_eq!(
@@ -72,16 +165,289 @@ mod sql_query_test {
     #[test]
     fn test_get_drop_table_sql() {
         assert_eq!(
-            get_drop_table_sql(TABLE_NAME.to_string()),
-            format!("DROP TABLE IF EXISTS {TABLE_NAME}")
+            get_drop_table_sql(&table_name()),
+            format!("DROP TABLE IF EXISTS \"{TABLE_NAME}\"")
+        )
+    }
+
+    #[test]
+    fn test_get_drop_content_table_sql() {
+        assert_eq!(
+            get_drop_content_table_sql(&table_name()),
+            format!("DROP TABLE IF EXISTS \"{TABLE_NAME}_content\"")
         )
     }
 
     #[test]
     fn test_get_drop_search_tables_sql() {
         assert_eq!(
-            get_delete_from_search_table_sql(TABLE_NAME),
-            format!("DELETE FROM {SEARCH_TABLES} WHERE table_name = '{TABLE_NAME}'")
+            get_delete_from_search_table_sql(),
+            format!("DELETE FROM {SEARCH_TABLES} WHERE table_name = $1")
         )
     }
+
+    #[test]
+    fn test_knowledge_base_name_normalized() {
+        let table_name = KnowledgeBaseName::parse("  Test_Table1 ").unwrap();
+
+        assert_eq!(table_name.as_str(), "test_table1");
+        assert_eq!(table_name.quoted(), "\"test_table1\"");
+        assert_eq!(table_name.quoted_content(), "\"test_table1_content\"");
+    }
+
+    #[test]
+    fn test_knowledge_base_name_rejects_hostile_names() {
+        let hostile_names = [
+            "",
+            "   ",
+            "test; DROP TABLE search_tables; --",
+            "test' OR '1'='1",
+            "test\"; DROP TABLE users; --",
+            "test_table--",
+            "test table",
+            "test.table",
+            "public.test",
+            "1test",
+            "_test",
+            "tést",
+            "test\0",
+        ];
+
+        for name in hostile_names {
+            assert!(
+                KnowledgeBaseName::parse(name).is_err(),
+                "{name:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_knowledge_base_name_rejects_reserved_names() {
+        for name in [SEARCH_TABLES, INGESTION_JOBS, CHAT_SESSIONS, CHAT_MESSAGES, "select", "user", "pg_catalog", "other_content"] {
+            assert!(
+                KnowledgeBaseName::parse(name).is_err(),
+                "{name:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_knowledge_base_name_rejects_index_names_on_creation() {
+        for name in ["docs_tsv_idx", "docs_embedding_idx", "docs_rebuild_idx"] {
+            assert!(
+                KnowledgeBaseName::parse_new(name).is_err(),
+                "{name:?} should be rejected"
+            );
+
+            // Knowledge bases created before the rule stay usable.
+            assert!(KnowledgeBaseName::parse(name).is_ok());
+        }
+
+        assert!(KnowledgeBaseName::parse_new("orders_idx").is_ok());
+        assert!(KnowledgeBaseName::parse_new("  Docs ").is_ok());
+    }
+
+    #[test]
+    fn test_knowledge_base_name_length() {
+        let longest = "a".repeat(MAX_KNOWLEDGE_BASE_NAME_LEN);
+
+        assert!(KnowledgeBaseName::parse(&longest).is_ok());
+        assert!(KnowledgeBaseName::parse(&(longest + "a")).is_err());
+    }
+
+    #[test]
+    fn test_list_ingestion_jobs_sql() {
+        let expected_sql = format!("SELECT * FROM ingestion_jobs WHERE ($1::text IS NULL OR table_name = $1) ORDER BY created_at DESC LIMIT {MAX_LISTED_JOBS}");
+
+        assert_eq!(list_ingestion_jobs_sql(), expected_sql);
+    }
+
+    #[test]
+    fn test_update_ingestion_job_sql() {
+        let expected_sql = "UPDATE ingestion_jobs SET status = $2, chunk_count = $3, error = $4, updated_at = now() WHERE id = $1";
+
+        assert_eq!(update_ingestion_job_sql(), expected_sql);
+    }
+
+    #[test]
+    fn test_create_full_text_index_sql() {
+        let table_name = table_name();
+
+        let expected_sql =
+            "CREATE INDEX IF NOT EXISTS \"test_table_tsv_idx\" ON \"test_table\" USING GIN (content_tsv)";
+
+        assert_eq!(create_full_text_index_sql(&table_name), expected_sql);
+    }
+
+    #[test]
+    fn test_get_unmigrated_vector_tables_sql() {
+        let expected_sql = "SELECT table_name FROM search_tables WHERE EXISTS (SELECT 1 FROM information_schema.tables t WHERE t.table_schema = current_schema() AND t.table_name = search_tables.table_name) AND NOT EXISTS (SELECT 1 FROM information_schema.columns c WHERE c.table_schema = current_schema() AND c.table_name = search_tables.table_name AND c.column_name = 'content_tsv')";
+
+        assert_eq!(get_unmigrated_vector_tables_sql(), expected_sql);
+    }
+
+    #[test]
+    fn test_migrate_vector_table_sql() {
+        let table_name = table_name();
+
+        let migrations = migrate_vector_table_sql(&table_name);
+
+        assert_eq!(migrations.len(), 2);
+        assert!(migrations[0].starts_with(
+            "ALTER TABLE \"test_table\" ADD COLUMN IF NOT EXISTS content_tsv tsvector"
+        ));
+        assert_eq!(migrations[1], create_full_text_index_sql(&table_name));
+    }
+
+    #[test]
+    fn test_get_full_text_result_query() {
+        let table_name = table_name();
+
+        let expected_sql = "SELECT *, CAST(ts_rank_cd(content_tsv, websearch_to_tsquery('english', $1)) AS FLOAT8) as score FROM \"test_table\" WHERE content_tsv @@ websearch_to_tsquery('english', $1) ORDER BY score DESC LIMIT 5";
+
+        assert_eq!(get_full_text_result_query(&table_name, 5, ""), expected_sql);
+    }
+
+    #[test]
+    fn test_get_hybrid_result_query() {
+        let table_name = table_name();
+
+        let sql = get_hybrid_result_query(&table_name, DistanceMetric::Cosine, 5, 0.5, "");
+
+        assert!(sql.contains("WHERE (1.0-(embedding <=> $1::vector)) >=0.5"));
+        assert!(sql.contains("content_tsv @@ websearch_to_tsquery('english', $2)"));
+        assert!(sql.contains(&format!("LIMIT {}", 5 * HYBRID_CANDIDATE_FACTOR)));
+        assert!(sql.contains(&format!("1.0/({RRF_K}+v.rank)")));
+        assert!(sql.ends_with("ORDER BY fused.score DESC LIMIT 5"));
+    }
+
+    #[test]
+    fn test_search_mode_parse() {
+        assert_eq!(SearchMode::default(), SearchMode::Vector);
+
+        for mode in [SearchMode::Vector, SearchMode::FullText, SearchMode::Hybrid] {
+            assert_eq!(SearchMode::parse(mode.as_str()).unwrap(), mode);
+        }
+
+        assert!(SearchMode::parse("keyword").is_err());
+        assert!(!SearchMode::FullText.uses_embedding());
+    }
+
+    #[test]
+    fn test_create_vector_index_sql() {
+        let hnsw = VectorIndex::Hnsw {
+            m: 16,
+            ef_construction: 64,
+        };
+
+        assert_eq!(
+            create_vector_index_sql(&table_name(), DistanceMetric::Cosine, &hnsw),
+            "CREATE INDEX IF NOT EXISTS \"test_table_embedding_idx\" ON \"test_table\" USING hnsw (embedding vector_cosine_ops) WITH (m = 16, ef_construction = 64)"
+        );
+
+        assert_eq!(
+            create_vector_index_sql(
+                &table_name(),
+                DistanceMetric::InnerProduct,
+                &VectorIndex::IvfFlat { lists: 100 }
+            ),
+            "CREATE INDEX IF NOT EXISTS \"test_table_embedding_idx\" ON \"test_table\" USING ivfflat (embedding vector_ip_ops) WITH (lists = 100)"
+        );
+
+        assert_eq!(
+            drop_vector_index_sql(&table_name()),
+            "DROP INDEX IF EXISTS \"test_table_embedding_idx\""
+        );
+
+        assert_eq!(
+            rebuild_vector_index_sql(&table_name(), DistanceMetric::L2, &hnsw),
+            "CREATE INDEX CONCURRENTLY \"test_table_rebuild_idx\" ON \"test_table\" USING hnsw (embedding vector_l2_ops) WITH (m = 16, ef_construction = 64)"
+        );
+
+        assert_eq!(
+            drop_rebuilt_vector_index_sql(&table_name()),
+            "DROP INDEX CONCURRENTLY IF EXISTS \"test_table_rebuild_idx\""
+        );
+
+        assert_eq!(
+            rename_rebuilt_vector_index_sql(&table_name()),
+            "ALTER INDEX \"test_table_rebuild_idx\" RENAME TO \"test_table_embedding_idx\""
+        );
+    }
+
+    #[test]
+    fn test_vector_index_validation() {
+        let hnsw: VectorIndex = serde_json::from_str(r#"{"type": "hnsw"}"#).unwrap();
+
+        assert_eq!(
+            hnsw,
+            VectorIndex::Hnsw {
+                m: HNSW_DEFAULT_M,
+                ef_construction: HNSW_DEFAULT_EF_CONSTRUCTION
+            }
+        );
+        assert!(hnsw.validate(EMBEDDING_DIMENSION).is_ok());
+        assert!(hnsw.validate(MAX_INDEXED_DIMENSION + 1).is_err());
+
+        let too_small_ef = VectorIndex::Hnsw {
+            m: 32,
+            ef_construction: 40,
+        };
+        assert!(too_small_ef.validate(EMBEDDING_DIMENSION).is_err());
+
+        assert!(VectorIndex::IvfFlat { lists: 0 }
+            .validate(EMBEDDING_DIMENSION)
+            .is_err());
+    }
+
+    #[test]
+    fn test_search_tuning_settings() {
+        assert!(SearchTuning::default().settings().is_empty());
+
+        let tuning = SearchTuning {
+            ef_search: Some(100),
+            probes: Some(10),
+        };
+
+        assert!(tuning.validate().is_ok());
+        assert_eq!(
+            tuning.settings(),
+            vec![
+                ("hnsw.ef_search", "100".to_string()),
+                ("ivfflat.probes", "10".to_string())
+            ]
+        );
+
+        let invalid = SearchTuning {
+            ef_search: Some(0),
+            probes: None,
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_chat_history_sql() {
+        assert_eq!(
+            upsert_chat_session_sql(),
+            "INSERT INTO chat_sessions (id) VALUES ($1) ON CONFLICT (id) DO UPDATE SET updated_at = now()"
+        );
+
+        assert_eq!(
+            insert_chat_message_sql(),
+            "INSERT INTO chat_messages (session_id, role, content) VALUES ($1, $2, $3)"
+        );
+
+        assert_eq!(
+            get_recent_chat_messages_sql(),
+            format!("SELECT * FROM (SELECT id, role, content, created_at FROM chat_messages WHERE session_id = $1 ORDER BY id DESC LIMIT {MAX_HISTORY_MESSAGES}) recent ORDER BY id ASC")
+        );
+
+        assert!(create_chat_messages_sql()
+            .contains("REFERENCES chat_sessions (id) ON DELETE CASCADE"));
+
+        assert_eq!(
+            delete_chat_session_sql(),
+            "DELETE FROM chat_sessions WHERE id = $1"
+        );
+    }
 }
@@ -1,6 +1,6 @@
 #[cfg(test)]
 mod text_splitter_text {
-    use crate::utils::text_splitter::TextSplitter;
+    use crate::utils::text_splitter::{split_chunks, TextSplitter};
     #[test]
     fn test_split_20_5() {
         let some_long_text: String = "A story about a boy".to_string();
@@ -81,4 +81,67 @@ mod text_splitter_text {
             .split(&some_long_text)
             .is_empty());
     }
+
+    fn starts_and_lengths(chunks: &[(usize, String)]) -> Vec<(usize, usize)> {
+        chunks
+            .iter()
+            .map(|(start, chunk)| (*start, chunk.chars().count()))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_chunks_ascii() {
+        let text = "a".repeat(3000);
+        let chunks = split_chunks(&text, 1000, 100);
+
+        assert_eq!(
+            starts_and_lengths(&chunks),
+            vec![(0, 1100), (1000, 1100), (2000, 1000)]
+        );
+    }
+
+    #[test]
+    fn test_split_chunks_non_ascii() {
+        let text = "é€a".repeat(10);
+        let chunks = split_chunks(&text, 4, 2);
+
+        // Offsets are bytes: é is 2 bytes, € is 3.
+        assert_eq!(chunks[0], (0, "é€aé€a".to_owned()));
+        assert_eq!(chunks[1], (8, "€aé€aé".to_owned()));
+        assert_eq!(chunks.len(), 8);
+
+        for (start, chunk) in &chunks {
+            assert!(text[*start..].starts_with(chunk.as_str()));
+        }
+
+        // Every character is in a chunk exactly once without the overlap.
+        let joined: String = chunks
+            .iter()
+            .map(|(_, chunk)| chunk.chars().take(4).collect::<String>())
+            .collect();
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn test_split_chunks_short_text() {
+        assert_eq!(
+            split_chunks("short", 1000, 100),
+            vec![(0, "short".to_owned())]
+        );
+        assert_eq!(split_chunks("abc", 10, 100), vec![(0, "abc".to_owned())]);
+        assert!(split_chunks("", 10, 2).is_empty());
+    }
+
+    #[test]
+    fn test_split_chunks_overlap_larger_than_chunk() {
+        assert_eq!(
+            split_chunks("abcde", 2, 100),
+            vec![
+                (0, "abcde".to_owned()),
+                (2, "cde".to_owned()),
+                (4, "e".to_owned())
+            ]
+        );
+        assert_eq!(split_chunks("abc", 0, 0).len(), 3);
+    }
 }
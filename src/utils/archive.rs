@@ -0,0 +1,299 @@
+use crate::utils::extractors::{detect_extractor, DocumentExtractor};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+/// What one uploaded archive may expand to, nested archives included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArchiveLimits {
+    pub max_entries: usize,
+    pub max_bytes: u64,
+    /// The uploaded archive is level 1.
+    pub max_depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let file_name = file_name.rsplit('/').next()?.to_lowercase();
+
+        if file_name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if file_name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        if head.starts_with(b"PK\x03\x04") {
+            Some(Self::Zip)
+        } else if head.starts_with(b"\x1f\x8b") {
+            Some(Self::TarGz)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// How an uploaded file is learned.
+#[derive(Clone)]
+pub enum UploadFormat {
+    Document(Arc<dyn DocumentExtractor>),
+    Archive(ArchiveKind),
+}
+
+/// An archive by extension, then a document format, then an archive by
+/// signature, so ZIP based documents like DOCX aren't expanded.
+pub fn detect_upload(
+    file_name: &str,
+    content_type: Option<&str>,
+    head: &[u8],
+) -> Option<UploadFormat> {
+    if let Some(kind) = ArchiveKind::from_file_name(file_name) {
+        return Some(UploadFormat::Archive(kind));
+    }
+
+    detect_extractor(file_name, content_type, head)
+        .map(UploadFormat::Document)
+        .or_else(|| ArchiveKind::sniff(head).map(UploadFormat::Archive))
+}
+
+/// A file expanded from an archive, `path` includes the nested archives it's in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveMember {
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Expanded from an archive, in the order of the archive.
+#[derive(Debug)]
+pub enum ArchiveEntry {
+    File(ArchiveMember),
+    /// A nested archive that couldn't be read, the files before the error were expanded.
+    Unreadable {
+        path: String,
+        error: anyhow::Error,
+    },
+}
+
+/// The upload goes past its `ArchiveLimits`, nothing of it is learned.
+#[derive(Debug)]
+pub struct ArchiveLimitExceeded(String);
+
+impl fmt::Display for ArchiveLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ArchiveLimitExceeded {}
+
+/// Entries and bytes left for the whole upload.
+struct Budget {
+    limits: ArchiveLimits,
+    entries: usize,
+    bytes: u64,
+}
+
+impl Budget {
+    fn take_entry(&mut self) -> Result<()> {
+        if self.entries == self.limits.max_entries {
+            return Err(ArchiveLimitExceeded(format!(
+                "Archive has more than {} files",
+                self.limits.max_entries
+            ))
+            .into());
+        }
+
+        self.entries += 1;
+
+        Ok(())
+    }
+
+    /// Reads `reader` to the end, failing once the archive expands past `max_bytes`.
+    fn read(&mut self, reader: impl Read, path: &str) -> Result<Vec<u8>> {
+        let remaining = self.limits.max_bytes - self.bytes;
+        let mut bytes = Vec::new();
+
+        reader
+            .take(remaining + 1)
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("Unable to read {path}"))?;
+
+        if bytes.len() as u64 > remaining {
+            return Err(ArchiveLimitExceeded(format!(
+                "Archive expands to more than {} bytes",
+                self.limits.max_bytes
+            ))
+            .into());
+        }
+
+        self.bytes += bytes.len() as u64;
+
+        Ok(bytes)
+    }
+}
+
+/// Relative path of an entry, `None` for paths leaving the archive.
+fn entry_path(path: &Path) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Hidden files and the metadata macOS adds to archives.
+fn is_hidden(path: &str) -> bool {
+    path.split('/')
+        .any(|part| part.starts_with('.') || part == "__MACOSX")
+}
+
+/// Passes the files of the archive to `on_entry` one at a time, with nested
+/// archives expanded in their place. Directories, links and hidden files are
+/// skipped. Going past `limits` fails the whole archive, nested archives that
+/// can't be read are passed on as [`ArchiveEntry::Unreadable`].
+pub fn expand_archive(
+    kind: ArchiveKind,
+    bytes: &[u8],
+    limits: ArchiveLimits,
+    mut on_entry: impl FnMut(ArchiveEntry) -> Result<()>,
+) -> Result<()> {
+    let mut budget = Budget {
+        limits,
+        entries: 0,
+        bytes: 0,
+    };
+
+    expand_into(kind, bytes, "", 1, &mut budget, &mut on_entry)
+}
+
+fn expand_into(
+    kind: ArchiveKind,
+    bytes: &[u8],
+    prefix: &str,
+    depth: usize,
+    budget: &mut Budget,
+    on_entry: &mut dyn FnMut(ArchiveEntry) -> Result<()>,
+) -> Result<()> {
+    if depth > budget.limits.max_depth {
+        return Err(ArchiveLimitExceeded(format!(
+            "Archives are nested more than {} levels deep at {}",
+            budget.limits.max_depth,
+            prefix.trim_end_matches('/')
+        ))
+        .into());
+    }
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+            for index in 0..archive.len() {
+                let file = archive.by_index(index)?;
+
+                if !file.is_file() {
+                    continue;
+                }
+
+                budget.take_entry()?;
+
+                let Some(path) = file.enclosed_name().as_deref().and_then(entry_path) else {
+                    continue;
+                };
+
+                if is_hidden(&path) {
+                    continue;
+                }
+
+                let path = format!("{prefix}{path}");
+                let bytes = budget.read(file, &path)?;
+
+                expand_file(path, bytes, depth, budget, on_entry)?;
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let reader: Box<dyn Read + '_> = match kind {
+                ArchiveKind::TarGz => Box::new(GzDecoder::new(bytes)),
+                _ => Box::new(bytes),
+            };
+            let mut archive = tar::Archive::new(reader);
+
+            for entry in archive.entries()? {
+                let entry = entry?;
+
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+
+                budget.take_entry()?;
+
+                let Some(path) = entry_path(&entry.path()?) else {
+                    continue;
+                };
+
+                if is_hidden(&path) {
+                    continue;
+                }
+
+                let path = format!("{prefix}{path}");
+                let bytes = budget.read(entry, &path)?;
+
+                expand_file(path, bytes, depth, budget, on_entry)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn expand_file(
+    path: String,
+    bytes: Vec<u8>,
+    depth: usize,
+    budget: &mut Budget,
+    on_entry: &mut dyn FnMut(ArchiveEntry) -> Result<()>,
+) -> Result<()> {
+    let Some(UploadFormat::Archive(kind)) = detect_upload(&path, None, &bytes) else {
+        return on_entry(ArchiveEntry::File(ArchiveMember { path, bytes }));
+    };
+
+    let prefix = format!("{path}/");
+
+    match expand_into(kind, &bytes, &prefix, depth + 1, budget, on_entry) {
+        Err(error) if !error.is::<ArchiveLimitExceeded>() => {
+            on_entry(ArchiveEntry::Unreadable { path, error })
+        }
+        result => result,
+    }
+}
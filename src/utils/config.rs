@@ -1,8 +1,21 @@
+use crate::utils::archive::ArchiveLimits;
+use crate::utils::constants::{
+    DEFAULT_LOCAL_MODEL, MAX_ARCHIVE_DEPTH, MAX_ARCHIVE_ENTRIES, MAX_ARCHIVE_SIZE,
+    MAX_POOL_CONNECTION,
+};
+use crate::utils::local_models::{ChatTemplate, LocalArchitecture};
 use crate::utils::vars;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub const SUPPORTED_EMBEDDING_MODELS: [&str; 2] = ["local", "azure"];
+
+static APP_CONFIG: OnceLock<AppConfig<'static>> = OnceLock::new();
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AppConfig<'a> {
@@ -11,6 +24,12 @@ pub struct AppConfig<'a> {
     pub azure_config_llm_inferencing: AzureConfigLLMInferencing<'a>,
     pub azure_config_slm_inferencing: AzureConfigSLMInferencing<'a>,
     pub local_embedding_config: LocalEmbeddingConfig<'a>,
+    #[serde(default = "AzureEmbeddingConfig::default")]
+    pub azure_embedding_config: AzureEmbeddingConfig<'a>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deployments: Vec<DeploymentConfig<'a>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_models: Vec<LocalModelConfig<'a>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -18,6 +37,31 @@ pub struct AppSettings<'a> {
     pub maximum_upload_size: u32,
     pub embedding_model: Cow<'a, str>,
     pub inferencing_model: Cow<'a, str>,
+    #[serde(default = "default_max_pool_connection")]
+    pub max_pool_connection: u32,
+    #[serde(default = "default_max_archive_entries")]
+    pub max_archive_entries: usize,
+    /// In MB, uncompressed.
+    #[serde(default = "default_max_archive_size")]
+    pub max_archive_size: u32,
+    #[serde(default = "default_max_archive_depth")]
+    pub max_archive_depth: usize,
+}
+
+fn default_max_pool_connection() -> u32 {
+    MAX_POOL_CONNECTION
+}
+
+fn default_max_archive_entries() -> usize {
+    MAX_ARCHIVE_ENTRIES
+}
+
+fn default_max_archive_size() -> u32 {
+    MAX_ARCHIVE_SIZE
+}
+
+fn default_max_archive_depth() -> usize {
+    MAX_ARCHIVE_DEPTH
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -51,6 +95,110 @@ pub struct AzureEmbeddingConfig<'a> {
     pub dimension: Option<u32>,
 }
 
+impl<'a> AzureEmbeddingConfig<'a> {
+    pub fn default() -> Self {
+        Self {
+            resource_name: Cow::Borrowed("openai"),
+            api_version: Cow::Borrowed("2024-02-01"),
+            deployment_id: Cow::Borrowed("text-embedding-ada-002"),
+            embedding_model: Cow::Borrowed("text-embedding-ada-002"),
+            dimension: None,
+        }
+    }
+}
+
+/// Named chat deployment selected by `deployment_type` in the websocket message.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeploymentConfig<'a> {
+    pub name: Cow<'a, str>,
+    #[serde(flatten)]
+    pub backend: DeploymentBackend<'a>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u16>,
+    /// Prompt and answer tokens the model accepts, defaults per backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum DeploymentBackend<'a> {
+    /// Runs one of the `local_models`, `model` is the default when the request doesn't name one.
+    Local {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<Cow<'a, str>>,
+    },
+    Azure {
+        resource_name: Cow<'a, str>,
+        api_version: Cow<'a, str>,
+        deployment_id: Cow<'a, str>,
+        inferencing_model: Cow<'a, str>,
+    },
+    /// Plain OpenAI chat completions API at `base_url`, with the key read from `api_key_env`.
+    #[serde(rename = "openai")]
+    OpenAI {
+        base_url: Cow<'a, str>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key_env: Option<Cow<'a, str>>,
+        inferencing_model: Cow<'a, str>,
+    },
+}
+
+/// Quantized GGUF chat model run by `local` deployments, selected by `deployment_model`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LocalModelConfig<'a> {
+    pub name: Cow<'a, str>,
+    pub architecture: LocalArchitecture,
+    /// Directory holding `tokenizer.json` and the weights, `PHI2_QUANTIZED_PATH` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<Cow<'a, str>>,
+    /// GGUF file name inside `path`.
+    pub weights: Cow<'a, str>,
+    /// Built-in template, overrides the `chat_template` of `tokenizer_config.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<ChatTemplate>,
+    /// Tokens that stop generation, replacing the template and tokenizer defaults.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub eos_tokens: Vec<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<usize>,
+}
+
+impl LocalModelConfig<'_> {
+    pub fn model_dir(&self) -> Result<PathBuf> {
+        match &self.path {
+            Some(path) => Ok(PathBuf::from(path.as_ref())),
+            None => vars::get_inferencing_model_path(),
+        }
+    }
+
+    pub fn chat_template(&self) -> ChatTemplate {
+        self.chat_template
+            .unwrap_or_else(|| self.architecture.chat_template())
+    }
+
+    /// Configured end of turn tokens, or the defaults of the chat template.
+    pub fn eos_tokens(&self) -> Vec<String> {
+        if self.eos_tokens.is_empty() {
+            self.chat_template()
+                .eos_tokens()
+                .iter()
+                .map(|token| token.to_string())
+                .collect()
+        } else {
+            self.eos_tokens
+                .iter()
+                .map(|token| token.to_string())
+                .collect()
+        }
+    }
+
+    pub fn context_window(&self) -> usize {
+        self.context_window
+            .unwrap_or_else(|| self.architecture.context_window())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LocalEmbeddingConfig<'a> {
     pub embedding_model: Cow<'a, str>,
@@ -64,9 +212,13 @@ impl<'a> AppConfig<'a> {
                 maximum_upload_size: 100,
                 embedding_model: Cow::Borrowed("local"),
                 inferencing_model: Cow::Borrowed("local"),
+                max_pool_connection: MAX_POOL_CONNECTION,
+                max_archive_entries: MAX_ARCHIVE_ENTRIES,
+                max_archive_size: MAX_ARCHIVE_SIZE,
+                max_archive_depth: MAX_ARCHIVE_DEPTH,
             },
             web_config: WebSettings {
-                ip_address: Cow::Borrowed("127.0.0.1"),
+                ip_address: Cow::Borrowed("0.0.0.0"),
                 port: 8000,
             },
             azure_config_llm_inferencing: AzureConfigLLMInferencing {
@@ -85,16 +237,256 @@ impl<'a> AppConfig<'a> {
                 embedding_model: Cow::Borrowed("BAAI_V1.5L"),
                 dimension: 1024,
             },
+            azure_embedding_config: AzureEmbeddingConfig::default(),
+            deployments: Vec::new(),
+            local_models: Vec::new(),
         }
     }
     pub fn save(&self) -> std::io::Result<()> {
         let config = toml::to_string(&self).unwrap();
         std::fs::write(vars::get_app_config_path(), config)
     }
+    /// Defaults when `APP_CONFIG_PATH` isn't set, an error when it can't be read.
     pub fn load() -> Result<Self> {
-        match std::fs::read_to_string(vars::get_app_config_path()) {
-            Ok(config) => Ok(toml::from_str(&config)?),
-            Err(_) => Ok(AppConfig::default()),
+        let Some(config_path) = vars::try_get_app_config_path() else {
+            return Ok(AppConfig::default());
+        };
+
+        let config = std::fs::read_to_string(&config_path)
+            .with_context(|| format!("Can't read the config file {}", config_path.display()))?;
+
+        if config.trim().is_empty() {
+            return Ok(AppConfig::default());
+        }
+
+        toml::from_str(&config)
+            .with_context(|| format!("Invalid config file {}", config_path.display()))
+    }
+
+    /// Apply `APP_*` environment variables on top of the loaded values.
+    pub fn with_env_overrides(mut self) -> Result<Self> {
+        if let Some(ip_address) = vars::get_env_override(vars::APP_IP_ADDRESS) {
+            self.web_config.ip_address = Cow::Owned(ip_address);
+        }
+        if let Some(port) = vars::get_env_override(vars::APP_PORT) {
+            self.web_config.port = port
+                .parse()
+                .with_context(|| format!("Invalid {}: {port}", vars::APP_PORT))?;
+        }
+        if let Some(upload_size) = vars::get_env_override(vars::APP_MAXIMUM_UPLOAD_SIZE) {
+            self.app_config.maximum_upload_size = upload_size.parse().with_context(|| {
+                format!("Invalid {}: {upload_size}", vars::APP_MAXIMUM_UPLOAD_SIZE)
+            })?;
+        }
+        if let Some(pool_size) = vars::get_env_override(vars::APP_MAX_POOL_CONNECTION) {
+            self.app_config.max_pool_connection = pool_size.parse().with_context(|| {
+                format!("Invalid {}: {pool_size}", vars::APP_MAX_POOL_CONNECTION)
+            })?;
+        }
+        if let Some(embedding_model) = vars::get_env_override(vars::APP_EMBEDDING_MODEL) {
+            self.app_config.embedding_model = Cow::Owned(embedding_model);
+        }
+        if let Some(inferencing_model) = vars::get_env_override(vars::APP_INFERENCING_MODEL) {
+            self.app_config.inferencing_model = Cow::Owned(inferencing_model);
+        }
+
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.ip_address()?;
+
+        if self.web_config.port == 0 {
+            anyhow::bail!("web_config.port must be greater than 0");
         }
+        if self.app_config.maximum_upload_size == 0 {
+            anyhow::bail!("app_config.maximum_upload_size must be greater than 0");
+        }
+        if self.app_config.max_pool_connection == 0 {
+            anyhow::bail!("app_config.max_pool_connection must be greater than 0");
+        }
+        if self.app_config.max_archive_entries == 0 {
+            anyhow::bail!("app_config.max_archive_entries must be greater than 0");
+        }
+        if self.app_config.max_archive_size == 0 {
+            anyhow::bail!("app_config.max_archive_size must be greater than 0");
+        }
+        if self.app_config.max_archive_depth == 0 {
+            anyhow::bail!("app_config.max_archive_depth must be greater than 0");
+        }
+        if !SUPPORTED_EMBEDDING_MODELS.contains(&self.app_config.embedding_model.as_ref()) {
+            anyhow::bail!(
+                "Unsupported app_config.embedding_model: {}, expected one of {:?}",
+                self.app_config.embedding_model,
+                SUPPORTED_EMBEDDING_MODELS
+            );
+        }
+        if self.local_embedding_config.dimension == 0 {
+            anyhow::bail!("local_embedding_config.dimension must be greater than 0");
+        }
+        for local_model in &self.local_models {
+            if local_model.name.trim().is_empty() {
+                anyhow::bail!("local_models entries need a name");
+            }
+            if local_model.weights.trim().is_empty() {
+                anyhow::bail!("local_models.{} needs a weights file", local_model.name);
+            }
+            if local_model.context_window == Some(0) {
+                anyhow::bail!(
+                    "local_models.{} context_window must be greater than 0",
+                    local_model.name
+                );
+            }
+        }
+        for deployment in self.deployments() {
+            if let DeploymentBackend::Local { model: Some(model) } = &deployment.backend {
+                if self.local_model(model).is_none() {
+                    anyhow::bail!(
+                        "Unknown local model {model} in deployment {}, expected one of {:?}",
+                        deployment.name,
+                        self.local_model_names()
+                    );
+                }
+            }
+        }
+        if self.deployment(&self.app_config.inferencing_model).is_none() {
+            anyhow::bail!(
+                "Unknown app_config.inferencing_model: {}, expected one of {:?}",
+                self.app_config.inferencing_model,
+                self.deployment_names()
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn ip_address(&self) -> Result<IpAddr> {
+        self.web_config
+            .ip_address
+            .parse()
+            .with_context(|| format!("Invalid web_config.ip_address: {}", self.web_config.ip_address))
+    }
+
+    /// Built-in `local` and `azure` deployments, overridden by `deployments` entries of the same name.
+    pub fn deployments(&self) -> Vec<DeploymentConfig<'_>> {
+        let azure_config = &self.azure_config_llm_inferencing;
+
+        let built_in = [
+            DeploymentConfig {
+                name: Cow::Borrowed("local"),
+                backend: DeploymentBackend::Local { model: None },
+                max_tokens: None,
+                context_window: None,
+            },
+            DeploymentConfig {
+                name: Cow::Borrowed("azure"),
+                backend: DeploymentBackend::Azure {
+                    resource_name: Cow::Borrowed(&azure_config.resource_name),
+                    api_version: Cow::Borrowed(&azure_config.api_version),
+                    deployment_id: Cow::Borrowed(&azure_config.deployment_id),
+                    inferencing_model: Cow::Borrowed(&azure_config.inferencing_model),
+                },
+                max_tokens: None,
+                context_window: None,
+            },
+        ];
+
+        let mut deployments: Vec<DeploymentConfig<'_>> = built_in
+            .into_iter()
+            .filter(|built_in| {
+                !self
+                    .deployments
+                    .iter()
+                    .any(|deployment| deployment.name.eq_ignore_ascii_case(&built_in.name))
+            })
+            .collect();
+
+        deployments.extend(self.deployments.iter().cloned());
+
+        deployments
+    }
+
+    /// Deployment by case-insensitive name.
+    pub fn deployment(&self, name: &str) -> Option<DeploymentConfig<'_>> {
+        self.deployments()
+            .into_iter()
+            .find(|deployment| deployment.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn deployment_names(&self) -> Vec<String> {
+        self.deployments()
+            .into_iter()
+            .map(|deployment| deployment.name.into_owned())
+            .collect()
+    }
+
+    /// Built-in `phi2` model from `PHI2_QUANTIZED_PATH`, overridden by `local_models` entries of the same name.
+    pub fn local_models(&self) -> Vec<LocalModelConfig<'_>> {
+        let built_in = LocalModelConfig {
+            name: Cow::Borrowed(DEFAULT_LOCAL_MODEL),
+            architecture: LocalArchitecture::Phi2,
+            path: None,
+            weights: Cow::Borrowed("model-q4k.gguf"),
+            chat_template: None,
+            eos_tokens: Vec::new(),
+            context_window: None,
+        };
+
+        let mut local_models: Vec<LocalModelConfig<'_>> = Vec::new();
+
+        if !self
+            .local_models
+            .iter()
+            .any(|local_model| local_model.name.eq_ignore_ascii_case(&built_in.name))
+        {
+            local_models.push(built_in);
+        }
+
+        local_models.extend(self.local_models.iter().cloned());
+
+        local_models
+    }
+
+    /// Local model by case-insensitive name.
+    pub fn local_model(&self, name: &str) -> Option<LocalModelConfig<'_>> {
+        self.local_models()
+            .into_iter()
+            .find(|local_model| local_model.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn local_model_names(&self) -> Vec<String> {
+        self.local_models()
+            .into_iter()
+            .map(|local_model| local_model.name.into_owned())
+            .collect()
+    }
+
+    /// Maximum multipart upload size in bytes.
+    pub fn maximum_upload_bytes(&self) -> u64 {
+        1024 * 1024 * self.app_config.maximum_upload_size as u64
+    }
+
+    pub fn archive_limits(&self) -> ArchiveLimits {
+        ArchiveLimits {
+            max_entries: self.app_config.max_archive_entries,
+            max_bytes: 1024 * 1024 * self.app_config.max_archive_size as u64,
+            max_depth: self.app_config.max_archive_depth,
+        }
+    }
+}
+
+impl AppConfig<'static> {
+    /// Load, override and validate the config, then make it globally available.
+    pub fn init() -> Result<&'static Self> {
+        let config = AppConfig::load()?.with_env_overrides()?;
+
+        config.validate()?;
+
+        Ok(APP_CONFIG.get_or_init(|| config))
+    }
+
+    /// Config set by `init`, or the defaults when the server wasn't booted through `main`.
+    pub fn global() -> &'static Self {
+        APP_CONFIG.get_or_init(AppConfig::default)
     }
 }
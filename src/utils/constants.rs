@@ -1,6 +1,76 @@
+use crate::utils::knowledge_base::{DistanceMetric, KnowledgeBaseName, VectorIndex};
+
 pub const SEARCH_TABLES: &'static str = "search_tables";
+pub const INGESTION_JOBS: &'static str = "ingestion_jobs";
+pub const CHAT_SESSIONS: &'static str = "chat_sessions";
+pub const CHAT_MESSAGES: &'static str = "chat_messages";
 pub const EMBEDDING_DIMENSION: usize = 1024;
+pub const EMBEDDING_BATCH_SIZE: usize = 32;
+pub const AZURE_EMBEDDING_DIMENSION: usize = 1536;
 pub const MAX_POOL_CONNECTION: u32 = 20;
+pub const MAX_KNOWLEDGE_BASE_NAME_LEN: usize = 48;
+pub const DEFAULT_CHUNK_SIZE: usize = 1000;
+pub const DEFAULT_CHUNK_OVERLAP: usize = 150;
+pub const MIN_CHUNK_SIZE: usize = 100;
+pub const MAX_CHUNK_SIZE: usize = 8000;
+/// Embedding model of knowledge bases created before it was recorded in `search_tables`.
+pub const LEGACY_EMBEDDING_MODEL_ID: &'static str = "local:BAAI_V1.5L";
+pub const RESERVED_TABLE_NAMES: [&'static str; 4] =
+    [SEARCH_TABLES, INGESTION_JOBS, CHAT_SESSIONS, CHAT_MESSAGES];
+/// Indexes of a knowledge base are named `{table}_{kind}_idx`.
+pub const GENERATED_INDEX_KINDS: [&str; 3] = ["tsv", "embedding", "rebuild"];
+pub const MAX_LISTED_JOBS: usize = 100;
+pub const MAX_LISTED_SESSIONS: usize = 100;
+/// Most recent messages of a session sent to the model with a new question.
+pub const MAX_HISTORY_MESSAGES: usize = 20;
+pub const MAX_SESSION_ID_LEN: usize = 128;
+/// Text search configuration used for `content_tsv` and the queries against it.
+pub const FULL_TEXT_CONFIG: &'static str = "english";
+/// Rank offset of reciprocal rank fusion, `1 / (RRF_K + rank)`.
+pub const RRF_K: usize = 60;
+/// Each ranking contributes `limit * HYBRID_CANDIDATE_FACTOR` candidates to the fusion.
+pub const HYBRID_CANDIDATE_FACTOR: usize = 4;
+/// Candidates fetched per kept result when reranking and no count is requested.
+pub const RERANK_CANDIDATE_FACTOR: usize = 4;
+pub const MAX_RERANK_CANDIDATES: usize = 100;
+/// Largest `vector` dimension pgvector's HNSW and IVFFlat indexes accept.
+pub const MAX_INDEXED_DIMENSION: usize = 2000;
+pub const HNSW_DEFAULT_M: u32 = 16;
+pub const HNSW_DEFAULT_EF_CONSTRUCTION: u32 = 64;
+pub const IVFFLAT_DEFAULT_LISTS: u32 = 100;
+pub const MAX_FILTER_CONDITIONS: usize = 64;
+/// Bytes read from the start of an upload to detect its format.
+pub const SNIFF_BYTES: usize = 8192;
+/// Records of a structured upload embedded and written at a time.
+pub const INSERT_BATCH_DOCUMENTS: usize = 256;
+/// Files an uploaded archive may hold, nested archives included.
+pub const MAX_ARCHIVE_ENTRIES: usize = 1000;
+/// Uncompressed MB an uploaded archive may expand to.
+pub const MAX_ARCHIVE_SIZE: u32 = 64;
+/// Levels of archives inside archives, the uploaded one is level 1.
+pub const MAX_ARCHIVE_DEPTH: usize = 2;
+/// Context window of the quantized PHI-2 model, prompt and answer together.
+pub const PHI2_CONTEXT_WINDOW: usize = 2048;
+/// Local model used when neither the deployment nor the request names one.
+pub const DEFAULT_LOCAL_MODEL: &str = "phi2";
+/// Sampling defaults of local models when the request doesn't set them.
+pub const DEFAULT_SEED: u64 = 12345;
+pub const DEFAULT_TEMPERATURE: f64 = 0.7;
+pub const DEFAULT_REPEAT_PENALTY: f32 = 1.1;
+pub const DEFAULT_REPEAT_LAST_N: usize = 64;
+pub const MAX_GENERATION_TOKENS: usize = 8192;
+/// The OpenAI API accepts at most 4 stop sequences.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+/// Questions one websocket connection can have answered at the same time.
+pub const MAX_ANSWERS_PER_CONNECTION: usize = 2;
+/// Context window assumed for remote deployments without `context_window`.
+pub const DEFAULT_REMOTE_CONTEXT_WINDOW: usize = 8192;
+/// Tokens taken by the role and delimiters around every message.
+pub const MESSAGE_TOKEN_OVERHEAD: usize = 8;
+/// Share of the prompt left after the system message and question given to references, the rest goes to history.
+pub const REFERENCE_BUDGET_PERCENT: usize = 75;
+/// A reference is only truncated to fit when at least this many tokens are left for it.
+pub const MIN_REFERENCE_TOKENS: usize = 64;
 pub const CREATE_VECTOR_SQL: &'static str = "CREATE EXTENSION IF NOT EXISTS vector";
 
 pub fn create_search_base_sql() -> String {
@@ -10,54 +80,285 @@ pub fn create_search_base_sql() -> String {
     return query;
 }
 
-pub fn get_adj_chunk_sql(table_name: &str) -> String {
+/// Adds the knowledge base settings columns to `search_tables` created by older versions.
+pub fn migrate_search_base_sql() -> Vec<String> {
+    vec![
+        format!("ALTER TABLE {SEARCH_TABLES} ADD COLUMN IF NOT EXISTS embedding_model TEXT NOT NULL DEFAULT '{LEGACY_EMBEDDING_MODEL_ID}'"),
+        format!("ALTER TABLE {SEARCH_TABLES} ADD COLUMN IF NOT EXISTS dimension int NOT NULL DEFAULT {EMBEDDING_DIMENSION}"),
+        format!("ALTER TABLE {SEARCH_TABLES} ADD COLUMN IF NOT EXISTS distance_metric TEXT NOT NULL DEFAULT 'cosine'"),
+        format!("ALTER TABLE {SEARCH_TABLES} ADD COLUMN IF NOT EXISTS chunk_size int NOT NULL DEFAULT {DEFAULT_CHUNK_SIZE}"),
+        format!("ALTER TABLE {SEARCH_TABLES} ADD COLUMN IF NOT EXISTS chunk_overlap int NOT NULL DEFAULT {DEFAULT_CHUNK_OVERLAP}"),
+    ]
+}
+
+/// Names used to be stored as given, while Postgres folded the unquoted table
+/// names to lowercase. Keeps one row per lowercase name, the lowercase one or
+/// else the oldest, and lowercases it to match `KnowledgeBaseName`.
+pub fn lowercase_search_tables_sql() -> Vec<String> {
+    vec![
+        format!("DELETE FROM {SEARCH_TABLES} s USING {SEARCH_TABLES} t WHERE lower(t.table_name) = lower(s.table_name) AND t.id <> s.id AND ((t.table_name = lower(t.table_name)) > (s.table_name = lower(s.table_name)) OR ((t.table_name = lower(t.table_name)) = (s.table_name = lower(s.table_name)) AND t.id < s.id))"),
+        format!("UPDATE {SEARCH_TABLES} SET table_name = lower(table_name) WHERE table_name <> lower(table_name)"),
+    ]
+}
+
+pub fn get_adj_chunk_sql(table_name: &KnowledgeBaseName) -> String {
+    format!(
+        "SELECT *, CAST(0 AS FLOAT8) as score FROM {} WHERE content_id = $1 AND chunk_number >= $2 AND chunk_number <= $3 ORDER BY chunk_number ASC",
+        table_name.quoted()
+    )
+}
+
+fn content_tsv_column_sql() -> String {
+    format!("content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('{FULL_TEXT_CONFIG}', coalesce(content_chunk, ''))) STORED")
+}
+
+pub fn create_vector_table_sql(table_name: &KnowledgeBaseName, dimension: usize) -> String {
+    format!("CREATE TABLE IF NOT EXISTS {} (id bigserial PRIMARY KEY, content_id TEXT, content_chunk TEXT, chunk_number int, embedding vector({dimension}), metadata JSON, created_at timestamp, {})", table_name.quoted(), content_tsv_column_sql())
+}
+
+pub fn create_full_text_index_sql(table_name: &KnowledgeBaseName) -> String {
+    format!(
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING GIN (content_tsv)",
+        table_name.quoted_index("tsv"),
+        table_name.quoted()
+    )
+}
+
+/// Knowledge bases created by older versions, without `content_tsv`. Rows whose
+/// table is gone are left out.
+pub fn get_unmigrated_vector_tables_sql() -> String {
+    format!("SELECT table_name FROM {SEARCH_TABLES} WHERE EXISTS (SELECT 1 FROM information_schema.tables t WHERE t.table_schema = current_schema() AND t.table_name = {SEARCH_TABLES}.table_name) AND NOT EXISTS (SELECT 1 FROM information_schema.columns c WHERE c.table_schema = current_schema() AND c.table_name = {SEARCH_TABLES}.table_name AND c.column_name = 'content_tsv')")
+}
+
+/// Adds `content_tsv` and its index to vector tables created by older versions,
+/// Postgres rewrites the table to fill the column.
+pub fn migrate_vector_table_sql(table_name: &KnowledgeBaseName) -> Vec<String> {
+    vec![
+        format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {}",
+            table_name.quoted(),
+            content_tsv_column_sql()
+        ),
+        create_full_text_index_sql(table_name),
+    ]
+}
+
+pub fn create_vector_index_sql(
+    table_name: &KnowledgeBaseName,
+    distance_metric: DistanceMetric,
+    vector_index: &VectorIndex,
+) -> String {
     format!(
-        "SELECT *, CAST(0 AS FLOAT8) as score FROM {table_name} WHERE content_id = $1 AND chunk_number >= $2 AND chunk_number <= $3 ORDER BY chunk_number ASC"
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING {} (embedding {}) WITH ({})",
+        table_name.quoted_index("embedding"),
+        table_name.quoted(),
+        vector_index.method(),
+        distance_metric.operator_class(),
+        vector_index.options_sql()
     )
 }
 
-pub fn create_vector_table_sql(table_name: &str) -> String {
-    format!("CREATE TABLE IF NOT EXISTS {table_name} (id bigserial PRIMARY KEY, content_id TEXT, content_chunk TEXT, chunk_number int, embedding vector({EMBEDDING_DIMENSION}), metadata JSON, created_at timestamp)")
+pub fn drop_vector_index_sql(table_name: &KnowledgeBaseName) -> String {
+    format!(
+        "DROP INDEX IF EXISTS {}",
+        table_name.quoted_index("embedding")
+    )
 }
 
-pub fn create_raw_content_table_sql(table_name: &str) -> String {
-    format!("CREATE TABLE IF NOT EXISTS {table_name}_content (id bigserial PRIMARY KEY, content_id TEXT, title TEXT, text TEXT, metadata JSON)")
+/// Builds the replacement of the vector index without blocking writes, it can't
+/// run inside a transaction.
+pub fn rebuild_vector_index_sql(
+    table_name: &KnowledgeBaseName,
+    distance_metric: DistanceMetric,
+    vector_index: &VectorIndex,
+) -> String {
+    format!(
+        "CREATE INDEX CONCURRENTLY {} ON {} USING {} (embedding {}) WITH ({})",
+        table_name.quoted_index("rebuild"),
+        table_name.quoted(),
+        vector_index.method(),
+        distance_metric.operator_class(),
+        vector_index.options_sql()
+    )
+}
+
+/// Drops what's left of a failed rebuild, Postgres keeps it as an invalid index.
+pub fn drop_rebuilt_vector_index_sql(table_name: &KnowledgeBaseName) -> String {
+    format!(
+        "DROP INDEX CONCURRENTLY IF EXISTS {}",
+        table_name.quoted_index("rebuild")
+    )
+}
+
+pub fn rename_rebuilt_vector_index_sql(table_name: &KnowledgeBaseName) -> String {
+    format!(
+        "ALTER INDEX {} RENAME TO {}",
+        table_name.quoted_index("rebuild"),
+        table_name.quoted_index("embedding")
+    )
+}
+
+/// Transaction local setting, `$1` is the setting name and `$2` its value.
+pub fn set_local_config_sql() -> String {
+    "SELECT set_config($1, $2, true)".to_string()
+}
+
+pub fn create_raw_content_table_sql(table_name: &KnowledgeBaseName) -> String {
+    format!("CREATE TABLE IF NOT EXISTS {} (id bigserial PRIMARY KEY, content_id TEXT, title TEXT, text TEXT, metadata JSON)", table_name.quoted_content())
 }
 
 pub fn insert_into_search_table_sql() -> String {
-    format!("INSERT INTO {SEARCH_TABLES} (table_name) VALUES ($1)")
+    format!("INSERT INTO {SEARCH_TABLES} (table_name, embedding_model, dimension, distance_metric, chunk_size, chunk_overlap) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (table_name) DO NOTHING")
 }
 
-pub fn insert_into_vector_table_sql(table_name: &str) -> String {
-    format!("INSERT INTO {table_name} (content_id, content_chunk, chunk_number, embedding, metadata, created_at) VALUES ($1, $2, $3, $4, $5::jsonb, $6)")
+pub fn get_search_table_settings_sql() -> String {
+    format!("SELECT embedding_model, dimension, distance_metric, chunk_size, chunk_overlap FROM {SEARCH_TABLES} WHERE table_name = $1")
 }
 
-pub fn bulk_insert_into_vector_table_sql(table_name: &str) -> String {
+pub fn insert_into_vector_table_sql(table_name: &KnowledgeBaseName) -> String {
+    format!("INSERT INTO {} (content_id, content_chunk, chunk_number, embedding, metadata, created_at) VALUES ($1, $2, $3, $4, $5::jsonb, $6)", table_name.quoted())
+}
+
+pub fn bulk_insert_into_vector_table_sql(table_name: &KnowledgeBaseName) -> String {
     format!("
-        INSERT INTO {table_name}(content_id, content_chunk, chunk_number, embedding, metadata, created_at)
+        INSERT INTO {}(content_id, content_chunk, chunk_number, embedding, metadata, created_at)
         SELECT * FROM UNNEST($1::text[], $2::text[], $3::int4[], $4::vector[], $5::jsonb[], $6::timestamp[])
-    ")
+    ", table_name.quoted())
 }
 
-pub fn insert_raw_content_sql(table_name: &str) -> String {
-    format!("INSERT INTO {table_name}_content (content_id, title, text, metadata) VALUES ($1, $2, $3, $4::jsonb)")
+pub fn insert_raw_content_sql(table_name: &KnowledgeBaseName) -> String {
+    format!("INSERT INTO {} (content_id, title, text, metadata) VALUES ($1, $2, $3, $4::jsonb)", table_name.quoted_content())
 }
 
-pub fn get_similar_result_query(table_name: &str, limit: usize, minimum_score: f32) -> String {
+pub fn get_similar_result_query(
+    table_name: &KnowledgeBaseName,
+    distance_metric: DistanceMetric,
+    limit: usize,
+    minimum_score: f32,
+    filter: &str,
+) -> String {
+    let score = distance_metric.score_sql();
+    let distance = distance_metric.distance_sql();
     format!(
-        "SELECT *, (1.0-(embedding <=> $1::vector)) as score FROM {table_name} WHERE (1.0-(embedding <=> $1::vector)) >={minimum_score} ORDER BY score DESC LIMIT {}",
+        "SELECT *, {score} as score FROM {} WHERE {score} >={minimum_score}{filter} ORDER BY {distance} LIMIT {}",
+        table_name.quoted(),
         limit
     )
 }
 
+/// Full-text ranking of the chunks matching `$1`, a `websearch_to_tsquery` string.
+pub fn get_full_text_result_query(
+    table_name: &KnowledgeBaseName,
+    limit: usize,
+    filter: &str,
+) -> String {
+    format!(
+        "SELECT *, CAST(ts_rank_cd(content_tsv, websearch_to_tsquery('{FULL_TEXT_CONFIG}', $1)) AS FLOAT8) as score FROM {} WHERE content_tsv @@ websearch_to_tsquery('{FULL_TEXT_CONFIG}', $1){filter} ORDER BY score DESC LIMIT {}",
+        table_name.quoted(),
+        limit
+    )
+}
+
+/// Reciprocal rank fusion of the vector ranking against `$1` and the full-text
+/// ranking against `$2`. `minimum_score` only filters the vector candidates,
+/// `filter` applies to both rankings.
+pub fn get_hybrid_result_query(
+    table_name: &KnowledgeBaseName,
+    distance_metric: DistanceMetric,
+    limit: usize,
+    minimum_score: f32,
+    filter: &str,
+) -> String {
+    let score = distance_metric.score_sql();
+    let distance = distance_metric.distance_sql();
+    let table = table_name.quoted();
+    let candidates = limit * HYBRID_CANDIDATE_FACTOR;
+
+    format!(
+        "WITH vector_ranked AS (SELECT id, ROW_NUMBER() OVER (ORDER BY distance) AS rank FROM (SELECT id, {distance} AS distance FROM {table} WHERE {score} >={minimum_score}{filter} ORDER BY {distance} LIMIT {candidates}) nearest), \
+        text_ranked AS (SELECT id, ROW_NUMBER() OVER (ORDER BY ts_rank_cd(content_tsv, websearch_to_tsquery('{FULL_TEXT_CONFIG}', $2)) DESC) AS rank FROM {table} WHERE content_tsv @@ websearch_to_tsquery('{FULL_TEXT_CONFIG}', $2){filter} ORDER BY rank LIMIT {candidates}), \
+        fused AS (SELECT COALESCE(v.id, t.id) AS id, CAST(COALESCE(1.0/({RRF_K}+v.rank), 0.0) + COALESCE(1.0/({RRF_K}+t.rank), 0.0) AS FLOAT8) AS score FROM vector_ranked v FULL OUTER JOIN text_ranked t ON v.id = t.id) \
+        SELECT {table}.*, fused.score FROM fused JOIN {table} ON {table}.id = fused.id ORDER BY fused.score DESC LIMIT {limit}"
+    )
+}
+
 pub fn get_search_tables_sql() -> String {
     format!("SELECT table_name FROM {SEARCH_TABLES}")
 }
 
-pub fn get_drop_table_sql(table_name: String) -> String {
-    format!("DROP TABLE IF EXISTS {table_name}")
+pub fn get_drop_table_sql(table_name: &KnowledgeBaseName) -> String {
+    format!("DROP TABLE IF EXISTS {}", table_name.quoted())
+}
+
+pub fn get_drop_content_table_sql(table_name: &KnowledgeBaseName) -> String {
+    format!("DROP TABLE IF EXISTS {}", table_name.quoted_content())
+}
+
+pub fn get_delete_from_search_table_sql() -> String {
+    format!("DELETE FROM {SEARCH_TABLES} WHERE table_name = $1")
+}
+
+pub fn create_ingestion_jobs_sql() -> String {
+    format!("CREATE TABLE IF NOT EXISTS {INGESTION_JOBS} (id TEXT PRIMARY KEY, table_name TEXT NOT NULL, file_name TEXT NOT NULL, status TEXT NOT NULL, chunk_count int NOT NULL DEFAULT 0, error TEXT, created_at timestamp NOT NULL DEFAULT now(), updated_at timestamp NOT NULL DEFAULT now())")
+}
+
+pub fn fail_interrupted_ingestion_jobs_sql() -> String {
+    format!("UPDATE {INGESTION_JOBS} SET status = 'failed', error = 'Interrupted by server restart', updated_at = now() WHERE status IN ('queued', 'running')")
+}
+
+pub fn insert_ingestion_job_sql() -> String {
+    format!("INSERT INTO {INGESTION_JOBS} (id, table_name, file_name, status, error) VALUES ($1, $2, $3, $4, $5)")
+}
+
+pub fn update_ingestion_job_sql() -> String {
+    format!("UPDATE {INGESTION_JOBS} SET status = $2, chunk_count = $3, error = $4, updated_at = now() WHERE id = $1")
+}
+
+pub fn get_ingestion_job_sql() -> String {
+    format!("SELECT * FROM {INGESTION_JOBS} WHERE id = $1")
+}
+
+pub fn list_ingestion_jobs_sql() -> String {
+    format!("SELECT * FROM {INGESTION_JOBS} WHERE ($1::text IS NULL OR table_name = $1) ORDER BY created_at DESC LIMIT {MAX_LISTED_JOBS}")
+}
+
+pub fn create_chat_sessions_sql() -> String {
+    format!("CREATE TABLE IF NOT EXISTS {CHAT_SESSIONS} (id TEXT PRIMARY KEY, created_at timestamp NOT NULL DEFAULT now(), updated_at timestamp NOT NULL DEFAULT now())")
+}
+
+pub fn create_chat_messages_sql() -> String {
+    format!("CREATE TABLE IF NOT EXISTS {CHAT_MESSAGES} (id bigserial PRIMARY KEY, session_id TEXT NOT NULL REFERENCES {CHAT_SESSIONS} (id) ON DELETE CASCADE, role TEXT NOT NULL, content TEXT NOT NULL, created_at timestamp NOT NULL DEFAULT now())")
+}
+
+pub fn create_chat_messages_index_sql() -> String {
+    format!("CREATE INDEX IF NOT EXISTS {CHAT_MESSAGES}_session_idx ON {CHAT_MESSAGES} (session_id, id)")
+}
+
+pub fn upsert_chat_session_sql() -> String {
+    format!("INSERT INTO {CHAT_SESSIONS} (id) VALUES ($1) ON CONFLICT (id) DO UPDATE SET updated_at = now()")
+}
+
+pub fn insert_chat_message_sql() -> String {
+    format!("INSERT INTO {CHAT_MESSAGES} (session_id, role, content) VALUES ($1, $2, $3)")
+}
+
+/// Last `MAX_HISTORY_MESSAGES` messages of session `$1`, oldest first.
+pub fn get_recent_chat_messages_sql() -> String {
+    format!("SELECT * FROM (SELECT id, role, content, created_at FROM {CHAT_MESSAGES} WHERE session_id = $1 ORDER BY id DESC LIMIT {MAX_HISTORY_MESSAGES}) recent ORDER BY id ASC")
+}
+
+pub fn get_chat_messages_sql() -> String {
+    format!("SELECT id, role, content, created_at FROM {CHAT_MESSAGES} WHERE session_id = $1 ORDER BY id ASC")
+}
+
+pub fn get_chat_session_sql() -> String {
+    format!("SELECT s.id, s.created_at, s.updated_at, COUNT(m.id) AS message_count FROM {CHAT_SESSIONS} s LEFT JOIN {CHAT_MESSAGES} m ON m.session_id = s.id WHERE s.id = $1 GROUP BY s.id")
+}
+
+pub fn list_chat_sessions_sql() -> String {
+    format!("SELECT s.id, s.created_at, s.updated_at, COUNT(m.id) AS message_count FROM {CHAT_SESSIONS} s LEFT JOIN {CHAT_MESSAGES} m ON m.session_id = s.id GROUP BY s.id ORDER BY s.updated_at DESC LIMIT {MAX_LISTED_SESSIONS}")
 }
 
-pub fn get_delete_from_search_table_sql(table_name: &str) -> String {
-    format!("DELETE FROM {SEARCH_TABLES} WHERE table_name = '{table_name}'")
+pub fn delete_chat_session_sql() -> String {
+    format!("DELETE FROM {CHAT_SESSIONS} WHERE id = $1")
 }
@@ -0,0 +1,693 @@
+use crate::utils::text_splitter::split_chunks;
+
+use anyhow::{Context, Result};
+use ego_tree::NodeRef;
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use quick_xml::events::{BytesStart, Event as XmlEvent};
+use quick_xml::Reader;
+use scraper::{Html, Node};
+use serde_json::{json, Map, Value};
+use std::io::{Cursor, Read};
+
+/// A heading starting at byte `offset` of the extracted text, level 1 is the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub offset: usize,
+    pub level: usize,
+    pub title: String,
+}
+
+/// Page `number` of a paginated document starts at byte `offset` of the extracted text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    pub offset: usize,
+    pub number: usize,
+}
+
+/// Part of a document stored as one embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentChunk {
+    /// Byte offset in the document text.
+    pub start: usize,
+    pub text: String,
+    /// Path of the section the chunk starts in, see [`ExtractedDocument::heading_path`].
+    pub heading: Option<String>,
+    /// First and last page the chunk spans, see [`ExtractedDocument::page_range`].
+    pub pages: Option<(usize, usize)>,
+}
+
+/// Clean text of a document, with its outline and metadata stored on every chunk.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractedDocument {
+    pub text: String,
+    pub headings: Vec<Heading>,
+    /// Empty unless the format has pages.
+    pub pages: Vec<Page>,
+    pub metadata: Map<String, Value>,
+}
+
+impl ExtractedDocument {
+    /// Empty document, `format` is stored in its metadata.
+    pub fn new(format: &str) -> Self {
+        let mut metadata = Map::new();
+        metadata.insert("format".to_owned(), json!(format));
+
+        Self {
+            metadata,
+            ..Default::default()
+        }
+    }
+
+    fn push_heading(&mut self, level: usize, title: &str) {
+        let title = collapse_whitespace(title);
+
+        if title.is_empty() {
+            return;
+        }
+
+        self.start_block();
+        self.headings.push(Heading {
+            offset: self.text.len(),
+            level,
+            title: title.to_owned(),
+        });
+        self.text += &title;
+        self.start_block();
+    }
+
+    /// Following text starts on a new paragraph.
+    fn start_block(&mut self) {
+        let trimmed = self.text.trim_end_matches([' ', '\t']).len();
+        self.text.truncate(trimmed);
+
+        if !self.text.is_empty() && !self.text.ends_with("\n\n") {
+            self.text.push_str(if self.text.ends_with('\n') {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+    }
+
+    fn add_code_language(&mut self, language: &str) {
+        let language = language.trim().to_lowercase();
+
+        if language.is_empty() {
+            return;
+        }
+
+        let languages = self
+            .metadata
+            .entry("code_languages")
+            .or_insert_with(|| json!([]));
+
+        if let Some(languages) = languages.as_array_mut() {
+            if !languages.contains(&json!(language)) {
+                languages.push(json!(language));
+            }
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        let trimmed = self.text.trim_end().len();
+        self.text.truncate(trimmed);
+
+        if !self.metadata.contains_key("title") {
+            if let Some(heading) = self.headings.iter().min_by_key(|heading| heading.level) {
+                self.metadata
+                    .insert("title".to_owned(), json!(heading.title));
+            }
+        }
+
+        self
+    }
+
+    /// Titles of the headings `offset` is under, outermost first, as `"Setup > Linux"`.
+    pub fn heading_path(&self, offset: usize) -> Option<String> {
+        let mut path: Vec<&Heading> = Vec::new();
+
+        for heading in self
+            .headings
+            .iter()
+            .take_while(|heading| heading.offset <= offset)
+        {
+            path.retain(|parent| parent.level < heading.level);
+            path.push(heading);
+        }
+
+        (!path.is_empty()).then(|| {
+            path.iter()
+                .map(|heading| heading.title.as_str())
+                .collect::<Vec<&str>>()
+                .join(" > ")
+        })
+    }
+
+    /// Chunks of the text, see [`split_chunks`].
+    pub fn chunks(&self, chunk_size: usize, overlap_size: usize) -> Vec<DocumentChunk> {
+        split_chunks(&self.text, chunk_size, overlap_size)
+            .into_iter()
+            .map(|(start, text)| DocumentChunk {
+                start,
+                heading: self.heading_path(start),
+                pages: self.page_range(start, start + text.len()),
+                text,
+            })
+            .collect()
+    }
+
+    /// First and last page of the text between byte `start` and `end`.
+    pub fn page_range(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let page_at = |offset: usize| {
+            self.pages
+                .iter()
+                .take_while(|page| page.offset <= offset)
+                .last()
+                .map(|page| page.number)
+        };
+
+        Some((page_at(start)?, page_at(end.max(start + 1) - 1)?))
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub fn extract_markdown(markdown: &str) -> ExtractedDocument {
+    let mut document = ExtractedDocument::new("markdown");
+    let mut heading: Option<(usize, String)> = None;
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                heading = Some((level as usize, String::new()));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, title)) = heading.take() {
+                    document.push_heading(level, &title);
+                }
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                if let CodeBlockKind::Fenced(info) = kind {
+                    // The info string is the language, optionally followed by attributes.
+                    if let Some(language) = info.split([' ', ',', '{']).next() {
+                        document.add_code_language(language);
+                    }
+                }
+                document.start_block();
+            }
+            Event::Start(Tag::Item) => {
+                document
+                    .text
+                    .truncate(document.text.trim_end_matches(' ').len());
+
+                if !document.text.is_empty() && !document.text.ends_with('\n') {
+                    document.text.push('\n');
+                }
+                document.text += "- ";
+            }
+            // Paragraphs of list items stay on the bullet line.
+            Event::Start(Tag::Paragraph | Tag::BlockQuote(_) | Tag::Table(_))
+            | Event::End(TagEnd::Paragraph | TagEnd::CodeBlock | TagEnd::Table)
+                if !document.text.ends_with("- ") =>
+            {
+                document.start_block()
+            }
+            Event::End(TagEnd::TableRow | TagEnd::TableHead) => document.text.push('\n'),
+            Event::End(TagEnd::TableCell) => document.text.push('\t'),
+            Event::Text(text) | Event::Code(text) => match heading.as_mut() {
+                Some((_, title)) => *title += &text,
+                None => document.text += &text,
+            },
+            Event::SoftBreak => match heading.as_mut() {
+                Some((_, title)) => title.push(' '),
+                None => document.text.push('\n'),
+            },
+            Event::HardBreak => document.text.push('\n'),
+            _ => {}
+        }
+    }
+
+    document.finish()
+}
+
+/// Elements whose content isn't part of the readable text.
+const SKIPPED_HTML_ELEMENTS: [&str; 7] = [
+    "head", "script", "style", "noscript", "template", "svg", "iframe",
+];
+
+const BLOCK_HTML_ELEMENTS: [&str; 22] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "header",
+    "footer",
+    "aside",
+    "nav",
+    "ul",
+    "ol",
+    "li",
+    "table",
+    "tr",
+    "blockquote",
+    "pre",
+    "figure",
+    "figcaption",
+    "dl",
+    "dt",
+    "dd",
+    "form",
+];
+
+pub fn extract_html(html: &str) -> ExtractedDocument {
+    let mut document = ExtractedDocument::new("html");
+    let html = Html::parse_document(html);
+
+    if let Some(title) = html
+        .tree
+        .root()
+        .descendants()
+        .find(|node| matches!(node.value(), Node::Element(element) if element.name() == "title"))
+        .map(|title| collapse_whitespace(&node_text(title)))
+        .filter(|title| !title.is_empty())
+    {
+        document.metadata.insert("title".to_owned(), json!(title));
+    }
+
+    walk_html(html.tree.root(), &mut document, false);
+
+    document.finish()
+}
+
+fn node_text(node: NodeRef<Node>) -> String {
+    node.descendants()
+        .filter_map(|node| node.value().as_text().map(|text| text.to_string()))
+        .collect()
+}
+
+fn walk_html(node: NodeRef<Node>, document: &mut ExtractedDocument, preformatted: bool) {
+    for child in node.children() {
+        match child.value() {
+            Node::Text(text) if preformatted => document.text += &**text,
+            Node::Text(text) => {
+                let collapsed = collapse_whitespace(text);
+
+                if collapsed.is_empty() {
+                    continue;
+                }
+
+                let separate = text.starts_with(char::is_whitespace)
+                    && !document.text.is_empty()
+                    && !document.text.ends_with(char::is_whitespace);
+
+                if separate {
+                    document.text.push(' ');
+                }
+
+                document.text += &collapsed;
+
+                if text.ends_with(char::is_whitespace) {
+                    document.text.push(' ');
+                }
+            }
+            Node::Element(element) => {
+                let name = element.name();
+
+                if SKIPPED_HTML_ELEMENTS.contains(&name) {
+                    continue;
+                }
+
+                match name {
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        let level = name[1..].parse().unwrap_or(1);
+                        document.push_heading(level, &node_text(child));
+                    }
+                    "br" => document.text.push('\n'),
+                    "td" | "th" => {
+                        walk_html(child, document, preformatted);
+                        document.text.push('\t');
+                    }
+                    "pre" | "code" => {
+                        // `language-rust` and `lang-rust` are the usual highlighter classes.
+                        if let Some(language) = element.classes().find_map(|class| {
+                            class
+                                .strip_prefix("language-")
+                                .or_else(|| class.strip_prefix("lang-"))
+                        }) {
+                            document.add_code_language(language);
+                        }
+
+                        if name == "pre" {
+                            document.start_block();
+                        }
+                        walk_html(child, document, true);
+                        if name == "pre" {
+                            document.start_block();
+                        }
+                    }
+                    _ if BLOCK_HTML_ELEMENTS.contains(&name) => {
+                        document.start_block();
+                        walk_html(child, document, preformatted);
+                        document.start_block();
+                    }
+                    _ => walk_html(child, document, preformatted),
+                }
+            }
+            _ => walk_html(child, document, preformatted),
+        }
+    }
+}
+
+/// Characters reStructuredText section titles can be underlined with.
+const RST_ADORNMENTS: &str = "=-`:'\"~^_*+#<>.";
+
+fn rst_adornment(line: &str) -> Option<char> {
+    let line = line.trim_end();
+    let first = line.chars().next()?;
+
+    (line.chars().count() >= 2
+        && RST_ADORNMENTS.contains(first)
+        && line.chars().all(|c| c == first))
+    .then_some(first)
+}
+
+/// Section levels follow the order adornment styles first appear in, as in docutils.
+pub fn extract_rst(rst: &str) -> ExtractedDocument {
+    let mut document = ExtractedDocument::new("rst");
+    let mut styles: Vec<(char, bool)> = Vec::new();
+
+    let lines: Vec<&str> = rst.lines().collect();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        let overline = rst_adornment(line);
+
+        // Title between an overline and an underline.
+        if let (Some(over), Some(title), Some(under)) = (
+            overline,
+            lines.get(index + 1),
+            lines.get(index + 2).and_then(|line| rst_adornment(line)),
+        ) {
+            if over == under && !title.trim().is_empty() && rst_adornment(title).is_none() {
+                let level = rst_level(&mut styles, (over, true));
+                document.push_heading(level, &strip_rst_markup(title));
+                index += 3;
+                continue;
+            }
+        }
+
+        // Title followed by an underline at least as long.
+        if let Some(under) = lines.get(index + 1).and_then(|next| {
+            rst_adornment(next)
+                .filter(|_| next.trim_end().chars().count() >= line.trim().chars().count())
+        }) {
+            if !line.trim().is_empty() && overline.is_none() && !line.starts_with(' ') {
+                let level = rst_level(&mut styles, (under, false));
+                document.push_heading(level, &strip_rst_markup(line));
+                index += 2;
+                continue;
+            }
+        }
+
+        // Transitions between sections.
+        if overline.is_some() {
+            document.start_block();
+            index += 1;
+            continue;
+        }
+
+        let trimmed = line.trim();
+
+        if let Some(directive) = trimmed.strip_prefix(".. ") {
+            if let Some((name, argument)) = directive.split_once("::") {
+                if matches!(name.trim(), "code-block" | "code" | "sourcecode") {
+                    document.add_code_language(argument);
+                }
+            }
+
+            // Directive markup and comments aren't text, their indented content is.
+            document.start_block();
+            index += 1;
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            document.start_block();
+        } else {
+            document.text += &strip_rst_markup(line);
+            document.text.push('\n');
+        }
+
+        index += 1;
+    }
+
+    document.finish()
+}
+
+fn rst_level(styles: &mut Vec<(char, bool)>, style: (char, bool)) -> usize {
+    match styles.iter().position(|known| *known == style) {
+        Some(position) => position + 1,
+        None => {
+            styles.push(style);
+            styles.len()
+        }
+    }
+}
+
+/// Drop inline markup, `` `label <url>`_ `` keeps the label.
+fn strip_rst_markup(line: &str) -> String {
+    let line = line.replace("``", "").replace("**", "");
+    let mut result = String::new();
+    let mut rest = line.as_str();
+
+    while let Some(start) = rest.find('`') {
+        let Some(length) = rest[start + 1..].find('`') else {
+            break;
+        };
+
+        result += &rest[..start];
+
+        let inner = &rest[start + 1..start + 1 + length];
+        let label = match inner.rfind(" <") {
+            Some(position) if inner.ends_with('>') => &inner[..position],
+            _ => inner,
+        };
+        result += label;
+
+        rest = rest[start + 2 + length..].trim_start_matches('_');
+    }
+
+    result += rest;
+    result.trim_end().to_owned()
+}
+
+/// Extensions `code_language` knows.
+pub const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "pyi", "js", "mjs", "cjs", "jsx", "ts", "tsx", "go", "java", "kt", "kts", "scala",
+    "c", "h", "cc", "cpp", "cxx", "hpp", "hh", "cs", "rb", "php", "swift", "sh", "bash", "zsh",
+    "sql", "lua", "r", "toml", "yaml", "yml",
+];
+
+/// Language of a source file, by extension.
+pub fn code_language(file_name: &str) -> Option<&'static str> {
+    let extension = file_name.rsplit_once('.')?.1.to_lowercase();
+
+    let language = match extension.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "sh" | "bash" | "zsh" => "shell",
+        "sql" => "sql",
+        "lua" => "lua",
+        "r" => "r",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        _ => return None,
+    };
+
+    Some(language)
+}
+
+/// Plain text is kept as written.
+pub fn extract_text(format: &str, text: &str) -> ExtractedDocument {
+    let mut document = ExtractedDocument::new(format);
+
+    document.text = text.to_owned();
+    document.finish()
+}
+
+/// Text of each page of a PDF, page numbers start at 1. Pages without text
+/// aren't recorded.
+pub fn extract_pdf(pages: &[String]) -> ExtractedDocument {
+    let mut document = ExtractedDocument::new("pdf");
+
+    for (index, page) in pages.iter().enumerate() {
+        let page = page.trim();
+
+        if page.is_empty() {
+            continue;
+        }
+
+        document.start_block();
+        document.pages.push(Page {
+            offset: document.text.len(),
+            number: index + 1,
+        });
+        document.text += page;
+    }
+
+    document
+        .metadata
+        .insert("page_count".to_owned(), json!(pages.len()));
+
+    document.finish()
+}
+
+/// Source code is kept as written, only its language is recorded.
+pub fn extract_code(file_name: &str, source: &str) -> ExtractedDocument {
+    let mut document = extract_text("code", source);
+
+    if let Some(language) = code_language(file_name) {
+        document
+            .metadata
+            .insert("language".to_owned(), json!(language));
+    }
+
+    document
+}
+
+/// Text of the part `name`, failing once it expands past `max_bytes`.
+fn docx_part(
+    archive: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    name: &str,
+    max_bytes: u64,
+) -> Result<Option<String>> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut contents = Vec::new();
+    file.take(max_bytes + 1).read_to_end(&mut contents)?;
+
+    if contents.len() as u64 > max_bytes {
+        anyhow::bail!("{name} expands to more than {max_bytes} bytes");
+    }
+
+    Ok(Some(
+        String::from_utf8(contents).with_context(|| format!("{name} isn't UTF-8 text"))?,
+    ))
+}
+
+fn xml_attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+    match element.try_get_attribute(name)? {
+        Some(attribute) => Ok(Some(attribute.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Heading level of a paragraph style, Word names them `Heading1` to `Heading9`.
+fn docx_heading_level(style: &str) -> Option<usize> {
+    match style {
+        "Title" => Some(1),
+        _ => style
+            .strip_prefix("Heading")
+            .and_then(|level| level.parse::<usize>().ok())
+            .filter(|level| (1..=9).contains(level)),
+    }
+}
+
+/// Text of a DOCX file, each of its XML parts may expand to at most `max_bytes`.
+pub fn extract_docx(bytes: &[u8], max_bytes: u64) -> Result<ExtractedDocument> {
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(bytes)).context("A DOCX file is a ZIP archive")?;
+
+    let body = docx_part(&mut archive, "word/document.xml", max_bytes)?
+        .context("word/document.xml is missing from the DOCX file")?;
+
+    let mut document = ExtractedDocument::new("docx");
+
+    if let Some(core) = docx_part(&mut archive, "docProps/core.xml", max_bytes)? {
+        if let Some(title) = docx_title(&core)? {
+            document.metadata.insert("title".to_owned(), json!(title));
+        }
+    }
+
+    let mut reader = Reader::from_str(&body);
+
+    let mut paragraph = String::new();
+    let mut heading_level: Option<usize> = None;
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event()? {
+            XmlEvent::Start(element) | XmlEvent::Empty(element)
+                if element.name().as_ref() == b"w:pStyle" =>
+            {
+                heading_level = xml_attribute(&element, b"w:val")?
+                    .as_deref()
+                    .and_then(docx_heading_level);
+            }
+            XmlEvent::Start(element) => in_text = element.name().as_ref() == b"w:t",
+            XmlEvent::Empty(element) => match element.name().as_ref() {
+                b"w:tab" => paragraph.push('\t'),
+                b"w:br" | b"w:cr" => paragraph.push('\n'),
+                _ => {}
+            },
+            XmlEvent::Text(text) if in_text => paragraph += &text.unescape()?,
+            XmlEvent::End(element) => match element.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:p" => {
+                    match heading_level.take() {
+                        Some(level) => document.push_heading(level, &paragraph),
+                        None if paragraph.trim().is_empty() => {}
+                        None => {
+                            document.text += paragraph.trim_end();
+                            document.text.push('\n');
+                        }
+                    }
+                    paragraph.clear();
+                }
+                _ => {}
+            },
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(document.finish())
+}
+
+fn docx_title(core: &str) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(core);
+    let mut in_title = false;
+
+    loop {
+        match reader.read_event()? {
+            XmlEvent::Start(element) => in_title = element.name().as_ref() == b"dc:title",
+            XmlEvent::Text(text) if in_title => {
+                let title = collapse_whitespace(&text.unescape()?);
+                return Ok((!title.is_empty()).then_some(title));
+            }
+            XmlEvent::End(_) => in_title = false,
+            XmlEvent::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
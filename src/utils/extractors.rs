@@ -0,0 +1,398 @@
+use crate::utils::config::AppConfig;
+use crate::utils::constants::SNIFF_BYTES;
+use crate::utils::extract::{self, ExtractedDocument};
+use crate::utils::records::{self, RecordTemplate};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// A file format documents can be learned from.
+///
+/// Add custom formats to [`ExtractorRegistry::default`], uploads are dispatched
+/// to them without changes to the route.
+pub trait DocumentExtractor: Send + Sync {
+    /// Stored as the `format` of the chunks.
+    fn name(&self) -> &'static str;
+
+    /// Lowercase, without the dot.
+    fn extensions(&self) -> &[&'static str];
+
+    fn mime_types(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Whether `head`, the first `SNIFF_BYTES` of the file, has this format's signature.
+    /// Formats without one only match by extension or MIME type.
+    fn sniff(&self, _head: &[u8]) -> bool {
+        false
+    }
+
+    fn extract(&self, file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument>;
+
+    /// Documents the file is learned as. Formats whose records are documents of
+    /// their own return one per record, with `template` deciding their text.
+    fn extract_documents(
+        &self,
+        file_name: &str,
+        bytes: &[u8],
+        _template: &RecordTemplate,
+    ) -> Result<Vec<ExtractedDocument>> {
+        Ok(vec![self.extract(file_name, bytes)?])
+    }
+}
+
+fn utf8<'a>(file_name: &str, bytes: &'a [u8]) -> Result<&'a str> {
+    std::str::from_utf8(bytes).with_context(|| format!("{file_name} isn't UTF-8 text"))
+}
+
+pub struct PdfExtractor;
+
+impl DocumentExtractor for PdfExtractor {
+    fn name(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["pdf"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["application/pdf"]
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        head.starts_with(b"%PDF-")
+    }
+
+    fn extract(&self, _file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        let pages = pdf_extract::extract_text_from_mem_by_pages(bytes)?;
+
+        Ok(extract::extract_pdf(&pages))
+    }
+}
+
+pub struct DocxExtractor;
+
+impl DocumentExtractor for DocxExtractor {
+    fn name(&self) -> &'static str {
+        "docx"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["docx"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["application/vnd.openxmlformats-officedocument.wordprocessingml.document"]
+    }
+
+    /// A ZIP archive whose first entries are `word/` parts, entry names aren't compressed.
+    fn sniff(&self, head: &[u8]) -> bool {
+        head.starts_with(b"PK\x03\x04") && head.windows(5).any(|window| window == b"word/")
+    }
+
+    /// Its parts are held to the size an uploaded archive may expand to.
+    fn extract(&self, _file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        extract::extract_docx(bytes, AppConfig::global().archive_limits().max_bytes)
+    }
+}
+
+pub struct HtmlExtractor;
+
+impl DocumentExtractor for HtmlExtractor {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["html", "htm"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["text/html", "application/xhtml+xml"]
+    }
+
+    fn sniff(&self, head: &[u8]) -> bool {
+        let head = String::from_utf8_lossy(head);
+        let head = head
+            .trim_start_matches('\u{feff}')
+            .trim_start()
+            .to_lowercase();
+
+        head.starts_with("<!doctype html") || head.starts_with("<html")
+    }
+
+    fn extract(&self, file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        Ok(extract::extract_html(utf8(file_name, bytes)?))
+    }
+}
+
+pub struct MarkdownExtractor;
+
+impl DocumentExtractor for MarkdownExtractor {
+    fn name(&self) -> &'static str {
+        "markdown"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["md", "markdown"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["text/markdown", "text/x-markdown"]
+    }
+
+    fn extract(&self, file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        Ok(extract::extract_markdown(utf8(file_name, bytes)?))
+    }
+}
+
+pub struct RstExtractor;
+
+impl DocumentExtractor for RstExtractor {
+    fn name(&self) -> &'static str {
+        "rst"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["rst"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["text/x-rst"]
+    }
+
+    fn extract(&self, file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        Ok(extract::extract_rst(utf8(file_name, bytes)?))
+    }
+}
+
+pub struct CodeExtractor;
+
+impl DocumentExtractor for CodeExtractor {
+    fn name(&self) -> &'static str {
+        "code"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        extract::CODE_EXTENSIONS
+    }
+
+    fn extract(&self, file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        Ok(extract::extract_code(file_name, utf8(file_name, bytes)?))
+    }
+}
+
+pub struct TextExtractor;
+
+impl DocumentExtractor for TextExtractor {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["txt"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["text/plain"]
+    }
+
+    fn extract(&self, file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        Ok(extract::extract_text(self.name(), utf8(file_name, bytes)?))
+    }
+}
+
+pub struct CsvExtractor;
+
+impl DocumentExtractor for CsvExtractor {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["csv"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["text/csv"]
+    }
+
+    fn extract(&self, _file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        records::records_document(
+            self.name(),
+            records::parse_csv(bytes)?,
+            &RecordTemplate::default(),
+        )
+    }
+
+    fn extract_documents(
+        &self,
+        _file_name: &str,
+        bytes: &[u8],
+        template: &RecordTemplate,
+    ) -> Result<Vec<ExtractedDocument>> {
+        records::record_documents(self.name(), records::parse_csv(bytes)?, template)
+    }
+}
+
+pub struct JsonExtractor;
+
+impl DocumentExtractor for JsonExtractor {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["json"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["application/json"]
+    }
+
+    fn extract(&self, _file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        records::records_document(
+            self.name(),
+            records::parse_json(bytes)?,
+            &RecordTemplate::default(),
+        )
+    }
+
+    fn extract_documents(
+        &self,
+        _file_name: &str,
+        bytes: &[u8],
+        template: &RecordTemplate,
+    ) -> Result<Vec<ExtractedDocument>> {
+        records::record_documents(self.name(), records::parse_json(bytes)?, template)
+    }
+}
+
+pub struct JsonlExtractor;
+
+impl DocumentExtractor for JsonlExtractor {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn extensions(&self) -> &[&'static str] {
+        &["jsonl", "ndjson"]
+    }
+
+    fn mime_types(&self) -> &[&'static str] {
+        &["application/jsonl", "application/x-ndjson"]
+    }
+
+    fn extract(&self, _file_name: &str, bytes: &[u8]) -> Result<ExtractedDocument> {
+        records::records_document(
+            self.name(),
+            records::parse_jsonl(bytes)?,
+            &RecordTemplate::default(),
+        )
+    }
+
+    fn extract_documents(
+        &self,
+        _file_name: &str,
+        bytes: &[u8],
+        template: &RecordTemplate,
+    ) -> Result<Vec<ExtractedDocument>> {
+        records::record_documents(self.name(), records::parse_jsonl(bytes)?, template)
+    }
+}
+
+/// Extractors in the order they are tried, for each of signature, extension and MIME type.
+#[derive(Clone)]
+pub struct ExtractorRegistry {
+    extractors: Vec<Arc<dyn DocumentExtractor>>,
+}
+
+impl Default for ExtractorRegistry {
+    fn default() -> Self {
+        Self {
+            extractors: vec![
+                Arc::new(PdfExtractor),
+                Arc::new(DocxExtractor),
+                Arc::new(HtmlExtractor),
+                Arc::new(MarkdownExtractor),
+                Arc::new(RstExtractor),
+                Arc::new(CodeExtractor),
+                Arc::new(CsvExtractor),
+                Arc::new(JsonExtractor),
+                Arc::new(JsonlExtractor),
+                Arc::new(TextExtractor),
+            ],
+        }
+    }
+}
+
+impl ExtractorRegistry {
+    /// Extractor for a file, by the signature in `head`, then the extension of
+    /// `file_name`, then the `content_type` it was uploaded with.
+    pub fn detect(
+        &self,
+        file_name: &str,
+        content_type: Option<&str>,
+        head: &[u8],
+    ) -> Option<Arc<dyn DocumentExtractor>> {
+        let head = &head[..head.len().min(SNIFF_BYTES)];
+
+        let extension = file_name
+            .rsplit('/')
+            .next()
+            .and_then(|file_name| file_name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_lowercase());
+
+        // Parameters like `; charset=utf-8` don't change the format.
+        let mime_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|mime_type| mime_type.trim().to_lowercase());
+
+        self.find(|extractor| extractor.sniff(head))
+            .or_else(|| {
+                let extension = extension.as_deref()?;
+                self.find(|extractor| extractor.extensions().contains(&extension))
+            })
+            .or_else(|| {
+                let mime_type = mime_type.as_deref()?;
+                self.find(|extractor| extractor.mime_types().contains(&mime_type))
+            })
+    }
+
+    fn find(
+        &self,
+        matches: impl Fn(&dyn DocumentExtractor) -> bool,
+    ) -> Option<Arc<dyn DocumentExtractor>> {
+        self.extractors
+            .iter()
+            .find(|extractor| matches(extractor.as_ref()))
+            .cloned()
+    }
+}
+
+lazy_static! {
+    static ref EXTRACTORS: ExtractorRegistry = ExtractorRegistry::default();
+}
+
+/// Extractor for an upload, see [`ExtractorRegistry::detect`].
+pub fn detect_extractor(
+    file_name: &str,
+    content_type: Option<&str>,
+    head: &[u8],
+) -> Option<Arc<dyn DocumentExtractor>> {
+    EXTRACTORS.detect(file_name, content_type, head)
+}
+
+/// The first `SNIFF_BYTES` of the file at `path`.
+pub async fn read_head(path: &str) -> Result<Vec<u8>> {
+    let file = File::open(path).await?;
+    let mut head = Vec::with_capacity(SNIFF_BYTES);
+
+    file.take(SNIFF_BYTES as u64).read_to_end(&mut head).await?;
+
+    Ok(head)
+}
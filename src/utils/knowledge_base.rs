@@ -0,0 +1,385 @@
+use crate::utils::constants::{
+    GENERATED_INDEX_KINDS, HNSW_DEFAULT_EF_CONSTRUCTION, HNSW_DEFAULT_M, IVFFLAT_DEFAULT_LISTS,
+    MAX_CHUNK_SIZE, MAX_INDEXED_DIMENSION, MAX_KNOWLEDGE_BASE_NAME_LEN, MIN_CHUNK_SIZE,
+    RESERVED_TABLE_NAMES,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+/// Postgres reserved keywords, rejected even though names are always quoted
+/// so that they stay usable from plain `psql` sessions.
+const RESERVED_KEYWORDS: [&str; 77] = [
+    "all", "analyse", "analyze", "and", "any", "array", "as", "asc", "asymmetric", "both",
+    "case", "cast", "check", "collate", "column", "constraint", "create", "current_catalog",
+    "current_date", "current_role", "current_time", "current_timestamp", "current_user",
+    "default", "deferrable", "desc", "distinct", "do", "else", "end", "except", "false", "fetch",
+    "for", "foreign", "from", "grant", "group", "having", "in", "initially", "intersect", "into",
+    "lateral", "leading", "limit", "localtime", "localtimestamp", "not", "null", "offset", "on",
+    "only", "or", "order", "placing", "primary", "references", "returning", "select",
+    "session_user", "some", "symmetric", "table", "then", "to", "trailing", "true", "union",
+    "unique", "user", "using", "variadic", "when", "where", "window", "with",
+];
+
+/// Validated name of a knowledge base (the vector table and its `_content` table).
+///
+/// Names are trimmed and lowercased, must start with a letter and may only contain
+/// ASCII letters, digits and underscores. Use `quoted`/`quoted_content` when
+/// building SQL, never the raw string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KnowledgeBaseName(String);
+
+impl KnowledgeBaseName {
+    pub fn parse(name: &str) -> Result<Self> {
+        let name = name.trim().to_lowercase();
+
+        if name.is_empty() {
+            anyhow::bail!("Knowledge base name can't be empty");
+        }
+
+        if name.len() > MAX_KNOWLEDGE_BASE_NAME_LEN {
+            anyhow::bail!(
+                "Knowledge base name can't be longer than {MAX_KNOWLEDGE_BASE_NAME_LEN} characters"
+            );
+        }
+
+        if !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+            anyhow::bail!("Knowledge base name must start with a letter");
+        }
+
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            anyhow::bail!("Knowledge base name may only contain letters, digits and underscores");
+        }
+
+        if name.starts_with("pg_")
+            || name.ends_with("_content")
+            || RESERVED_TABLE_NAMES.contains(&name.as_str())
+            || RESERVED_KEYWORDS.contains(&name.as_str())
+        {
+            anyhow::bail!("Knowledge base name is reserved: {name}");
+        }
+
+        Ok(Self(name))
+    }
+
+    /// `parse` for a knowledge base about to be created, also rejecting the names
+    /// of indexes created for other knowledge bases.
+    pub fn parse_new(name: &str) -> Result<Self> {
+        let name = Self::parse(name)?;
+
+        if GENERATED_INDEX_KINDS
+            .iter()
+            .any(|kind| name.0.ends_with(&format!("_{kind}_idx")))
+        {
+            anyhow::bail!("Knowledge base name is reserved: {name}");
+        }
+
+        Ok(name)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Quoted identifier of the vector table.
+    pub fn quoted(&self) -> String {
+        quote_identifier(&self.0)
+    }
+
+    /// Quoted identifier of the raw content table.
+    pub fn quoted_content(&self) -> String {
+        quote_identifier(&format!("{}_content", self.0))
+    }
+
+    /// Quoted identifier of an index on the vector table, e.g. `"docs_tsv_idx"`.
+    pub fn quoted_index(&self, kind: &str) -> String {
+        quote_identifier(&format!("{}_{kind}_idx", self.0))
+    }
+}
+
+impl fmt::Display for KnowledgeBaseName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for KnowledgeBaseName {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// pgvector distance operator a knowledge base is searched with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceMetric {
+    Cosine,
+    L2,
+    InnerProduct,
+}
+
+impl DistanceMetric {
+    pub fn parse(metric: &str) -> Result<Self> {
+        match metric {
+            "cosine" => Ok(Self::Cosine),
+            "l2" => Ok(Self::L2),
+            "inner_product" => Ok(Self::InnerProduct),
+            _ => anyhow::bail!("Unsupported distance metric: {metric}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cosine => "cosine",
+            Self::L2 => "l2",
+            Self::InnerProduct => "inner_product",
+        }
+    }
+
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Self::Cosine => "<=>",
+            Self::L2 => "<->",
+            Self::InnerProduct => "<#>",
+        }
+    }
+
+    /// pgvector operator class an index has to be built with to serve this metric.
+    pub fn operator_class(&self) -> &'static str {
+        match self {
+            Self::Cosine => "vector_cosine_ops",
+            Self::L2 => "vector_l2_ops",
+            Self::InnerProduct => "vector_ip_ops",
+        }
+    }
+
+    /// SQL expression of the raw distance to `$1`, ordering by it ascending lets
+    /// Postgres use the vector index.
+    pub fn distance_sql(&self) -> String {
+        format!("(embedding {} $1::vector)", self.operator())
+    }
+
+    /// SQL expression scoring `embedding` against `$1`, higher is more similar.
+    pub fn score_sql(&self) -> String {
+        match self {
+            Self::Cosine => "(1.0-(embedding <=> $1::vector))".to_string(),
+            Self::L2 => "(1.0/(1.0+(embedding <-> $1::vector)))".to_string(),
+            Self::InnerProduct => "((embedding <#> $1::vector)*-1.0)".to_string(),
+        }
+    }
+}
+
+/// Approximate nearest neighbour index on the `embedding` column, parameters
+/// follow pgvector's `CREATE INDEX ... WITH (...)` options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VectorIndex {
+    Hnsw {
+        #[serde(default = "default_hnsw_m")]
+        m: u32,
+        #[serde(default = "default_hnsw_ef_construction")]
+        ef_construction: u32,
+    },
+    IvfFlat {
+        #[serde(default = "default_ivfflat_lists")]
+        lists: u32,
+    },
+}
+
+fn default_hnsw_m() -> u32 {
+    HNSW_DEFAULT_M
+}
+
+fn default_hnsw_ef_construction() -> u32 {
+    HNSW_DEFAULT_EF_CONSTRUCTION
+}
+
+fn default_ivfflat_lists() -> u32 {
+    IVFFLAT_DEFAULT_LISTS
+}
+
+impl VectorIndex {
+    pub fn validate(&self, dimension: usize) -> Result<()> {
+        if dimension > MAX_INDEXED_DIMENSION {
+            anyhow::bail!(
+                "Vector indexes support up to {MAX_INDEXED_DIMENSION} dimensions, knowledge base uses {dimension}"
+            );
+        }
+
+        match *self {
+            Self::Hnsw { m, ef_construction } => {
+                if !(2..=100).contains(&m) {
+                    anyhow::bail!("HNSW m must be between 2 and 100");
+                }
+
+                if !(4..=1000).contains(&ef_construction) || ef_construction < 2 * m {
+                    anyhow::bail!(
+                        "HNSW ef_construction must be between 4 and 1000 and at least 2 * m"
+                    );
+                }
+            }
+            Self::IvfFlat { lists } => {
+                if !(1..=32768).contains(&lists) {
+                    anyhow::bail!("IVFFlat lists must be between 1 and 32768");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::Hnsw { .. } => "hnsw",
+            Self::IvfFlat { .. } => "ivfflat",
+        }
+    }
+
+    /// Storage parameters for the `WITH (...)` clause.
+    pub fn options_sql(&self) -> String {
+        match self {
+            Self::Hnsw { m, ef_construction } => {
+                format!("m = {m}, ef_construction = {ef_construction}")
+            }
+            Self::IvfFlat { lists } => format!("lists = {lists}"),
+        }
+    }
+}
+
+/// Per query index tuning, applied with `SET LOCAL` for the search transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchTuning {
+    /// `hnsw.ef_search`, candidates kept while walking an HNSW index.
+    pub ef_search: Option<u32>,
+    /// `ivfflat.probes`, lists scanned in an IVFFlat index.
+    pub probes: Option<u32>,
+}
+
+impl SearchTuning {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ef_search) = self.ef_search {
+            if !(1..=1000).contains(&ef_search) {
+                anyhow::bail!("ef_search must be between 1 and 1000");
+            }
+        }
+
+        if let Some(probes) = self.probes {
+            if !(1..=32768).contains(&probes) {
+                anyhow::bail!("probes must be between 1 and 32768");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `(setting, value)` pairs to apply, empty when nothing is tuned.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let mut settings = Vec::new();
+
+        if let Some(ef_search) = self.ef_search {
+            settings.push(("hnsw.ef_search", ef_search.to_string()));
+        }
+
+        if let Some(probes) = self.probes {
+            settings.push(("ivfflat.probes", probes.to_string()));
+        }
+
+        settings
+    }
+}
+
+/// How a query is matched against the chunks of a knowledge base.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Embedding similarity only.
+    #[default]
+    Vector,
+    /// Postgres full-text search on `content_tsv` only.
+    FullText,
+    /// Vector and full-text rankings fused with reciprocal rank fusion.
+    Hybrid,
+}
+
+impl SearchMode {
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode {
+            "vector" => Ok(Self::Vector),
+            "full_text" => Ok(Self::FullText),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => anyhow::bail!("Unsupported search mode: {mode}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Vector => "vector",
+            Self::FullText => "full_text",
+            Self::Hybrid => "hybrid",
+        }
+    }
+
+    /// Whether the query has to be embedded.
+    pub fn uses_embedding(&self) -> bool {
+        !matches!(self, Self::FullText)
+    }
+}
+
+/// Settings a knowledge base was created with, stored in `search_tables`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KnowledgeBaseSettings {
+    pub embedding_model: String,
+    pub dimension: usize,
+    pub distance_metric: DistanceMetric,
+    pub chunk_size: usize,
+    pub chunk_overlap: usize,
+}
+
+impl KnowledgeBaseSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.dimension == 0 {
+            anyhow::bail!("Embedding dimension must be greater than 0");
+        }
+
+        if self.chunk_size < MIN_CHUNK_SIZE || self.chunk_size > MAX_CHUNK_SIZE {
+            anyhow::bail!("Chunk size must be between {MIN_CHUNK_SIZE} and {MAX_CHUNK_SIZE}");
+        }
+
+        if self.chunk_overlap >= self.chunk_size {
+            anyhow::bail!("Chunk overlap must be smaller than chunk size");
+        }
+
+        Ok(())
+    }
+
+    /// Reject embeddings produced by a different model or with the wrong dimension.
+    pub fn check_embedding(&self, model_id: &str, embedding: &[f32]) -> Result<()> {
+        if model_id != self.embedding_model {
+            anyhow::bail!(
+                "Embedding model mismatch, knowledge base uses {} but got {model_id}",
+                self.embedding_model
+            );
+        }
+
+        if embedding.len() != self.dimension {
+            anyhow::bail!(
+                "Embedding dimension mismatch, knowledge base uses {} but got {}",
+                self.dimension,
+                embedding.len()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use crate::utils::constants::PHI2_CONTEXT_WINDOW;
+
+use serde::{Deserialize, Serialize};
+
+/// candle-transformers implementation a GGUF file is loaded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalArchitecture {
+    Phi2,
+    Phi3,
+    Llama,
+    Mistral,
+    Qwen2,
+}
+
+impl LocalArchitecture {
+    /// Template the instruct releases of the architecture were trained with.
+    pub fn chat_template(&self) -> ChatTemplate {
+        match self {
+            Self::Phi2 | Self::Qwen2 => ChatTemplate::ChatMl,
+            Self::Phi3 => ChatTemplate::Phi3,
+            Self::Llama => ChatTemplate::Llama3,
+            Self::Mistral => ChatTemplate::MistralInstruct,
+        }
+    }
+
+    pub fn context_window(&self) -> usize {
+        match self {
+            Self::Phi2 => PHI2_CONTEXT_WINDOW,
+            Self::Phi3 => 4096,
+            Self::Llama | Self::Mistral | Self::Qwen2 => 8192,
+        }
+    }
+}
+
+/// Built-in prompt format a local model expects its conversation in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTemplate {
+    #[serde(rename = "chatml")]
+    ChatMl,
+    Llama3,
+    Phi3,
+    MistralInstruct,
+    Gemma,
+}
+
+impl ChatTemplate {
+    /// Tokens that end the assistant's turn, those missing from the vocabulary are ignored.
+    pub fn eos_tokens(&self) -> &'static [&'static str] {
+        match self {
+            Self::ChatMl => &["<|im_end|>", "<|endoftext|>"],
+            Self::Llama3 => &["<|eot_id|>", "<|end_of_text|>"],
+            Self::Phi3 => &["<|end|>", "<|endoftext|>"],
+            Self::MistralInstruct => &["</s>"],
+            Self::Gemma => &["<end_of_turn>", "<eos>"],
+        }
+    }
+}
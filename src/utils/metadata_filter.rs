@@ -0,0 +1,152 @@
+use crate::utils::constants::MAX_FILTER_CONDITIONS;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Restricts a search to chunks whose `metadata` matches every set condition.
+///
+/// Keys and values are always bound as query parameters, only the shape of
+/// the clause comes from here.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataFilter {
+    /// Top level field equals the value, e.g. `{"source": "report.pdf"}`.
+    #[serde(default)]
+    pub equals: BTreeMap<String, String>,
+    /// Top level field equals one of the values.
+    #[serde(default, rename = "in")]
+    pub any_of: BTreeMap<String, Vec<String>>,
+    /// `source` starts with this prefix.
+    pub source_prefix: Option<String>,
+    /// `upload_time` is at or after this instant.
+    pub uploaded_after: Option<DateTime<Utc>>,
+    /// `upload_time` is before this instant.
+    pub uploaded_before: Option<DateTime<Utc>>,
+}
+
+/// Value bound for a compiled filter placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterParam {
+    Text(String),
+    TextArray(Vec<String>),
+    Timestamp(NaiveDateTime),
+}
+
+/// Compiled filter, `clause` refers to `params` starting at the placeholder
+/// number it was compiled with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterSql {
+    pub clause: String,
+    pub params: Vec<FilterParam>,
+}
+
+impl FilterSql {
+    /// ` AND (...)` to append to an existing `WHERE`, empty without conditions.
+    pub fn and_clause(&self) -> String {
+        if self.clause.is_empty() {
+            String::new()
+        } else {
+            format!(" AND ({})", self.clause)
+        }
+    }
+}
+
+/// `upload_time` is stored as text, its first 19 characters are the UTC
+/// `YYYY-MM-DD HH:MM:SS` timestamp. Rows without one, or with text in another
+/// format, don't match instead of failing the cast.
+const UPLOAD_TIME_SQL: &str = "(CASE WHEN metadata::jsonb ->> 'upload_time' ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}[ T][0-9]{2}:[0-9]{2}:[0-9]{2}' THEN left(metadata::jsonb ->> 'upload_time', 19)::timestamp END)";
+
+impl MetadataFilter {
+    pub fn is_empty(&self) -> bool {
+        self.equals.is_empty()
+            && self.any_of.is_empty()
+            && self.source_prefix.is_none()
+            && self.uploaded_after.is_none()
+            && self.uploaded_before.is_none()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let conditions = self.equals.len()
+            + self.any_of.values().map(|values| values.len()).sum::<usize>();
+
+        if conditions > MAX_FILTER_CONDITIONS {
+            anyhow::bail!("Metadata filter can't have more than {MAX_FILTER_CONDITIONS} values");
+        }
+
+        for key in self.equals.keys().chain(self.any_of.keys()) {
+            if key.trim().is_empty() {
+                anyhow::bail!("Metadata filter keys can't be empty");
+            }
+        }
+
+        if let Some((key, _)) = self.any_of.iter().find(|(_, values)| values.is_empty()) {
+            anyhow::bail!("Metadata filter `in` for {key} needs at least one value");
+        }
+
+        if let (Some(after), Some(before)) = (self.uploaded_after, self.uploaded_before) {
+            if after >= before {
+                anyhow::bail!("uploaded_after must be before uploaded_before");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compile to a parameterised condition whose first placeholder is `$first_param`.
+    pub fn to_sql(&self, first_param: usize) -> Result<FilterSql> {
+        self.validate()?;
+
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<FilterParam> = Vec::new();
+
+        let mut next_param = |param: FilterParam| {
+            params.push(param);
+            first_param + params.len() - 1
+        };
+
+        for (key, value) in &self.equals {
+            let key_param = next_param(FilterParam::Text(key.to_owned()));
+            let value_param = next_param(FilterParam::Text(value.to_owned()));
+
+            conditions.push(format!(
+                "(metadata::jsonb ->> ${key_param}) = ${value_param}"
+            ));
+        }
+
+        for (key, values) in &self.any_of {
+            let key_param = next_param(FilterParam::Text(key.to_owned()));
+            let values_param = next_param(FilterParam::TextArray(values.to_owned()));
+
+            conditions.push(format!(
+                "(metadata::jsonb ->> ${key_param}) = ANY(${values_param}::text[])"
+            ));
+        }
+
+        if let Some(source_prefix) = &self.source_prefix {
+            let prefix_param = next_param(FilterParam::Text(source_prefix.to_owned()));
+
+            conditions.push(format!(
+                "starts_with(metadata::jsonb ->> 'source', ${prefix_param})"
+            ));
+        }
+
+        if let Some(uploaded_after) = self.uploaded_after {
+            let after_param = next_param(FilterParam::Timestamp(uploaded_after.naive_utc()));
+
+            conditions.push(format!("{UPLOAD_TIME_SQL} >= ${after_param}"));
+        }
+
+        if let Some(uploaded_before) = self.uploaded_before {
+            let before_param = next_param(FilterParam::Timestamp(uploaded_before.naive_utc()));
+
+            conditions.push(format!("{UPLOAD_TIME_SQL} < ${before_param}"));
+        }
+
+        Ok(FilterSql {
+            clause: conditions.join(" AND "),
+            params,
+        })
+    }
+}
@@ -1,5 +1,12 @@
+pub mod archive;
 pub mod config;
 pub mod constants;
+pub mod extract;
+pub mod extractors;
+pub mod knowledge_base;
+pub mod local_models;
+pub mod metadata_filter;
+pub mod records;
 pub mod text_splitter;
 pub mod upload;
 pub mod utils;
@@ -0,0 +1,204 @@
+use crate::utils::extract::{self, ExtractedDocument};
+
+use anyhow::{anyhow, bail, Context, Result};
+use minijinja::Environment;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// Fields of a CSV row or JSON object, in the order they were read.
+pub type Record = Vec<(String, Value)>;
+
+/// How records of structured files (CSV, JSON, JSONL) become documents. Fields
+/// that aren't part of the text are stored in the chunk metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordTemplate {
+    /// Fields written to the text as `field: value` lines.
+    pub text_fields: Vec<String>,
+    /// Minijinja template rendered with the record's fields, the fields it uses
+    /// are part of the text.
+    pub template: Option<String>,
+}
+
+impl RecordTemplate {
+    /// From the `text_fields` (comma separated) and `text_template` upload fields.
+    pub fn parse(text_fields: Option<&str>, template: Option<&str>) -> Result<Self> {
+        let text_fields = text_fields
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        let template = template
+            .map(str::trim)
+            .filter(|template| !template.is_empty())
+            .map(str::to_owned);
+
+        if let Some(template) = &template {
+            Environment::new()
+                .template_from_str(template)
+                .context("Invalid text_template")?;
+        }
+
+        Ok(Self {
+            text_fields,
+            template,
+        })
+    }
+
+    /// Fields that make up the text, `None` when every field does.
+    fn fields_in_text(&self, env: &Environment) -> Result<Option<HashSet<String>>> {
+        let mut fields: HashSet<String> = self.text_fields.iter().cloned().collect();
+
+        if let Some(template) = &self.template {
+            fields.extend(env.template_from_str(template)?.undeclared_variables(false));
+        }
+
+        Ok((!fields.is_empty()).then_some(fields))
+    }
+
+    fn render(&self, env: &Environment, record: &Record) -> Result<String> {
+        if let Some(template) = &self.template {
+            let context: Map<String, Value> = record.iter().cloned().collect();
+
+            return Ok(env.template_from_str(template)?.render(context)?);
+        }
+
+        let lines: Vec<String> = record
+            .iter()
+            .filter(|(field, _)| self.text_fields.is_empty() || self.text_fields.contains(field))
+            .filter_map(|(field, value)| Some(format!("{field}: {}", value_text(value)?)))
+            .collect();
+
+        Ok(lines.join("\n"))
+    }
+}
+
+fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) if text.trim().is_empty() => None,
+        Value::String(text) => Some(text.trim().to_owned()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Rows of a CSV file with a header, empty cells are left out.
+pub fn parse_csv(bytes: &[u8]) -> Result<Vec<Record>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(bytes);
+
+    let headers = reader.headers()?.clone();
+
+    reader
+        .records()
+        .enumerate()
+        .map(|(index, row)| {
+            let row = row.with_context(|| format!("Invalid CSV row {}", index + 1))?;
+
+            Ok(headers
+                .iter()
+                .zip(row.iter())
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(header, cell)| (header.to_owned(), json!(cell)))
+                .collect())
+        })
+        .collect()
+}
+
+fn object_record(value: Value, number: usize) -> Result<Record> {
+    match value {
+        Value::Object(object) => Ok(object.into_iter().collect()),
+        _ => Err(anyhow!("Record {number} isn't a JSON object")),
+    }
+}
+
+/// An array of objects, or a single object.
+pub fn parse_json(bytes: &[u8]) -> Result<Vec<Record>> {
+    match serde_json::from_slice(bytes)? {
+        Value::Array(values) => values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| object_record(value, index + 1))
+            .collect(),
+        value => Ok(vec![object_record(value, 1)?]),
+    }
+}
+
+/// One object per line, blank lines are skipped.
+pub fn parse_jsonl(bytes: &[u8]) -> Result<Vec<Record>> {
+    let text = std::str::from_utf8(bytes).context("JSONL isn't UTF-8 text")?;
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let value = serde_json::from_str(line)
+                .with_context(|| format!("Invalid JSON on line {}", index + 1))?;
+
+            object_record(value, index + 1)
+        })
+        .collect()
+}
+
+/// One document per record with text, numbered from 1 in the `record` metadata.
+/// The fields outside the text are added to the metadata, except for names the
+/// chunks already use like `source` or `format`.
+pub fn record_documents(
+    format: &str,
+    records: Vec<Record>,
+    template: &RecordTemplate,
+) -> Result<Vec<ExtractedDocument>> {
+    let env = Environment::new();
+    let fields_in_text = template.fields_in_text(&env)?;
+    let mut documents = Vec::new();
+
+    for (index, record) in records.into_iter().enumerate() {
+        let number = index + 1;
+        let text = template
+            .render(&env, &record)
+            .with_context(|| format!("Unable to render record {number}"))?;
+
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let mut document = extract::extract_text(format, &text);
+
+        for (field, value) in record {
+            let in_text = fields_in_text
+                .as_ref()
+                .is_none_or(|fields| fields.contains(&field));
+
+            if !in_text && !value.is_null() {
+                document.metadata.entry(field).or_insert(value);
+            }
+        }
+
+        document.metadata.insert("record".to_owned(), json!(number));
+        documents.push(document);
+    }
+
+    if documents.is_empty() {
+        bail!("No record has text, check text_fields and text_template");
+    }
+
+    Ok(documents)
+}
+
+/// All records as paragraphs of one document.
+pub fn records_document(
+    format: &str,
+    records: Vec<Record>,
+    template: &RecordTemplate,
+) -> Result<ExtractedDocument> {
+    let text = record_documents(format, records, template)?
+        .into_iter()
+        .map(|document| document.text)
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    Ok(extract::extract_text(format, &text))
+}
@@ -183,3 +183,21 @@ impl TextSplitter {
         Ok(chunks)
     }
 }
+
+/// Chunks starting every `chunk_size` characters, each running `overlap_size`
+/// characters into the next one, with the byte offset they start at.
+pub fn split_chunks(text: &str, chunk_size: usize, overlap_size: usize) -> Vec<(usize, String)> {
+    let step = chunk_size.max(1);
+    let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let byte_offset = |chars: usize| offsets.get(chars).copied().unwrap_or(text.len());
+
+    (0..offsets.len())
+        .step_by(step)
+        .map(|start| {
+            let end = byte_offset(start.saturating_add(step).saturating_add(overlap_size));
+            let start = byte_offset(start);
+
+            (start, text[start..end].to_owned())
+        })
+        .collect()
+}
@@ -1,84 +1,190 @@
-use crate::local::database::bulk_insert_split_chunks;
-use anyhow::{Context, Error, Result};
+use crate::local::database::DocumentWriter;
+use crate::utils::archive::{expand_archive, ArchiveEntry, ArchiveKind, ArchiveLimits};
+use crate::utils::constants::INSERT_BATCH_DOCUMENTS;
+use crate::utils::extract::ExtractedDocument;
+use crate::utils::extractors::{detect_extractor, DocumentExtractor};
+use crate::utils::knowledge_base::KnowledgeBaseName;
+use crate::utils::records::RecordTemplate;
+use anyhow::{anyhow, bail, Context, Result};
 use chrono::Utc;
-use serde_json::json;
+use serde_json::{json, Value};
 use std::path::PathBuf;
-use tokio::{
-    fs,
-    io::{AsyncBufReadExt, BufReader},
-};
-
-#[derive(Clone, Debug, PartialEq)]
-pub enum FileType {
-    PDF,
-    Text,
-}
-
-pub async fn learn_from_pdf(table_name: &str, file_path: &PathBuf) -> Result<()> {
-    let bytes = fs::read(file_path.clone()).await?;
-
-    let extracted_file = pdf_extract::extract_text_from_mem(&bytes)?;
-
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::mpsc;
+
+/// Extract `file_path` with the extractor detected for it and store its chunks,
+/// `template` is used by formats with records.
+pub async fn learn_from_file(
+    table_name: &KnowledgeBaseName,
+    file_path: &PathBuf,
+    extractor: Arc<dyn DocumentExtractor>,
+    template: RecordTemplate,
+) -> Result<usize> {
     let file_name = file_path
         .file_name()
         .context("Unable to get file name")?
         .to_str()
-        .context("Unable to convert file name to String")?;
+        .context("Unable to convert file name to String")?
+        .to_owned();
+
+    println!(
+        "Processing {} File Name: {file_name}",
+        extractor.name().to_uppercase()
+    );
+
+    let bytes = fs::read(file_path).await?;
 
-    println!("Processing PDF File Name: {file_name}");
+    // Parsing large documents is CPU bound.
+    let documents = tokio::task::spawn_blocking({
+        let file_name = file_name.clone();
+        move || extractor.extract_documents(&file_name, &bytes, &template)
+    })
+    .await??;
 
-    bulk_insert_split_chunks(
-        table_name,
-        file_name,
-        &extracted_file,
-        json!({
-            "source": file_name,
-            "upload_time": Utc::now().to_string()
-        }),
-    )
-    .await
-    .map_err(Error::msg)?;
+    let mut writer = DocumentWriter::begin(table_name).await?;
+    let chunk_count = learn_from_documents(&mut writer, &file_name, documents).await?;
+    writer.commit().await?;
 
-    println!("Uploaded PDF File Name: {file_name}");
+    println!("Uploaded File Name: {file_name}");
 
-    Ok(())
+    Ok(chunk_count)
 }
 
-pub async fn learn_from_text(table_name: &str, file_path: &PathBuf) -> Result<()> {
+/// Learn every file of the archive at `file_path` with the extractor detected for
+/// it, recording its `archive` and `archive_path`. Unsupported files are skipped,
+/// files and nested archives that fail to extract fail the job. The archive is
+/// checked against `limits` first, then its files are expanded and learned one
+/// at a time in a single transaction, nothing is stored when any of them fails.
+pub async fn learn_from_archive(
+    table_name: &KnowledgeBaseName,
+    file_path: &PathBuf,
+    kind: ArchiveKind,
+    limits: ArchiveLimits,
+    template: RecordTemplate,
+) -> Result<usize> {
     let file_name = file_path
         .file_name()
         .context("Unable to get file name")?
         .to_str()
-        .context("Unable to convert file name to String")?;
+        .context("Unable to convert file name to String")?
+        .to_owned();
+
+    println!(
+        "Processing {} Archive: {file_name}",
+        kind.name().to_uppercase()
+    );
+
+    let bytes = fs::read(file_path).await?;
+    let (sender, mut entries) = mpsc::channel(1);
+
+    let expanding = tokio::task::spawn_blocking(move || {
+        expand_archive(kind, &bytes, limits, |_| Ok(()))?;
+        expand_archive(kind, &bytes, limits, |entry| {
+            sender
+                .blocking_send(entry)
+                .map_err(|_| anyhow!("Archive ingestion stopped"))
+        })
+    });
+
+    let mut writer = DocumentWriter::begin(table_name).await?;
+    let mut chunk_count = 0;
+    let mut learned = 0;
+    let mut failures: Vec<String> = Vec::new();
+
+    while let Some(entry) = entries.recv().await {
+        let member = match entry {
+            ArchiveEntry::File(member) => member,
+            ArchiveEntry::Unreadable { path, error } => {
+                println!("Unable to expand {file_name}/{path}: {error:#}");
+                failures.push(format!("{path}: {error:#}"));
+                continue;
+            }
+        };
+
+        let source = format!("{file_name}/{}", member.path);
+
+        let Some(extractor) = detect_extractor(&member.path, None, &member.bytes) else {
+            println!("File type not supported: {source}");
+            continue;
+        };
+
+        let documents = tokio::task::spawn_blocking({
+            let path = member.path.clone();
+            let template = template.clone();
+            move || extractor.extract_documents(&path, &member.bytes, &template)
+        })
+        .await?;
+
+        let mut documents = match documents {
+            Ok(documents) => documents,
+            Err(e) => {
+                println!("Unable to extract {source}: {e:#}");
+                failures.push(format!("{}: {e:#}", member.path));
+                continue;
+            }
+        };
+
+        for document in &mut documents {
+            document
+                .metadata
+                .insert("archive".to_owned(), json!(file_name));
+            document
+                .metadata
+                .insert("archive_path".to_owned(), json!(member.path));
+        }
+
+        chunk_count += learn_from_documents(&mut writer, &source, documents).await?;
+        learned += 1;
+    }
 
-    println!("Processing PDF File Name: {file_name}");
+    expanding.await??;
 
-    let file = fs::File::open(&file_path).await?;
+    if !failures.is_empty() {
+        bail!(
+            "Nothing learned from {file_name}, {} files failed: {}",
+            failures.len(),
+            failures.join("; ")
+        );
+    }
 
-    let reader = BufReader::new(file);
+    if learned == 0 {
+        bail!("No supported files in {file_name}");
+    }
 
-    let mut reader_lines = reader.lines();
+    writer.commit().await?;
 
-    let mut contents: String = String::new();
+    println!("Uploaded Archive: {file_name}, {learned} files");
+
+    Ok(chunk_count)
+}
 
-    while let Some(line) = reader_lines.next_line().await? {
-        contents += &line;
-        contents.push('\n');
+/// Write `documents` with their metadata added to the usual `source` and
+/// `upload_time`, `INSERT_BATCH_DOCUMENTS` are embedded at a time. They're
+/// stored once `writer` is committed.
+pub async fn learn_from_documents(
+    writer: &mut DocumentWriter,
+    file_name: &str,
+    documents: Vec<ExtractedDocument>,
+) -> Result<usize> {
+    let upload_time = Utc::now().to_string();
+    let mut chunk_count = 0;
+
+    for batch in documents.chunks(INSERT_BATCH_DOCUMENTS) {
+        let batch: Vec<(&str, &ExtractedDocument, Value)> = batch
+            .iter()
+            .map(|document| {
+                let mut metadata = document.metadata.clone();
+
+                metadata.insert("source".to_owned(), json!(file_name));
+                metadata.insert("upload_time".to_owned(), json!(upload_time));
+
+                (file_name, document, Value::Object(metadata))
+            })
+            .collect();
+
+        chunk_count += writer.insert(&batch).await?;
     }
 
-    bulk_insert_split_chunks(
-        table_name,
-        file_name,
-        &contents,
-        json!({
-            "source": file_name,
-            "upload_time": Utc::now().to_string()
-        }),
-    )
-    .await
-    .map_err(Error::msg)?;
-
-    println!("Uploaded PDF File Name: {file_name}");
-
-    Ok(())
+    Ok(chunk_count)
 }
@@ -3,16 +3,26 @@ use std::path::PathBuf;
 
 const INFERENCING_MODEL_PATH: &'static str = "PHI2_QUANTIZED_PATH";
 const EMBEDDING_MODEL_PATH: &'static str = "EMBEDDING_MODEL_PATH";
+const RERANKER_MODEL_PATH: &'static str = "RERANKER_MODEL_PATH";
 const GENAI_DB_URL: &'static str = "GENAI_DB_URL";
 const AZURE_AI_KEY: &'static str = "AZURE_OPENAI_KEY";
 const ST_EMBEDDING_MODEL_PATH: &'static str = "ST_EMBEDDING_MODEL_PATH";
 const ST_INFERENCING_MODEL_PATH: &'static str = "SAFETENSOR_MODEL_DIR";
+pub const APP_CONFIG_PATH: &'static str = "APP_CONFIG_PATH";
 
-pub fn get_inferencing_model_path() -> PathBuf {
-    if let Ok(path) = env::var(&INFERENCING_MODEL_PATH) {
-        return PathBuf::from(path);
+pub const APP_IP_ADDRESS: &'static str = "APP_IP_ADDRESS";
+pub const APP_PORT: &'static str = "APP_PORT";
+pub const APP_MAXIMUM_UPLOAD_SIZE: &'static str = "APP_MAXIMUM_UPLOAD_SIZE";
+pub const APP_MAX_POOL_CONNECTION: &'static str = "APP_MAX_POOL_CONNECTION";
+pub const APP_EMBEDDING_MODEL: &'static str = "APP_EMBEDDING_MODEL";
+pub const APP_INFERENCING_MODEL: &'static str = "APP_INFERENCING_MODEL";
+
+/// Directory of the built-in `phi2` local model.
+pub fn get_inferencing_model_path() -> anyhow::Result<PathBuf> {
+    match env::var(INFERENCING_MODEL_PATH) {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => anyhow::bail!("{INFERENCING_MODEL_PATH} not set in environment variables"),
     }
-    panic!("{INFERENCING_MODEL_PATH} not set in environment variables");
 }
 
 pub fn get_azureai_api_key() -> String {
@@ -37,6 +47,14 @@ pub fn get_embedding_model_path() -> PathBuf {
     panic!("{EMBEDDING_MODEL_PATH} not set in environment variables")
 }
 
+/// Optional, reranking requests fail with this error when it's not set.
+pub fn get_reranker_model_path() -> anyhow::Result<PathBuf> {
+    match env::var(RERANKER_MODEL_PATH) {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => anyhow::bail!("{RERANKER_MODEL_PATH} not set in environment variables"),
+    }
+}
+
 pub fn safetensor_embedding_model_path() -> PathBuf {
     match env::var(ST_EMBEDDING_MODEL_PATH) {
         Ok(res) => return path_exists(&res),
@@ -69,10 +87,23 @@ pub fn path_exists(dir: &String) -> PathBuf {
 
 pub fn get_app_config_path() -> PathBuf {
     let path = PathBuf::from(
-        env::var("APP_CONFIG_PATH").expect("APP_CONFIG_PATH not set in environment variables"),
+        env::var(APP_CONFIG_PATH).expect("APP_CONFIG_PATH not set in environment variables"),
     );
     if path.exists() {
         return path;
     }
     panic!("Dir: {:?} doesn't exists", path);
 }
+
+/// Config path if `APP_CONFIG_PATH` is set, whether or not the file exists.
+pub fn try_get_app_config_path() -> Option<PathBuf> {
+    get_env_override(APP_CONFIG_PATH).map(PathBuf::from)
+}
+
+/// Value of an override environment variable, ignoring empty values.
+pub fn get_env_override(var: &str) -> Option<String> {
+    match env::var(var) {
+        Ok(value) if !value.trim().is_empty() => Some(value.trim().to_string()),
+        _ => None,
+    }
+}